@@ -1,5 +1,5 @@
 use vello::peniko;
-use winit::dpi::{LogicalSize, PhysicalPosition};
+use winit::dpi::{LogicalSize, PhysicalPosition, PhysicalSize};
 use winit::platform::macos::WindowAttributesExtMacOS;
 use winit::window::WindowAttributes;
 use winit::application::ApplicationHandler;
@@ -8,16 +8,31 @@ use winit::event_loop::{ActiveEventLoop, EventLoopProxy};
 use winit::window::{Window, WindowId};
 use anyhow;
 
-use std::collections::HashMap;
-use std::sync::Arc;
+use std::collections::{HashMap, VecDeque};
+use std::io::Read;
+use std::path::PathBuf;
+use std::sync::{Arc, Mutex};
 
-use crate::buffer::{TextBuffer, SyncList};
+use crate::buffer::{TextBuffer, BufferId};
+use crate::registry::Registry;
+use crate::prompt::Prompt;
 use crate::renderer::redraw_requested_handler;
 use crate::renderer::FALLBACK_FONT_DATA;
 use crate::renderer::{FontRender, Style, TITLEBAR_HEIGHT, X_PADDING, Y_PADDING, CURSOR_WIDTH, CURSOR_HEIGHT, get_font_metrics};
 use crate::renderer::blink_cursor;
 use crate::buffer::CustomEvent;
-use crate::pane::{PaneId, Pane};
+use crate::pane::{PaneId, Pane, Mode};
+use crate::observer::{Observers, BufferEvent};
+use crate::journal;
+use crate::autosave;
+use crate::session::{self, Session};
+use crate::cli::{self, Input};
+use crate::wait::Wait;
+use crate::server;
+use crate::rpc::{self, Rpc};
+use crate::layout::{Layout, LayoutOp};
+use im::OrdMap;
+use crop::Rope;
 
 use std::ffi::CStr;
 use std::num::NonZeroUsize;
@@ -25,7 +40,7 @@ use std::num::NonZeroUsize;
 use winit::event::{MouseScrollDelta, ElementState, MouseButton, ButtonSource};
 use winit::window::CursorIcon;
 use winit::window::Cursor;
-use winit::keyboard::ModifiersKeyState;
+use winit::keyboard::{ModifiersKeyState, Key, NamedKey};
 use vello::RendererOptions;
 use vello::kurbo::Rect;
 use vello::util::RenderSurface;
@@ -39,9 +54,8 @@ use objc2_foundation::{NSArray, NSString, NSURL, MainThreadMarker, NSObject, NSO
 use std::thread;
 use std::sync::mpsc;
 
-use crate::buffer::BufferOp;
-use crate::buffer::buffer_op_handler;
-use crate::buffer::BufferId;
+use crate::buffer::{BufferMsg, BufferOp, Op, WindowOp};
+use crate::buffer::{buffer_op_handler, Context, Ui};
 use crate::renderer::{GlyphPosCache, LineCache};
 
 const FONT_DATA: &[u8] = include_bytes!("/Users/jason/Library/Fonts/Hack-Regular.ttf");
@@ -51,7 +65,6 @@ pub struct Args {
     pub bg_color: peniko::Color, 
     pub fg_color: peniko::Color,
     pub font_data: &'static [u8],
-    pub filename: Option<String>,
 }
 
 pub struct WindowState<'a> {
@@ -61,23 +74,25 @@ pub struct WindowState<'a> {
     pub scene: Scene,
     pub renderer: Renderer,
     pub render_cx: RenderContext,
-    pub glyph_pos_caches: HashMap<BufferId, GlyphPosCache>,
-    pub line_caches: HashMap<BufferId, LineCache>,
+    pub glyph_pos_caches: HashMap<PaneId, GlyphPosCache>,
+    pub line_caches: HashMap<PaneId, LineCache>,
     pub layout: Layout,
+    // last known pointer position, so scrolling goes to the pane under it
+    pub pointer: PhysicalPosition<f64>,
+    // shown in the status line until the next key
+    pub message: Option<String>,
+    // takes the keyboard until it's answered
+    pub prompt: Option<Prompt>,
 
     pub should_draw_cursor: bool,
 }
 
-pub struct Layout {
-    pane_id: PaneId, // this will need to change
-}
-
 impl<'a> WindowState<'a> {
-    fn new(surface: RenderSurface<'a>, window: Arc<dyn Window>, font_render: FontRender, scene: Scene, renderer: Renderer, render_cx: RenderContext) -> Self {
+    fn new(surface: RenderSurface<'a>, window: Arc<dyn Window>, mut font_render: FontRender, scene: Scene, renderer: Renderer, render_cx: RenderContext, layout: Layout) -> Self {
         let should_draw_cursor = true;
         let glyph_pos_caches = HashMap::new();
         let line_caches = HashMap::new();
-        let layout = Layout { pane_id: 0};
+        font_render.style.set_layout(&layout);
 
         WindowState {
             surface,
@@ -89,6 +104,9 @@ impl<'a> WindowState<'a> {
             glyph_pos_caches,
             line_caches,
             layout,
+            pointer: PhysicalPosition::new(0., 0.),
+            message: None,
+            prompt: None,
 
             should_draw_cursor,
         }
@@ -98,13 +116,35 @@ impl<'a> WindowState<'a> {
 pub struct App<'a> {
     args: Args,
     windows: HashMap<WindowId, WindowState<'a>>,
-    buffers: Arc<SyncList<TextBuffer>>,
-    active_buffer: HashMap<WindowId, BufferId>,
+    buffers: Arc<Registry<TextBuffer>>,
     mods: Modifiers,
-    buffer_tx: mpsc::Sender<(BufferOp, Vec<PaneId>)>,
+    buffer_tx: mpsc::Sender<BufferMsg>,
     render_rx: mpsc::Receiver<CustomEvent>,
     cursor_blink_last_key: mpsc::Sender<()>,
-    panes: Arc<SyncList<Pane>>,
+    panes: Arc<Registry<Pane>>,
+    observers: Arc<Observers>,
+    // the thread writing the journals, it's let finish before we exit
+    journal: Option<thread::JoinHandle<()>>,
+    autosave: Arc<Mutex<autosave::Policy>>,
+    // a message from before there was a window to show it in (the file couldn't
+    // be read, ...)
+    early_message: Option<String>,
+    // questions from the buffer thread waiting their turn, there's one being
+    // asked already (or no window to ask in yet). They're asked in order
+    prompts: VecDeque<Prompt>,
+    // the windows to open once we can, from the session or just the one
+    start_windows: Vec<session::StartWindow>,
+    // where the session's saved when we exit. None when chop was given files to
+    // open, those aren't what was being worked on
+    session: Option<PathBuf>,
+    // the files chop's been asked to `--wait` on
+    wait: Wait,
+    // what we're listening on (see `server` and `rpc`), removed when we exit
+    sockets: Vec<PathBuf>,
+    // the ones waiting on files they handed us, until they've been replied to
+    clients: Vec<(Wait, server::Client)>,
+    // for the app delegate, to send us files from Finder
+    ui: Ui,
 }
 
 declare_class!(
@@ -116,85 +156,317 @@ declare_class!(
         const NAME: &'static str = "MyAppDelegate";
     }
 
-    impl DeclaredClass for AppDelegate {}
+    // to hand the files it's asked to open to the UI thread
+    impl DeclaredClass for AppDelegate {
+        type Ivars = Ui;
+    }
 
     unsafe impl NSObjectProtocol for AppDelegate {}
 
     unsafe impl NSApplicationDelegate for AppDelegate {
         #[method(application:openURLs:)]
         #[allow(non_snake_case)]
-        fn application_openURLs(&self, application: &NSApplication, urls: &NSArray<NSURL>) {
-            let urls = extract_urls_from_array(urls);
-            log::warn!("open urls: {application:?}, {urls:?}");
+        fn application_openURLs(&self, _application: &NSApplication, urls: &NSArray<NSURL>) {
+            let mut files = vec![];
+            for url in extract_urls_from_array(urls) {
+                match server::path_of_url(&url) {
+                    Some(path) => files.push(Input::File { path, line: None, col: None }),
+                    None => log::warn!("can't open {url}, it isn't a file"),
+                }
+            }
+            // the same way as `chop file`, but it's us they're meant for (even with
+            // `-n`), so they don't go through the socket
+            let request = server::Request {files, read_only: false, wait: false};
+            self.ivars().send(CustomEvent::Open(request, server::Client::nobody()));
         }
     }
 );
 
 impl AppDelegate {
-    pub fn new(mtm: MainThreadMarker) -> Retained<Self> {
-        unsafe { msg_send_id![super(mtm.alloc().set_ivars(())), init] }
+    pub fn new(mtm: MainThreadMarker, ui: Ui) -> Retained<Self> {
+        unsafe { msg_send_id![super(mtm.alloc().set_ivars(ui)), init] }
     }
 }
 
 impl<'a> App<'a> {
-    pub fn new(filename: Option<String>, event_loop_proxy: EventLoopProxy) -> Self {
+    pub fn new(options: cli::Options, event_loop_proxy: EventLoopProxy) -> Self {
         let font_size = 28.0;
         let bg_color = peniko::Color::rgb8(0xFA, 0xFA, 0xFA);
         let fg_color = peniko::Color::rgb8(0x0, 0x0, 0x0);
-        let args = Args {font_size, bg_color, fg_color, font_data: FONT_DATA, filename};
+        let args = Args {font_size, bg_color, fg_color, font_data: FONT_DATA};
 
         let (buffer_tx, buffer_rx) = mpsc::channel();
 
         let (render_tx, render_rx) = mpsc::channel();
 
-        let buffers = Arc::new(SyncList::new());
-        let panes = Arc::new(SyncList::new());
+        // Both threads write these (this one when files are opened), every write's
+        // a compare-and-swap so they never clobber each other (see `registry`)
+        let buffers = Arc::new(Registry::new());
+        let panes = Arc::new(Registry::new());
+        let observers = Arc::new(Observers::new());
+
+        let mut start_windows = vec![];
+        // for the buffer thread once it's up
+        let mut startup_ops = vec![];
+        let mut wait = Wait::default();
+        let session = session::file().filter(|_| options.files.is_empty());
+        if !options.files.is_empty() {
+            let opened = open_inputs(&buffers, &panes, &options.files, options.read_only).unwrap_or_else(|msg| {
+                log::error!("{msg}");
+                eprintln!("chop: {msg}");
+                std::process::exit(1);
+            });
+            start_windows.push((Layout::new(opened.pane_id), None));
+            startup_ops = opened.ops;
+            if options.wait {
+                wait.buffers = opened.files;
+            }
+        } else if let Some(session) = session.as_deref().and_then(Session::load) {
+            let (windows, views) = session.restore(&buffers, &panes);
+            start_windows = windows;
+            startup_ops.extend(views.into_iter().map(|(op, pane_id)| BufferMsg::Op(op, vec![pane_id])));
+        }
+        // no session (or nothing left of it)
+        if start_windows.is_empty() {
+            let buffer = TextBuffer::from_blank();
+            let buf_id = buffers.insert_with(|_| buffer);
+            let pane_id = panes.insert_with(|pane_id| Pane::new(buf_id, pane_id));
+            start_windows.push((Layout::new(pane_id), None));
+        }
+        for op in startup_ops {
+            buffer_tx.send(op).unwrap();
+        }
 
-        // INVARIANT: BUFFERS SHOULD ONLY EVER BE MODIFIED (`store`d) BY THIS THREAD
-        // If this is not upheld, then we have a race condition where the buffer changes
-        // between the load, computation, and store, and we miss something
-        let handler = buffer_op_handler(buffer_rx, buffers.clone(), panes.clone(), render_tx.clone(), event_loop_proxy.clone());
-        thread::spawn(handler);
+        // the crash recovery journals, see `journal`
+        let journal = journal::dir().map(|dir| journal::spawn(dir, observers.subscribe(), buffers.clone()));
 
-        let (cursor_blink_last_key, cursor_blink_rx) = mpsc::channel();
-        thread::spawn(|| blink_cursor(render_tx, event_loop_proxy, cursor_blink_rx));
+        if wait.is_waiting() {
+            let buffer_tx = buffer_tx.clone();
+            wait.spawn(observers.subscribe(), buffers.clone(), move |_, buffers| {
+                // with anything else unsaved they'll have to exit themselves
+                if !buffers.values().any(|b| b.has_unsaved_changes()) {
+                    let _ = buffer_tx.send(BufferMsg::Exit);
+                }
+            });
+        }
 
-        if let Some(file_path) = &args.filename {
-            if let Ok(buffer) = TextBuffer::from_filename(&file_path) {
-                let buf_id = buffers.len();
-                let pane_id = panes.len();
-                panes.store(pane_id, Pane::new(buf_id, pane_id));
-                buffers.store(buf_id, buffer);
-            } else {
-                log::error!("file doesn't exist {:?}", args.filename);
-                std::process::exit(1);
+        // unless there's one running already, we're it (see `server`), and the one
+        // that can be remote controlled (see `rpc`)
+        let ui = Ui::new(render_tx.clone(), event_loop_proxy.clone());
+        let mut sockets = vec![];
+        if !options.new_instance {
+            let path = server::socket_path();
+            match server::listen(&path, ui.clone()) {
+                Ok(()) => sockets.push(path),
+                Err(e) => log::error!("couldn't listen on {}: {e}", path.display()),
+            }
+        }
+        if !sockets.is_empty() {
+            let ui = ui.clone();
+            let run_ops = Box::new(move |pane_id, ops, ack| ui.send(CustomEvent::RunOps(pane_id, ops, Some(ack))));
+            let path = rpc::socket_path();
+            match Rpc::new(buffers.clone(), panes.clone(), buffer_tx.clone(), run_ops).listen(&path) {
+                Ok(()) => sockets.push(path),
+                Err(e) => log::error!("couldn't listen on {}: {e}", path.display()),
             }
-        } else {
-            let buffer = TextBuffer::from_blank();
-            let buf_id = buffers.len();
-            let pane_id = panes.len();
-            panes.store(pane_id, Pane::new(buf_id, pane_id));
-            buffers.store(buf_id, buffer);
         }
 
+        let autosave = Arc::new(Mutex::new(autosave::Policy::default()));
+        autosave::spawn(observers.subscribe(), autosave.clone(), buffer_tx.clone());
+
+        let cx = Context {buffers: buffers.clone(), panes: panes.clone(), observers: observers.clone(), buffer_tx: buffer_tx.clone(), ui: ui.clone()};
+        let handler = buffer_op_handler(cx, buffer_rx);
+        // after the first buffer is in, so the file watcher starts out watching it
+        thread::spawn(handler);
+
+        let (cursor_blink_last_key, cursor_blink_rx) = mpsc::channel();
+        thread::spawn(|| blink_cursor(render_tx, event_loop_proxy, cursor_blink_rx));
+
         let app = App {
             args, 
             windows: HashMap::new(),
             mods: Modifiers::default(),
             buffers,
-            active_buffer: HashMap::new(),
             buffer_tx,
             render_rx,
             cursor_blink_last_key,
             panes,
+            observers,
+            journal,
+            autosave,
+            early_message: None,
+            prompts: VecDeque::new(),
+            start_windows,
+            session,
+            wait,
+            sockets,
+            clients: vec![],
+            ui,
         };
         app
     }
 
-    fn create_window(&mut self, event_loop: &dyn ActiveEventLoop, tab_id: Option<String>, buf_id: BufferId) -> anyhow::Result<WindowId> {
-        let size = LogicalSize {width: 800, height: 600};
-        let mut window_attributes = WindowAttributes::default()
-            .with_surface_size(size)
+    // the ops from a key press (or a prompt answer) in the focused pane of the window,
+    // returns whether the window needs a redraw
+    fn run_ops(&mut self, window_id: WindowId, ops: Vec<Op>) -> bool {
+        let window_state = self.windows.get_mut(&window_id).unwrap();
+        let mut should_redraw = false;
+        for op in ops {
+            let pane_id = window_state.layout.focused;
+            match op {
+                Op::Window(WindowOp::Exit) => {
+                    return self.exit(window_id, &[]) || should_redraw;
+                },
+                Op::Window(WindowOp::Layout(LayoutOp::Close)) => {
+                    if let Some(prompt) = close_prompt(&self.buffers, &self.panes, pane_id) {
+                        window_state.prompt = Some(prompt);
+                        return true;
+                    }
+                    if !close_pane(window_state, &self.buffer_tx, false) {
+                        return self.close_window(window_id, false);
+                    }
+                    should_redraw = true;
+                },
+                Op::Window(WindowOp::Layout(LayoutOp::ForceClose)) => {
+                    if !close_pane(window_state, &self.buffer_tx, true) {
+                        return self.close_window(window_id, true);
+                    }
+                    should_redraw = true;
+                },
+                Op::Window(WindowOp::ClosePane { discard }) => {
+                    if !close_pane(window_state, &self.buffer_tx, discard) {
+                        return self.close_window(window_id, discard);
+                    }
+                    should_redraw = true;
+                },
+                Op::Window(WindowOp::Layout(op)) => {
+                    layout_op(window_state, &self.panes, op);
+                    should_redraw = true;
+                },
+                Op::Window(WindowOp::ListBuffers) => {
+                    window_state.message = Some(buffer_list(&self.buffers.get(), &self.panes.get()[&pane_id]));
+                    should_redraw = true;
+                },
+                Op::Window(WindowOp::Message(msg)) => {
+                    window_state.message = Some(msg);
+                    should_redraw = true;
+                },
+                Op::Buffer(BufferOp::Save) if self.buffers.get()[&self.panes.get()[&pane_id].buffer_id].file.is_none() => {
+                    // nowhere to save it yet, ask where
+                    edit_command(&self.panes, pane_id, "w ");
+                    should_redraw = true;
+                },
+                Op::Window(WindowOp::ForceExit) => {
+                    self.buffer_tx.send(BufferMsg::Exit).unwrap();
+                },
+                Op::Window(WindowOp::Abort) => {
+                    self.wait.abort();
+                    self.buffer_tx.send(BufferMsg::Exit).unwrap();
+                },
+                Op::Window(WindowOp::SetAutosave(setting)) => {
+                    self.autosave.lock().unwrap().apply(setting);
+                },
+                Op::Window(WindowOp::EditCommand(cmdline)) => {
+                    edit_command(&self.panes, pane_id, &cmdline);
+                    should_redraw = true;
+                },
+                Op::Buffer(op) => {
+                    self.buffer_tx.send(BufferMsg::Op(op, vec![pane_id])).unwrap();
+                },
+            }
+        }
+        should_redraw
+    }
+
+    // quit, unless that loses changes to buffers other than `discarded`, then it
+    // asks first (in the window) and it's true
+    fn exit(&mut self, window_id: WindowId, discarded: &[BufferId]) -> bool {
+        let unsaved = self.buffers.get().iter().filter(|(id, b)| b.has_unsaved_changes() && !discarded.contains(id)).count();
+        if unsaved > 0 {
+            let question = format!("{unsaved} buffer(s) have unsaved changes, quit anyway? [y]es [n]o");
+            let window_state = self.windows.get_mut(&window_id).unwrap();
+            window_state.prompt = Some(Prompt::new(question, vec![('y', vec![WindowOp::ForceExit.into()]), ('n', vec![])]));
+            return true;
+        }
+        // the buffer thread says when to actually exit, after anything still queued
+        self.buffer_tx.send(BufferMsg::Exit).unwrap();
+        false
+    }
+
+    // its last pane was closed (or the window was), true if it's still there
+    // asking about unsaved changes. Only the last window exits
+    fn close_window(&mut self, window_id: WindowId, discard: bool) -> bool {
+        if self.windows.len() == 1 {
+            let panes = self.panes.get();
+            let discarded: Vec<_> = match discard {
+                true => self.windows[&window_id].layout.panes().into_iter().filter_map(|id| panes.get(&id)).map(|p| p.buffer_id).collect(),
+                false => vec![],
+            };
+            return self.exit(window_id, &discarded);
+        }
+        let window_state = self.windows.remove(&window_id).unwrap();
+        for pane_id in window_state.layout.panes() {
+            self.buffer_tx.send(BufferMsg::Op(BufferOp::ClosePane { discard }, vec![pane_id])).unwrap();
+        }
+        false
+    }
+
+    // in a new window (tabbed with the others), like at startup
+    fn open(&mut self, event_loop: &dyn ActiveEventLoop, request: server::Request, client: server::Client) {
+        let opened = match open_inputs(&self.buffers, &self.panes, &request.files, request.read_only) {
+            Ok(opened) => opened,
+            Err(msg) => {
+                client.reply(&server::Reply::Error(msg));
+                return;
+            },
+        };
+        for id in opened.new {
+            self.buffer_tx.send(BufferMsg::StartLoading(id)).unwrap();
+        }
+        for op in opened.ops {
+            self.buffer_tx.send(op).unwrap();
+        }
+        match self.create_window(event_loop, Some("chop".to_string()), Layout::new(opened.pane_id), None) {
+            Ok(window_id) => {
+                let window_state = &self.windows[&window_id];
+                window_state.window.focus_window();
+                window_state.window.request_redraw();
+            },
+            Err(e) => log::error!("couldn't open a window: {e}"),
+        }
+        let wait = Wait {buffers: opened.files, ..Wait::default()};
+        if !request.wait || !wait.is_waiting() {
+            client.reply(&server::Reply::Done(0));
+            return;
+        }
+        let done = client.clone();
+        self.clients.retain(|(_, client)| !client.replied());
+        wait.spawn(self.observers.subscribe(), self.buffers.clone(), move |wait, _| done.reply(&server::Reply::Done(wait.code())));
+        self.clients.push((wait, client));
+    }
+
+    pub fn ui(&self) -> Ui {
+        self.ui.clone()
+    }
+
+    // what chop should exit with, once the event loop's done
+    pub fn exit_code(&self) -> i32 {
+        self.wait.exit_code(&self.buffers)
+    }
+
+    // edits and saves of every buffer, sent from the buffer thread
+    pub fn subscribe(&self) -> mpsc::Receiver<Arc<BufferEvent>> {
+        self.observers.subscribe()
+    }
+
+    // `size` is the default size if None
+    fn create_window(&mut self, event_loop: &dyn ActiveEventLoop, tab_id: Option<String>, layout: Layout, size: Option<PhysicalSize<u32>>) -> anyhow::Result<WindowId> {
+        let mut window_attributes = WindowAttributes::default();
+        window_attributes = match size {
+            Some(size) => window_attributes.with_surface_size(size),
+            None => window_attributes.with_surface_size(LogicalSize {width: 800, height: 600}),
+        };
+        window_attributes = window_attributes
             .with_transparent(true)
             .with_fullsize_content_view(true)
             .with_title_hidden(true)
@@ -291,7 +563,8 @@ impl<'a> App<'a> {
             bg_color: self.args.bg_color,
             cursor_color: peniko::Color::rgb8(0x5e, 0x9c, 0xf5),
             selection_color: peniko::Color::rgba8(0x5e, 0x9c, 0xf5, 0x66),
-            vheight: size.height as f32 - TITLEBAR_HEIGHT - Y_PADDING,
+            // leave room for the status line
+            vheight: size.height as f32 - TITLEBAR_HEIGHT - Y_PADDING - line_height,
             vwidth: size.width as f32 - X_PADDING,
             voffset_x: X_PADDING,
             voffset_y: TITLEBAR_HEIGHT + Y_PADDING,
@@ -302,6 +575,7 @@ impl<'a> App<'a> {
             titlebar,
             color_scheme,
             rust_syntax_map,
+            viewports: HashMap::new(),
         };
 
         let font_render = FontRender {
@@ -312,8 +586,7 @@ impl<'a> App<'a> {
         // =============== /OLD
 
         let window_id = window.id();
-        let window_state = WindowState::new(surface, window, font_render, scene, renderer, render_cx);
-        self.active_buffer.insert(window_id, buf_id);
+        let window_state = WindowState::new(surface, window, font_render, scene, renderer, render_cx, layout);
         self.windows.insert(window_id, window_state);
 
         log::info!("window created");
@@ -324,47 +597,103 @@ impl<'a> App<'a> {
 
 impl<'a> ApplicationHandler for App<'a> {
     fn can_create_surfaces(&mut self, _event_loop: &dyn ActiveEventLoop) {
-        let buffer_id = 0;
-        let win_id = self.create_window(_event_loop, None, buffer_id).unwrap();
-
-        // redraw
-        let window_state = self.windows.get(&win_id).expect("create_window() didn't put the window into the hashmap, should be impossible");
-        window_state.window.request_redraw()
+        for (layout, size) in std::mem::take(&mut self.start_windows) {
+            let win_id = self.create_window(_event_loop, None, layout, size).unwrap();
+            let first = self.windows.len() == 1;
+
+            // redraw
+            let window_state = self.windows.get_mut(&win_id).expect("create_window() didn't put the window into the hashmap, should be impossible");
+            // the first window gets anything said before there were any
+            window_state.message = self.early_message.take();
+            if first {
+                window_state.prompt = self.prompts.pop_front();
+            }
+            window_state.window.request_redraw()
+        }
     }
 
-    fn proxy_wake_up(&mut self, _event_loop: &dyn ActiveEventLoop) {
+    fn proxy_wake_up(&mut self, event_loop: &dyn ActiveEventLoop) {
         while let Ok(event) = self.render_rx.try_recv() {
-            // get last focused window
-            let mut focused_window = None;
-            for win_state in self.windows.values() {
-                if win_state.window.has_focus() {
-                    focused_window = Some(win_state.window.id());
-                }
-            }
-
-            // both arms call for a redraw
-            if let Some(window_id) = focused_window {
-                let window_state = self.windows.get_mut(&window_id).unwrap();
-                let redraw = |window_state: &mut WindowState| {
-                    let buf_ind = *self.active_buffer.get(&window_id).unwrap();
-                    let buffer_ref = &self.buffers.get()[buf_ind];
-                    let pane = &self.panes.get()[window_state.layout.pane_id];
-                    assert!(buf_ind == pane.buffer_id);
-                    let (gpc, lc) = redraw_requested_handler(window_state, buffer_ref, pane);
-                    window_state.glyph_pos_caches.insert(buf_ind, gpc);
-                    window_state.line_caches.insert(buf_ind, lc);
-                };
-                match event {
-                    CustomEvent::BufferRequestedRedraw(buf_id) => {
-                        if buf_id == *self.active_buffer.get(&window_id).unwrap() {
-                            redraw(window_state);
+            let buffers = self.buffers.get();
+            let panes = self.panes.get();
+            match event {
+                CustomEvent::BufferRequestedRedraw(buf_id) => {
+                    // every window showing the buffer in some pane
+                    for window_state in self.windows.values_mut() {
+                        if window_state.layout.panes().iter().any(|id| panes[id].buffer_id == buf_id) {
+                            redraw_requested_handler(window_state, &buffers, &panes);
                         }
-                    },
-                    CustomEvent::CursorBlink(should_draw) => {
+                    }
+                },
+                CustomEvent::CursorBlink(should_draw) => {
+                    // get last focused window
+                    if let Some(window_state) = self.windows.values_mut().filter(|w| w.window.has_focus()).last() {
                         window_state.should_draw_cursor = should_draw;
-                        redraw(window_state);
-                    },
-                }
+                        redraw_requested_handler(window_state, &buffers, &panes);
+                    }
+                },
+                CustomEvent::Exit => {
+                    // the buffer thread's said what's closed, let the journals catch up
+                    self.observers.close();
+                    if let Some(journal) = self.journal.take() {
+                        let _ = journal.join();
+                    }
+                    if let Some(path) = &self.session {
+                        let windows = self.windows.values().map(|w| (w.window.surface_size(), &w.layout));
+                        if let Err(e) = Session::capture(windows, &buffers, &panes).save(path) {
+                            log::error!("couldn't save the session to {}: {e}", path.display());
+                        }
+                    }
+                    for (wait, client) in &self.clients {
+                        client.reply(&server::Reply::Done(wait.exit_code(&self.buffers)));
+                    }
+                    for path in &self.sockets {
+                        let _ = std::fs::remove_file(path);
+                    }
+                    event_loop.exit();
+                },
+                CustomEvent::Open(request, client) => {
+                    self.open(event_loop, request, client);
+                },
+                CustomEvent::RunOps(pane_id, ops, ack) => {
+                    // in its window, as if it was focused. The focus goes back unless
+                    // they moved it themselves (`:split`, `:close`)
+                    let Some((&window_id, window_state)) = self.windows.iter_mut().find(|(_, w)| w.layout.contains(pane_id)) else {
+                        continue;
+                    };
+                    let was = std::mem::replace(&mut window_state.layout.focused, pane_id);
+                    let redraw = self.run_ops(window_id, ops);
+                    // after whatever they sent it
+                    if let Some(ack) = ack {
+                        self.buffer_tx.send(BufferMsg::Ack(ack)).unwrap();
+                    }
+                    if let Some(window_state) = self.windows.get_mut(&window_id) {
+                        if window_state.layout.focused == pane_id && window_state.layout.contains(was) {
+                            window_state.layout.focused = was;
+                        }
+                        if redraw || was != pane_id {
+                            window_state.window.request_redraw();
+                        }
+                    }
+                },
+                CustomEvent::Prompt(prompt) => {
+                    match self.windows.values_mut().filter(|w| w.window.has_focus()).last() {
+                        Some(window_state) if window_state.prompt.is_none() => {
+                            window_state.prompt = Some(prompt);
+                            redraw_requested_handler(window_state, &buffers, &panes);
+                        },
+                        // the one being asked still needs its answer
+                        _ => self.prompts.push_back(prompt),
+                    }
+                },
+                CustomEvent::Message(msg) => {
+                    if let Some(window_state) = self.windows.values_mut().filter(|w| w.window.has_focus()).last() {
+                        window_state.message = Some(msg);
+                        redraw_requested_handler(window_state, &buffers, &panes);
+                    } else if self.windows.is_empty() {
+                        self.early_message = Some(msg);
+                    }
+                },
             }
         }
     }
@@ -374,36 +703,45 @@ impl<'a> ApplicationHandler for App<'a> {
         let _ = event_loop;
     }
 
-    fn window_event(&mut self, event_loop: &dyn ActiveEventLoop, window_id: WindowId, event: WindowEvent) {
-        let mut window_state = self.windows.get_mut(&window_id).expect("recieving window event but we lost window, should be impossible");
-
-        let buf_ind = *self.active_buffer.get(&window_id).expect("active window has no active buffer");
-        let raw_buffer = &self.buffers.get()[buf_ind];
+    fn window_event(&mut self, _event_loop: &dyn ActiveEventLoop, window_id: WindowId, event: WindowEvent) {
+        let window_state = self.windows.get_mut(&window_id).expect("recieving window event but we lost window, should be impossible");
 
         match event {
             WindowEvent::CloseRequested => {
-                event_loop.exit();
+                if self.windows.len() > 1 {
+                    // anything unsaved is kept as a buffer, like closing its pane
+                    self.close_window(window_id, false);
+                } else if self.run_ops(window_id, vec![WindowOp::Exit.into()]) {
+                    self.windows[&window_id].window.request_redraw();
+                }
             },
             WindowEvent::SurfaceResized(size) => {
-                window_state.font_render.style.vheight = size.height as f32 - TITLEBAR_HEIGHT - Y_PADDING;
+                window_state.font_render.style.vheight = size.height as f32 - TITLEBAR_HEIGHT - Y_PADDING - window_state.font_render.style.line_height;
                 window_state.font_render.style.vwidth = size.width as f32 - X_PADDING;
+                window_state.font_render.style.set_layout(&window_state.layout);
                 window_state.render_cx.resize_surface(&mut window_state.surface, size.width, size.height);
                 window_state.window.request_redraw();
             },
             WindowEvent::MouseWheel{delta, ..} => {
+                // scroll whatever is under the pointer, not the focused pane
+                let area = window_state.font_render.style.text_area();
+                let (px, py) = (window_state.pointer.x as f32, window_state.pointer.y as f32);
+                let pane_id = window_state.layout.pane_at(area, px, py).unwrap_or(window_state.layout.focused);
+                let buf_ind = self.panes.get()[&pane_id].buffer_id;
+                let raw_buffer = &self.buffers.get()[&buf_ind];
                 match delta {
                     MouseScrollDelta::LineDelta(_, y) => {
-                        let end = (raw_buffer.num_lines()-1) as f32 * window_state.font_render.style.line_height;
+                        let line_height = window_state.font_render.style.line_height;
+                        let end = (raw_buffer.num_lines()-1) as f32;
 
                         // Adjust the scroll position based on the scroll delta
-                        let pane = self.panes.get()[window_state.layout.pane_id].scroll_y(-y * 20., end);
-                        self.panes.store(pane.id, pane);
+                        self.panes.modify(pane_id, |pane| (pane.scroll_y(-y * 20. / line_height, end), ()));
                         log::warn!("we don't expect a linedelta from mouse scroll on macOS, ignoring");
                     },
                     MouseScrollDelta::PixelDelta(PhysicalPosition{x: _, y}) => {
-                        let end = (raw_buffer.num_lines()-1) as f32 * window_state.font_render.style.line_height;
-                        let pane = self.panes.get()[window_state.layout.pane_id].scroll_y(-y as f32, end);
-                        self.panes.store(pane.id, pane);
+                        let line_height = window_state.font_render.style.line_height;
+                        let end = (raw_buffer.num_lines()-1) as f32;
+                        self.panes.modify(pane_id, |pane| (pane.scroll_y(-y as f32 / line_height, end), ()));
                         window_state.window.request_redraw();
                     },
                 }
@@ -418,10 +756,23 @@ impl<'a> ApplicationHandler for App<'a> {
                     let x = position.x as f32;
                     let y = position.y as f32;
 
+                    // clicking a pane focuses it
+                    let area = window_state.font_render.style.text_area();
+                    let Some(pane_id) = window_state.layout.pane_at(area, x, y) else {
+                        return;
+                    };
+                    if pane_id != window_state.layout.focused {
+                        window_state.layout.focused = pane_id;
+                        window_state.window.request_redraw();
+                    }
+
                     // find closest line
                     let mut closest_line = None;
                     let mut closest = f32::MAX;
-                    let lines = window_state.line_caches.get(&buf_ind).unwrap();
+                    // a split that hasn't been drawn yet has nothing to click on
+                    let Some(lines) = window_state.line_caches.get(&pane_id) else {
+                        return;
+                    };
                     for y1 in lines.iter() {
                         let middle = y1-window_state.font_render.style.ascent/2.;
                         let dist = (y - middle).abs();
@@ -430,13 +781,15 @@ impl<'a> ApplicationHandler for App<'a> {
                             closest_line = Some(y1);
                         }
                     }
-                    let closest_line = closest_line.expect("no lines in line cache");
+                    let Some(closest_line) = closest_line else {
+                        return;
+                    };
                     let right_line: f32 = *closest_line;
 
                     // which glyph
                     let mut closest = None;
                     let mut closest_dist = f32::MAX;
-                    for (i, ((_, _), (x1, y1))) in window_state.glyph_pos_caches.get(&buf_ind).unwrap().iter() {
+                    for (i, ((_, _), (x1, y1))) in window_state.glyph_pos_caches.get(&pane_id).unwrap().iter() {
                         if *y1 != right_line {
                             continue;
                         }
@@ -450,21 +803,34 @@ impl<'a> ApplicationHandler for App<'a> {
                         }
                     }
                     if let Some(i) = closest {
-                        let active = vec![window_state.layout.pane_id];
+                        let active = vec![pane_id];
                         if self.mods.lalt_state() == ModifiersKeyState::Pressed || self.mods.ralt_state() == ModifiersKeyState::Pressed {
-                            self.buffer_tx.send((BufferOp::AddCursor(*i), active)).unwrap();
+                            self.buffer_tx.send(BufferMsg::Op(BufferOp::AddCursor(*i), active)).unwrap();
                         } else {
-                            self.buffer_tx.send((BufferOp::SetMainCursor(*i), active)).unwrap();
+                            self.buffer_tx.send(BufferMsg::Op(BufferOp::SetMainCursor(*i), active)).unwrap();
                         }
                     }
                 } else {
                     log::info!("mouse input: {state:?}, {button:?}");
                 }
             },
+            WindowEvent::Focused(true) => {
+                // anything that came in while no window had focus
+                if window_state.prompt.is_none() && !self.prompts.is_empty() {
+                    window_state.prompt = self.prompts.pop_front();
+                    window_state.window.request_redraw();
+                }
+            },
+            WindowEvent::Focused(false) => {
+                if self.autosave.lock().unwrap().on_focus_loss {
+                    self.buffer_tx.send(BufferMsg::Autosave).unwrap();
+                }
+            },
             WindowEvent::ModifiersChanged(state) => {
                 self.mods = state
             },
             WindowEvent::PointerMoved { device_id: _, position, primary: _, source: _ } => {
+                window_state.pointer = position;
                 let top = window_state.font_render.style.voffset_y;
                 if position.y <= top as f64 {
                     if position.x < 128. { // buttons
@@ -479,26 +845,46 @@ impl<'a> ApplicationHandler for App<'a> {
             WindowEvent::KeyboardInput{device_id: _, event, is_synthetic: _} => {
                 if event.state != ElementState::Released {
                     self.cursor_blink_last_key.send(()).unwrap();
-                    let pane = &self.panes.get()[window_state.layout.pane_id];
-                    let (new_pane, ops) = pane.key(event.logical_key, &self.mods);
-                    let should_redraw = new_pane.mode != pane.mode;
-                    for op in ops {
-                        if op == BufferOp::Exit {
-                            event_loop.exit();
+                    if let Some(prompt) = window_state.prompt.take() {
+                        let answer = match &event.logical_key {
+                            Key::Named(NamedKey::Escape) => Some(vec![]),
+                            Key::Character(s) => s.chars().next().and_then(|c| prompt.answer(c)),
+                            _ => None,
+                        };
+                        match answer {
+                            Some(ops) => {
+                                self.run_ops(window_id, ops);
+                            },
+                            // keep asking
+                            None => window_state.prompt = Some(prompt),
+                        }
+                        // the answer might have closed it
+                        if let Some(window_state) = self.windows.get_mut(&window_id) {
+                            if window_state.prompt.is_none() {
+                                window_state.prompt = self.prompts.pop_front();
+                            }
+                            window_state.window.request_redraw();
                         }
-                        self.buffer_tx.send((op, vec![new_pane.id])).unwrap();
+                        return;
                     }
-                    self.panes.store(new_pane.id, new_pane);
-                    if should_redraw {
-                        window_state.window.request_redraw();
+                    // store first, so a split copies the pane as it is now
+                    let mods = &self.mods;
+                    let (ops, changed) = self.panes.modify(window_state.layout.focused, |pane| {
+                        let (new_pane, ops) = pane.key(event.logical_key.clone(), mods);
+                        let changed = new_pane.mode != pane.mode || new_pane.cmdline != pane.cmdline;
+                        (new_pane, (ops, changed))
+                    }).expect("the focused pane is always in the registry");
+                    let should_redraw = changed || window_state.message.is_some();
+                    window_state.message = None;
+                    if self.run_ops(window_id, ops) || should_redraw {
+                        if let Some(window_state) = self.windows.get(&window_id) {
+                            window_state.window.request_redraw();
+                        }
                     }
                 }
             },
             WindowEvent::RedrawRequested => {
-                let pane = &self.panes.get()[window_state.layout.pane_id];
-                let (gpc, lc) = redraw_requested_handler(&mut window_state, &raw_buffer, pane);
-                window_state.glyph_pos_caches.insert(buf_ind, gpc);
-                window_state.line_caches.insert(buf_ind, lc);
+                redraw_requested_handler(window_state, &self.buffers.get(), &self.panes.get());
             },
             WindowEvent::ThemeChanged(_theme) => {
                 // TODO
@@ -522,6 +908,161 @@ impl<'a> ApplicationHandler for App<'a> {
     }
 }
 
+// a file that doesn't exist yet gets made when it's saved. It's read by the
+// buffer thread, so the window can show up straight away. Stdin's read now
+fn open_input(input: &Input) -> Result<TextBuffer, String> {
+    let text = |text: &str| TextBuffer::new(None, Rope::from(text.replace("\r\n", "\n")));
+    match input {
+        Input::File { path, .. } => TextBuffer::pending(path).map_err(|e| format!("can't open {path}: {e}")),
+        Input::Stdin => {
+            let mut bytes = vec![];
+            std::io::stdin().read_to_end(&mut bytes)
+                .map(|_| text(&String::from_utf8_lossy(&bytes)))
+                .map_err(|e| format!("can't read stdin: {e}"))
+        },
+        Input::Text(s) => Ok(text(s)),
+    }
+}
+
+// a pane for some files from the command line (ours or another chop's)
+struct Opened {
+    pane_id: PaneId,
+    // for the buffer thread once the pane's up
+    ops: Vec<BufferMsg>,
+    // the buffers of the files (not stdin), for `--wait`
+    files: Vec<BufferId>,
+    // the buffers that were made for them, that still need reading
+    new: Vec<BufferId>,
+}
+
+// one pane showing the first, the rest are there to switch to. Files that are
+// open already aren't opened again. Nothing's opened if one of them can't be
+fn open_inputs(buffers: &Registry<TextBuffer>, panes: &Registry<Pane>, inputs: &[Input], read_only: bool) -> Result<Opened, String> {
+    let all = buffers.get();
+    let already_open = |input: &Input| match input {
+        Input::File { path, .. } => {
+            let path = std::path::absolute(path).ok();
+            all.iter().find(|(_, b)| b.file.as_ref().is_some_and(|fi| std::path::absolute(&fi.filename).ok() == path)).map(|(id, _)| *id)
+        },
+        _ => None,
+    };
+    let read = inputs.iter().map(|input| match already_open(input) {
+        Some(_) => Ok(None),
+        None => open_input(input).map(Some),
+    }).collect::<Result<Vec<_>, _>>()?;
+
+    let (mut ops, mut files, mut new) = (vec![], vec![], vec![]);
+    let mut pane_id = None;
+    for (input, buffer) in inputs.iter().zip(read) {
+        let buf_id = match buffer {
+            Some(buffer) => {
+                let id = buffers.insert_with(|_| buffer);
+                new.push(id);
+                id
+            },
+            None => already_open(input).unwrap(),
+        };
+        let pane_id = *pane_id.get_or_insert_with(|| panes.insert_with(|pane_id| Pane::new(buf_id, pane_id)));
+        if let Input::File { line: Some(line), col, .. } = input {
+            let goto = BufferOp::GoTo { buffer: buf_id, line: line.saturating_sub(1), col: col.unwrap_or(1).saturating_sub(1) };
+            ops.push(BufferMsg::Op(goto, vec![pane_id]));
+        }
+        if read_only {
+            ops.push(BufferMsg::SetReadOnly(buf_id));
+        }
+        if matches!(input, Input::File { .. }) {
+            files.push(buf_id);
+        }
+    }
+    let pane_id = match pane_id {
+        Some(pane_id) => pane_id,
+        // nothing given, so something to type in
+        None => {
+            let buf_id = buffers.insert_with(|_| TextBuffer::from_blank());
+            panes.insert_with(|pane_id| Pane::new(buf_id, pane_id))
+        },
+    };
+    Ok(Opened {pane_id, ops, files, new})
+}
+
+// like vim's `:ls`, `%` is the buffer in the pane and `#` the alternate
+fn buffer_list(buffers: &OrdMap<BufferId, TextBuffer>, pane: &Pane) -> String {
+    buffers.iter().map(|(id, buf)| {
+        let flag = if *id == pane.buffer_id {
+            "%"
+        } else if Some(*id) == pane.alternate {
+            "#"
+        } else {
+            ""
+        };
+        let modified = if buf.is_modified() { " [+]" } else { "" };
+        format!("{id}{flag} {}{modified}", buf.name())
+    }).collect::<Vec<_>>().join("  ")
+}
+
+fn edit_command(panes: &Registry<Pane>, pane_id: PaneId, cmdline: &str) {
+    panes.modify(pane_id, |pane| (Pane {mode: Mode::Command, cmdline: cmdline.to_string(), ..pane.clone()}, ()));
+}
+
+// closing the last pane on a buffer closes the buffer, so ask first if that loses anything
+fn close_prompt(buffers: &Registry<TextBuffer>, panes: &Registry<Pane>, pane_id: PaneId) -> Option<Prompt> {
+    if !panes.is_last_view(pane_id) {
+        return None;
+    }
+    let buffer = &buffers.get()[&panes.get()[&pane_id].buffer_id];
+    if !buffer.has_unsaved_changes() {
+        return None;
+    }
+    let close = |discard| Op::from(WindowOp::ClosePane { discard });
+    Some(if buffer.file.is_some() {
+        Prompt::new(format!("save changes to {}? [y]es [n]o [c]ancel", buffer.name()), vec![
+            ('y', vec![BufferOp::SaveThen(vec![close(false)]).into()]),
+            ('n', vec![close(true)]),
+            ('c', vec![]),
+        ])
+    } else {
+        Prompt::new(format!("discard {}? [y]es [n]o", buffer.name()), vec![
+            ('y', vec![close(true)]),
+            ('n', vec![]),
+        ])
+    })
+}
+
+// false if it's the last pane in the window, which closes the window instead
+fn close_pane(window_state: &mut WindowState, buffer_tx: &mpsc::Sender<BufferMsg>, discard: bool) -> bool {
+    let focused = window_state.layout.focused;
+    if !window_state.layout.close(focused) {
+        return false;
+    }
+    window_state.glyph_pos_caches.remove(&focused);
+    window_state.line_caches.remove(&focused);
+    window_state.font_render.style.set_layout(&window_state.layout);
+    buffer_tx.send(BufferMsg::Op(BufferOp::ClosePane { discard }, vec![focused])).unwrap();
+    true
+}
+
+fn layout_op(window_state: &mut WindowState, panes: &Registry<Pane>, op: LayoutOp) {
+    let area = window_state.font_render.style.text_area();
+    let layout = &mut window_state.layout;
+    match op {
+        LayoutOp::Split(dir) => {
+            // the new pane starts out looking at the same place
+            let pane = panes.get()[&layout.focused].clone();
+            let id = panes.insert_with(|id| Pane {id, ..pane});
+            layout.split(dir, id);
+        },
+        LayoutOp::Close | LayoutOp::ForceClose => {
+            unreachable!("closing goes through `close_pane`");
+        },
+        LayoutOp::Focus(dir) => layout.focus(area, dir),
+        LayoutOp::FocusNext => layout.focus_next(),
+        LayoutOp::Swap(dir) => layout.swap(area, dir),
+        LayoutOp::Resize(dir, percent) => layout.resize(dir, percent),
+        LayoutOp::Equalize => layout.equalize(),
+    }
+    window_state.font_render.style.set_layout(&window_state.layout);
+}
+
 fn extract_urls_from_array(array: &NSArray<NSURL>) -> Vec<String> {
     let mut urls = Vec::new();
 
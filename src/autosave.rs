@@ -0,0 +1,106 @@
+// Saving without being asked to. Any of these can be turned on with `:set`:
+//
+//   autosave_idle=<ms>      once nothing's been typed for that long
+//   autosave_interval=<ms>  that long after the first edit since the last
+//                           save, typing or not
+//   autosave_focus          when the window loses focus
+//
+// The timers run on their own thread, which hears about edits as an observer
+// and sends `BufferMsg::Autosave` to the buffer thread when one goes off. What
+// gets saved is decided there (see `TextBuffer::can_autosave`): modified
+// buffers with a file that nobody else has changed under us.
+
+use std::sync::{mpsc, Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::buffer::BufferMsg;
+use crate::observer::BufferEvent;
+
+// everything's off to start with
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct Policy {
+    pub idle: Option<Duration>,
+    pub interval: Option<Duration>,
+    pub on_focus_loss: bool,
+}
+
+// one `:set` of a policy
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Setting {
+    Idle(Option<Duration>),
+    Interval(Option<Duration>),
+    FocusLoss(bool),
+}
+
+impl Policy {
+    pub fn apply(&mut self, setting: Setting) {
+        match setting {
+            Setting::Idle(idle) => self.idle = idle,
+            Setting::Interval(interval) => self.interval = interval,
+            Setting::FocusLoss(on) => self.on_focus_loss = on,
+        }
+    }
+
+    // when the next save is due, None until there's been an edit since the last
+    // one. `first_edit` and `last_edit` are the first and latest of those edits
+    fn next(&self, first_edit: Option<Instant>, last_edit: Option<Instant>) -> Option<Instant> {
+        let idle = self.idle.zip(last_edit).map(|(idle, edit)| edit + idle);
+        let interval = self.interval.zip(first_edit).map(|(interval, edit)| edit + interval);
+        idle.into_iter().chain(interval).min()
+    }
+}
+
+// a policy that's just been turned on is noticed within this long
+const POLL: Duration = Duration::from_secs(1);
+
+pub fn spawn(events: mpsc::Receiver<Arc<BufferEvent>>, policy: Arc<Mutex<Policy>>, buffer_tx: mpsc::Sender<BufferMsg>) {
+    std::thread::spawn(move || {
+        let mut first_edit = None;
+        let mut last_edit = None;
+        loop {
+            let next = policy.lock().unwrap().next(first_edit, last_edit);
+            let wait = next.map_or(POLL, |next| next.saturating_duration_since(Instant::now()).min(POLL));
+            match events.recv_timeout(wait) {
+                // the idle timer starts again on every edit, the interval only on the first
+                Ok(event) if matches!(*event, BufferEvent::Changed(_)) => {
+                    let now = Instant::now();
+                    first_edit = first_edit.or(Some(now));
+                    last_edit = Some(now);
+                },
+                Ok(_) | Err(mpsc::RecvTimeoutError::Timeout) => {},
+                Err(mpsc::RecvTimeoutError::Disconnected) => return,
+            }
+            let next = policy.lock().unwrap().next(first_edit, last_edit);
+            if next.is_some_and(|next| next <= Instant::now()) {
+                if buffer_tx.send(BufferMsg::Autosave).is_err() {
+                    return;
+                }
+                first_edit = None;
+                last_edit = None;
+            }
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_next() {
+        let now = Instant::now();
+        let second = Duration::from_secs(1);
+        let later = now + second;
+        let mut policy = Policy::default();
+        assert_eq!(policy.next(Some(now), Some(now)), None);
+        policy.apply(Setting::Idle(Some(second)));
+        // nothing to save until something's typed
+        assert_eq!(policy.next(None, None), None);
+        assert_eq!(policy.next(Some(now), Some(later)), Some(later + second));
+        // still typing, but it's been long enough since the first edit
+        policy.apply(Setting::Interval(Some(second / 2)));
+        assert_eq!(policy.next(Some(now), Some(later)), Some(now + second / 2));
+        policy.apply(Setting::Idle(None));
+        assert_eq!(policy.next(None, None), None);
+    }
+}
@@ -1,42 +1,193 @@
-use std::collections::HashSet;
-use std::path::Path;
+use std::collections::{BTreeSet, HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
 use std::iter::Iterator;
 use std::sync::Arc;
-use std::time::SystemTime;
-use std::fs::{read_to_string, OpenOptions};
-use std::io::Write;
+use std::time::{Duration, Instant, SystemTime};
 use crop::Rope;
 use im::OrdMap;
 
-use arc_swap::ArcSwap;
 use winit::event_loop::EventLoopProxy;
 use std::sync::mpsc;
 
 use crate::pane::Selection;
 use crate::pane::Pane;
 use crate::pane::PaneId;
+use crate::pane::View;
+use crate::layout::LayoutOp;
+use crate::registry::Registry;
+use crate::file::atomic_write;
+use crate::prompt::Prompt;
+use crate::watcher::FileWatcher;
+use crate::diff;
+use crate::hex;
+use crate::compress::{self, Compression};
+use crate::journal;
+use crate::autosave;
+use crate::undo::{self, Cursors, History};
+use crate::versions;
+use crate::server::{Client, Request};
+use crate::rpc::Ack;
+use crate::loader::{self, LoadEvent, Progress, LARGE_FILE};
+use crate::encoding::{TextEncoding, LineEnding};
+use crate::observer::{Edit, Observers, BufferEvent, BufferChange, affected_lines};
 
 pub type BufferId = usize;
 
-#[derive(Debug, Eq, PartialEq)]
+// what a key, command or prompt answer does in the focused pane. The window
+// does its own and sends the rest to the buffer thread
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum Op {
+    Buffer(BufferOp),
+    Window(WindowOp),
+}
+
+impl From<BufferOp> for Op {
+    fn from(op: BufferOp) -> Self {
+        Op::Buffer(op)
+    }
+}
+
+impl From<WindowOp> for Op {
+    fn from(op: WindowOp) -> Self {
+        Op::Window(op)
+    }
+}
+
+// done in a pane by the buffer thread
+#[derive(Debug, Clone, Eq, PartialEq)]
 pub enum BufferOp {
     Insert(String),
     Delete,
     Save,
-    Exit,
+    // save, and only once that's worked run the ops (in the pane, on the UI
+    // thread). For closing without losing anything
+    SaveThen(Vec<Op>),
+    // attach the buffer to a new file and save it there. Without `overwrite` (or
+    // `create_dirs`) it asks before replacing a file (or making its directory)
+    SaveAs { path: String, overwrite: bool, create_dirs: bool },
     MoveHorizontal(i64),
     MoveVertical(i64),
     SetMainCursor(usize),
     AddCursor(usize),
+    // show the file in the pane, opening it if it isn't already
+    Open(String),
+    SwitchBuffer(SwitchTo),
+    // the pane has been taken out of its window, forget it (and its buffer if
+    // nothing else is showing it). Without `discard` a buffer with unsaved
+    // changes is kept around
+    ClosePane { discard: bool },
+    // answers to a buffer whose file changed under it: take the file's version,
+    // keep the buffer's (and stop asking), or show the difference
+    Reload(BufferId),
+    KeepLocal(BufferId),
+    DiffDisk(BufferId),
+    // what the buffer is saved as from now on, by name (`latin1`, `utf-16le`, ...)
+    SetEncoding(String),
+    // whether to start the file with a byte order mark
+    SetBom(bool),
+    // what each `\n` is saved as
+    SetLineEnding(LineEnding),
+    // show the file as a hex dump (see `hex`), or go back to text
+    SetHex(bool),
+    // stop reading the file, whatever's been read so far stays (read only)
+    CancelLoad,
+    // answers to a buffer whose file has a journal left by a crash (see `journal`):
+    // take what's in it, show the difference, or delete it
+    Recover(BufferId),
+    DiffRecovery(BufferId),
+    DiscardRecovery(BufferId),
+    // take back the last change to the buffer (see `undo`), or make it again
+    Undo,
+    Redo,
+    // the saved versions of the buffer's file (see `versions`): list them, show
+    // what's changed since one (1 is the newest), or put one back in the buffer
+    ListVersions,
+    DiffVersion(usize),
+    RestoreVersion(usize),
+    // put the pane's cursor on (0 indexed) `line` and `col` (in graphemes) of
+    // `buffer`, once its file is read. The pane doesn't have to be showing it
+    GoTo { buffer: BufferId, line: usize, col: usize },
+    // replace `start..end` (bytes) of the pane's buffer with `text`, from `rpc`.
+    // Cursors move along with the text around them, like when it's reloaded.
+    // Whether it could be done goes back through `ack`, not the message line
+    Replace { start: usize, end: usize, text: String, ack: Ack },
+    // put the pane's cursors here, the first is the main one
+    SetCursors(Vec<Selection>),
+    // put the pane back where it was in a saved session, once its file is read
+    RestoreView { cursors: Vec<Selection>, main_cursor_start: usize, top_line: usize },
+}
+
+// done by the window itself, these never get to the buffer thread
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum WindowOp {
+    // asks first if anything's unsaved
+    Exit,
+    // exit even with unsaved changes
+    ForceExit,
+    // exit with an error, to say the file wasn't what was wanted (`:cq`, see `wait`)
+    Abort,
+    Layout(LayoutOp),
+    // take the pane out of the window without asking, see `BufferOp::ClosePane`
+    ClosePane { discard: bool },
+    ListBuffers,
+    Message(String),
+    // open the command line with this already typed
+    EditCommand(String),
+    // change when autosaves happen
+    SetAutosave(autosave::Setting),
+}
+
+// everything the buffer thread is sent
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum BufferMsg {
+    // an op in the first pane, the rest are the other panes it's done in at the same time
+    Op(BufferOp, Vec<PaneId>),
+    // from the thread reading a file, see `loader`
+    Load(LoadEvent),
+    // something else wrote to (or deleted) the file, from the watcher
+    FileChanged(PathBuf),
+    // save every buffer that can be without asking, from the autosave timers
+    Autosave,
+    // don't let the buffer be edited or saved over its file (`--readonly`)
+    SetReadOnly(BufferId),
+    // start reading the file of a buffer the UI thread just made (for `server`)
+    StartLoading(BufferId),
+    // everything sent before it has been done, see `rpc`
+    Ack(Ack),
+    // tell the UI to exit once everything before it is done, anything unsaved
+    // is thrown away
+    Exit,
+}
+
+// which buffer a pane should show next
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
+pub enum SwitchTo {
+    Next,
+    Prev,
+    // the one it showed before this one
+    Alternate,
+    Id(BufferId),
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Clone, PartialEq)]
 pub enum CustomEvent {
     BufferRequestedRedraw(BufferId),
     CursorBlink(bool),
+    // show something to the user in the status line
+    Message(String),
+    // ask the user something
+    Prompt(Prompt),
+    // everything sent to the buffer thread before the exit is done
+    Exit,
+    // another chop (or Finder) wants files opened, see `server`
+    Open(Request, Client),
+    // run these in the pane as if they were typed there (a command from `rpc`,
+    // then `ack` once the buffer thread's done them)
+    RunOps(PaneId, Vec<Op>, Option<Ack>),
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct FileInfo {
     pub filename: Arc<Path>,
     // whether we've modified the buffer since `file_time`
@@ -44,12 +195,44 @@ pub struct FileInfo {
     // last time that the file and buffer were identical 
     // (used to check if other processes have changed it)
     pub file_time: SystemTime,
+    // the file doesn't exist yet, it gets created on the first save
+    pub is_new: bool,
+    // what the file was in, and so what it's saved in
+    pub encoding: TextEncoding,
+    pub line_ending: LineEnding,
+    // too big for the expensive stuff (reloading, diffing, observers)
+    pub large: bool,
+    // saving it would lose something (it couldn't all be read, or decoded)
+    pub read_only: bool,
+    // the buffer is a hex dump of the file, not its text
+    pub hex: bool,
+    // what the file's compressed with, the buffer has it decompressed
+    pub compression: Option<Compression>,
 }
 
-#[derive(Debug, Clone)]
+impl FileInfo {
+    // the file's mtime, None if it's gone
+    pub fn disk_time(&self) -> Option<SystemTime> {
+        self.filename.metadata().and_then(|m| m.modified()).ok()
+    }
+
+    // whether something else has written the file since we last read or saved it
+    pub fn changed_on_disk(&self) -> bool {
+        !self.is_new && self.disk_time().is_some_and(|t| t > self.file_time)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct TextBuffer {
     pub file: Option<FileInfo>,
     pub contents: Rope,
+    // bumped every time `contents` changes
+    pub version: u64,
+    // Some while the file (or the rest of a large one) is still being read
+    pub loading: Option<Progress>,
+    pub history: History,
+    // made by us to look at (a diff), closing it doesn't lose anything
+    pub scratch: bool,
 }
 
 impl Default for TextBuffer {
@@ -57,6 +240,10 @@ impl Default for TextBuffer {
         Self {
             file: None,
             contents: Rope::from(""),
+            version: 0,
+            loading: None,
+            history: History::default(),
+            scratch: false,
         }
     }
 }
@@ -69,21 +256,79 @@ impl TextBuffer {
             Self { 
                 file,
                 contents,
+                ..Default::default()
             }
     }
 
+    pub fn scratch(text: String) -> Self {
+        Self {contents: Rope::from(text), scratch: true, ..Default::default()}
+    }
+
     pub fn from_filename(filename_str: &str) -> Result<Self, std::io::Error> {
         let filename: Arc<Path> = Arc::from(Path::new(filename_str));
-        let size = filename.metadata()?.len();
-        let three_gb = 3*1024*1024*1024;
-        if size >= three_gb {
-            return Err(std::io::Error::new(std::io::ErrorKind::InvalidData, "File was larger than 3GB"));
+        let metadata = filename.metadata()?;
+        let size = metadata.len();
+        let file_time = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+        // a compressed one has to be decompressed all at once anyway
+        if size >= LARGE_FILE && Compression::of_file(&filename)?.is_none() {
+            // just enough to show, the buffer thread reads the rest
+            let (chunk, line_ending) = loader::first_chunk(&filename)?;
+            let loading = Some(Progress {read: chunk.len, total: size});
+            let fi = FileInfo {filename, is_modified: chunk.mixed, file_time, is_new: false, encoding: TextEncoding::default(), line_ending, large: true, read_only: chunk.lossy, hex: false, compression: None};
+            return Ok(Self {file: Some(fi), contents: Rope::from(chunk.text), loading, ..Default::default()});
         }
-        let contents = Rope::from(read_to_string(filename_str)?);
-        let fi = FileInfo {filename, is_modified: false, file_time: SystemTime::now()};
+        let (bytes, compression) = compress::read(&filename)?;
+        let hex = hex::is_binary(&bytes);
+        let (text, encoding, line_ending, lossy, mixed) = decode(&bytes, hex);
+        let contents = Rope::from(text);
+        let fi = FileInfo {filename, is_modified: mixed, file_time, is_new: false, encoding, line_ending, large: false, read_only: lossy, hex, compression};
         Ok(Self {file: Some(fi), contents, ..Default::default()})
     }
 
+    // with the undo history that was saved with its file (see `undo`), if
+    // there's one for what it has now
+    pub fn with_saved_history(self) -> Self {
+        let (Some(dir), Some(fi)) = (undo::dir(), &self.file) else {
+            return self;
+        };
+        if fi.large {
+            return self;
+        }
+        match undo::read(&dir, &fi.filename, &self.contents) {
+            Some(history) => Self {history, ..self},
+            None => self,
+        }
+    }
+
+    // an empty buffer for a file that doesn't exist yet
+    pub fn new_file(filename_str: &str) -> Self {
+        let filename: Arc<Path> = Arc::from(Path::new(filename_str));
+        let fi = FileInfo {filename, is_modified: false, file_time: SystemTime::now(), is_new: true, encoding: TextEncoding::default(), line_ending: LineEnding::default(), large: false, read_only: false, hex: false, compression: None};
+        Self {file: Some(fi), ..Default::default()}
+    }
+
+    // like `from_filename`, but a missing file is a new (empty) one
+    pub fn open(filename_str: &str) -> Result<Self, std::io::Error> {
+        match Self::from_filename(filename_str) {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => Ok(Self::new_file(filename_str)),
+            r => r,
+        }
+    }
+
+    // like `open`, but without reading anything. It's an empty read only stand-in
+    // until the buffer thread has read the file (see `loader::load`)
+    pub fn pending(filename_str: &str) -> Result<Self, std::io::Error> {
+        let filename: Arc<Path> = Arc::from(Path::new(filename_str));
+        let metadata = match filename.metadata() {
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(Self::new_file(filename_str)),
+            r => r?,
+        };
+        let size = metadata.len();
+        let file_time = metadata.modified().unwrap_or_else(|_| SystemTime::now());
+        let fi = FileInfo {filename, is_modified: false, file_time, is_new: false, encoding: TextEncoding::default(), line_ending: LineEnding::default(), large: size >= LARGE_FILE, read_only: true, hex: false, compression: None};
+        Ok(Self {file: Some(fi), loading: Some(Progress {read: 0, total: size}), ..Default::default()})
+    }
+
     pub fn from_blank() -> Self {
         let contents = Rope::new();
         Self {file: None, contents, ..Default::default()}
@@ -92,26 +337,92 @@ impl TextBuffer {
     // has to return Self because writing updates the file info 
     // (when they were last in sync and if it's modified)
     pub fn write(&self, filename: &Path) -> Result<Self, std::io::Error> {
-        let mut file = OpenOptions::new()
-            .write(true)
-            .open(filename)?;
-        for chunk in self.contents.chunks() {
-            file.write_all(chunk.as_bytes())?;
+        let encoding = self.file.as_ref().map(|fi| fi.encoding).unwrap_or_default();
+        let line_ending = self.file.as_ref().map(|fi| fi.line_ending).unwrap_or_default();
+        let compression = self.file.as_ref().and_then(|fi| fi.compression);
+        if compression.is_none() && !self.is_hex() && encoding.is_utf8() && line_ending == LineEnding::Lf {
+            atomic_write(filename, self.contents.chunks())?;
+        } else {
+            let bytes = self.to_bytes().map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+            let bytes = match compression {
+                Some(compression) => compression.compress(&bytes)?,
+                None => bytes,
+            };
+            atomic_write(filename, std::iter::once(bytes))?;
         }
 
+        let file_time = filename.metadata().and_then(|m| m.modified()).unwrap_or_else(|_| SystemTime::now());
         let fi = Some(FileInfo {
                 filename: Arc::from(filename),
                 is_modified: false,
-                file_time: SystemTime::now(),
+                file_time,
+                is_new: false,
+                encoding,
+                line_ending,
+                large: self.is_large(),
+                read_only: false,
+                hex: self.is_hex(),
+                compression,
         });
         let contents = self.contents.clone();
         Ok(Self {
             file: fi,
             contents,
-            ..*self
+            ..self.clone()
         })
     }
 
+    // what the file would have in it, before it's compressed
+    fn to_bytes(&self) -> Result<Vec<u8>, String> {
+        let text = self.contents.to_string();
+        match &self.file {
+            Some(fi) if fi.hex => hex::parse(&text),
+            Some(fi) => fi.encoding.encode(&fi.line_ending.apply(&text)),
+            None => Ok(text.into_bytes()),
+        }
+    }
+
+    // what to call the buffer in the status line and buffer list
+    pub fn name(&self) -> String {
+        match &self.file {
+            Some(fi) => fi.filename.display().to_string(),
+            None => "[No Name]".to_string(),
+        }
+    }
+
+    pub fn is_large(&self) -> bool {
+        self.file.as_ref().is_some_and(|fi| fi.large)
+    }
+
+    pub fn is_read_only(&self) -> bool {
+        self.file.as_ref().is_some_and(|fi| fi.read_only)
+    }
+
+    pub fn is_hex(&self) -> bool {
+        self.file.as_ref().is_some_and(|fi| fi.hex)
+    }
+
+    // modified, and saving it wouldn't need a question answered first (where
+    // to, whether to overwrite someone else's changes, ...)
+    pub fn can_autosave(&self) -> bool {
+        match &self.file {
+            Some(fi) => fi.is_modified && !fi.is_new && !fi.read_only && self.loading.is_none() && !fi.changed_on_disk(),
+            None => false,
+        }
+    }
+
+    pub fn is_modified(&self) -> bool {
+        self.file.as_ref().is_some_and(|fi| fi.is_modified)
+    }
+
+    // would anything be lost if we closed it
+    pub fn has_unsaved_changes(&self) -> bool {
+        match &self.file {
+            Some(fi) => fi.is_modified,
+            None => !self.scratch && self.contents.byte_len() > 0,
+        }
+    }
+
     pub fn num_lines(&self) -> usize {
         self.contents.lines().count()
     }
@@ -134,49 +445,48 @@ impl TextBuffer {
         // assert only 1 buffer is involved (this buffer)
         assert!(h.len() == 1, "Only 1 buffer should be involved. Found: {:?}", h);
 
-        // this can change
-        assert!(active.len() == 1);
-        assert!(panes.len() == 1);
-        let pane = panes.iter().filter(|pane| pane.id == active[0]).nth(0).expect("the active pane must be in the involved panes");
-
-        let file = self.file.clone();
-        let mut cursors = OrdMap::new();
-        let mut main_cursor_start = usize::MAX;
-        for s in pane.cursors_iter() {
-            let line = self.contents.line_of_byte(s.start);
-            let other_line = ((line as i64 + offset).max(0) as usize).min(self.contents.line_len());
-            let other_line_start = self.contents.byte_of_line(other_line);
-            let other_line_end = if other_line == self.contents.line_len() {
-                self.contents.byte_len()
-            } else {
-                self.contents.byte_of_line(other_line + 1)
-            };
+        let mut moved = vec![];
+        for pane in panes.iter().filter(|pane| active.contains(&pane.id)) {
+            let mut cursors = OrdMap::new();
+            let mut main_cursor_start = usize::MAX;
+            for s in pane.cursors_iter() {
+                let line = self.contents.line_of_byte(s.start);
+                let other_line = ((line as i64 + offset).max(0) as usize).min(self.contents.line_len());
+                let other_line_start = self.contents.byte_of_line(other_line);
+                let other_line_end = if other_line == self.contents.line_len() {
+                    self.contents.byte_len()
+                } else {
+                    self.contents.byte_of_line(other_line + 1)
+                };
 
-            let mut start = other_line_start;
-            for _ in 0..pane.grapheme_col_offset {
-                if start+1 >= other_line_end {
-                    break;
-                }
-                start += 1;
-                while !self.contents.is_grapheme_boundary(start) {
+                let mut start = other_line_start;
+                for _ in 0..pane.grapheme_col_offset {
+                    if start+1 >= other_line_end {
+                        break;
+                    }
                     start += 1;
+                    while !self.contents.is_grapheme_boundary(start) {
+                        start += 1;
+                    }
+                }
+                cursors.insert(start, Selection{start, offset: 0});
+                if s.start == pane.main_cursor_start {
+                    main_cursor_start = start;
                 }
             }
-            cursors.insert(start, Selection{start, offset: 0});
-            if s.start == pane.main_cursor_start {
-                main_cursor_start = start;
-            }
+            assert!(main_cursor_start != usize::MAX);
+            moved.push(Pane {
+                main_cursor_start,
+                cursors,
+                ..pane.clone()
+            });
         }
+        assert!(!moved.is_empty(), "the active panes must be in the involved panes");
+
+        let file = self.file.clone();
         let contents = self.contents.clone();
-        assert!(main_cursor_start != usize::MAX);
-        
-        let buf = Self {file, contents, ..*self};
-        let pane = Pane {
-            main_cursor_start,
-            cursors,
-            ..*pane
-        };
-        (buf, vec![pane])
+        let buf = Self {file, contents, ..self.clone()};
+        (buf, moved)
     }
 
     // move_horizontal moves the main cursor horizontally by `offset` graphemes
@@ -188,47 +498,47 @@ impl TextBuffer {
         // assert only 1 buffer is involved (this buffer)
         assert!(h.len() == 1, "Only 1 buffer should be involved. Found: {:?}", h);
 
-        // this can change
-        assert!(active.len() == 1);
-        assert!(panes.len() == 1);
-        let pane = panes.iter().filter(|pane| pane.id == active[0]).nth(0).expect("the active pane must be in the involved panes");
-
-        let file = self.file.clone();
-        let mut cursors = OrdMap::new();
-        let mut main_cursor_start = usize::MAX;
+        let mut moved = vec![];
         let dir = offset / offset.abs();
-        for s in pane.cursors_iter() {
-            let mut start = s.start as i64;
-            for _ in 0..offset.abs() {
-                start = (start + dir).max(0).min(self.contents.byte_len() as i64);
-                while !self.contents.is_grapheme_boundary(start as usize) {
-                    start += dir;
+        for pane in panes.iter().filter(|pane| active.contains(&pane.id)) {
+            let mut cursors = OrdMap::new();
+            let mut main_cursor_start = usize::MAX;
+            for s in pane.cursors_iter() {
+                let mut start = s.start as i64;
+                for _ in 0..offset.abs() {
+                    start = (start + dir).max(0).min(self.contents.byte_len() as i64);
+                    while !self.contents.is_grapheme_boundary(start as usize) {
+                        start += dir;
+                    }
                 }
+                let start = start as usize;
+                if s.start == pane.main_cursor_start {
+                    main_cursor_start = start;
+                }
+                cursors.insert(start, Selection{start, offset: 0 as i64});
             }
-            let start = start as usize;
-            if s.start == pane.main_cursor_start {
-                main_cursor_start = start;
-            }
-            cursors.insert(start, Selection{start, offset: 0 as i64});
+            assert!(main_cursor_start != usize::MAX);
+
+            // optimization: we could try to guess from the offset, but need to know if we change lines
+            let grapheme_col_offset = reset_grapheme_col_offset(&self.contents, main_cursor_start);
+            moved.push(Pane {
+                cursors, 
+                main_cursor_start, 
+                grapheme_col_offset, 
+                ..pane.clone()
+            });
         }
+        assert!(!moved.is_empty(), "the active panes must be in the involved panes");
+
+        let file = self.file.clone();
         let contents = self.contents.clone();
-        assert!(main_cursor_start != usize::MAX);
-        
-        // optimization: we could try to guess from the offset, but need to know if we change lines
-        let grapheme_col_offset = reset_grapheme_col_offset(&contents, main_cursor_start);
-        let buf = Self {file, contents, ..*self};
-        let pane = Pane {
-            cursors, 
-            main_cursor_start, 
-            grapheme_col_offset, 
-            ..*pane
-        };
-        (buf, vec![pane])
+        let buf = Self {file, contents, ..self.clone()};
+        (buf, moved)
     }
 
     // note: this is a little tricky because it can change the number of cursors.
     // imagine: abc|d|e  when you backspace you get: ab|e
-    pub fn backdelete_cursor(&self, panes: Vec<Pane>, active: Vec<PaneId>) -> (Self, Vec<Pane>) {
+    pub fn backdelete_cursor(&self, panes: Vec<Pane>, active: Vec<PaneId>) -> (Self, Vec<Pane>, Vec<Edit>) {
         let mut h = HashSet::new();
         for p in panes.iter() {
             h.insert(p.buffer_id);
@@ -236,54 +546,49 @@ impl TextBuffer {
         // assert only 1 buffer is involved (this buffer)
         assert!(h.len() == 1, "Only 1 buffer should be involved. Found: {:?}", h);
 
-        // this can change
-        assert!(active.len() == 1);
-        assert!(panes.len() == 1);
-        let pane = panes.iter().filter(|pane| pane.id == active[0]).nth(0).expect("the active pane must be in the involved panes");
-
-        let file = self.file.clone();
-
-        let mut deleted_bytes = vec![];
+        let mut edits = vec![];
         let mut contents = self.contents.clone();
-        for cursor in pane.cursors_iter().rev() {
+        // go from the back so deleting doesn't move the cursors we haven't gotten to yet
+        for end in active_cursor_starts(&panes, &active).into_iter().rev() {
             // we don't want to go under 0 so we max with 1 before subtracting
-            let mut start = cursor.start.max(1) - 1;
+            let mut start = end.max(1) - 1;
             while !contents.is_grapheme_boundary(start) {
                 start -= 1;
             }
-            let end = cursor.start;
-            contents.delete(start..end);
-            deleted_bytes.push(end - start);
+            if start != end {
+                let edit = Edit::delete(&contents, start..end);
+                edit.apply(&mut contents);
+                edits.push(edit);
+            }
         }
+        self.finish_edit(contents, edits, panes, &active)
+    }
 
-        // prefix sum (from the back) to get the adjustment
-        for i in (0..(deleted_bytes.len() - 1)).rev() {
-            deleted_bytes[i] += deleted_bytes[i+1];
+    // where an edit can go: in the buffer, and not in the middle of a character
+    pub fn check_range(&self, range: &Range<usize>) -> Result<(), String> {
+        let len = self.contents.byte_len();
+        if range.start > range.end || range.end > len {
+            return Err(format!("{}..{} isn't in {} ({len} bytes)", range.start, range.end, self.name()));
         }
-
-        let mut main_cursor_start = usize::MAX;
-        let mut cursors = OrdMap::new();
-        for (i, s) in pane.cursors_iter().enumerate() {
-            let start = s.start - deleted_bytes[deleted_bytes.len()-1 - i];
-            cursors.insert(start, Selection{start, offset: 0 as i64});
-            if s.start == pane.main_cursor_start {
-                main_cursor_start = start;
-            }
+        if !self.contents.is_grapheme_boundary(range.start) || !self.contents.is_grapheme_boundary(range.end) {
+            return Err(format!("{}..{} splits a character in {}", range.start, range.end, self.name()));
         }
-        assert!(main_cursor_start != usize::MAX);
-        let grapheme_col_offset = reset_grapheme_col_offset(&self.contents, main_cursor_start);
-        let buf = Self {file, contents, ..*self};
-        let pane = Pane {
-            cursors,
-            main_cursor_start, 
-            grapheme_col_offset,
-            ..*pane
-        };
-        (buf, vec![pane])
+        Ok(())
+    }
+
+    // `range` replaced with `text`, from outside of any pane (see `rpc`)
+    pub fn replaced(&self, range: Range<usize>, text: &str) -> Result<(Self, Vec<Edit>), String> {
+        self.check_range(&range)?;
+        let mut contents = self.contents.clone();
+        let edit = Edit::replace(&contents, range, text);
+        edit.apply(&mut contents);
+        let history = self.history.record(&self.contents, std::slice::from_ref(&edit), None, false);
+        let file = self.file.clone().map(|fi| FileInfo {is_modified: true, ..fi});
+        Ok((TextBuffer {file, contents, version: self.version + 1, history, ..self.clone()}, vec![edit]))
     }
 
     // we're assuming text ends on a grapheme boundary
-    pub fn insert(&self, text: &str, panes: Vec<Pane>, active: Vec<PaneId>) -> (Self, Vec<Pane>) {
+    pub fn insert(&self, text: &str, panes: Vec<Pane>, active: Vec<PaneId>) -> (Self, Vec<Pane>, Vec<Edit>) {
         let mut h = HashSet::new();
         for p in panes.iter() {
             h.insert(p.buffer_id);
@@ -291,10 +596,38 @@ impl TextBuffer {
         // assert only 1 buffer is involved (this buffer)
         assert!(h.len() == 1, "Only 1 buffer should be involved. Found: {:?}", h);
 
-        // this can change
-        assert!(active.len() == 1);
-        assert!(panes.len() == 1);
-        let pane = panes.iter().filter(|pane| pane.id == active[0]).nth(0).expect("the active pane must be in the involved panes");
+        let incr = text.len();
+        let mut contents = self.contents.clone();
+        let mut edits = vec![];
+        for (i, start) in active_cursor_starts(&panes, &active).into_iter().enumerate() {
+            let edit = Edit::insert(&contents, start + incr*i, text);
+            edit.apply(&mut contents);
+            edits.push(edit);
+        }
+        self.finish_edit(contents, edits, panes, &active)
+    }
+
+    // Every pane on this buffer gets its cursors and scroll position shifted by the
+    // edits. The active panes (the ones that did the typing) end up after any inserted
+    // text with their selections collapsed, the rest stay where they were in the text.
+    fn finish_edit(&self, contents: Rope, edits: Vec<Edit>, panes: Vec<Pane>, active: &[PaneId]) -> (Self, Vec<Pane>, Vec<Edit>) {
+        if edits.is_empty() {
+            return (self.clone(), panes, edits);
+        }
+
+        let panes = panes.iter().map(|pane| {
+            if !active.contains(&pane.id) {
+                return pane.shift(&edits, false);
+            }
+            let pane = pane.shift(&edits, true);
+            let cursors = pane.cursors_iter().map(|s| (s.start, Selection{start: s.start, offset: 0})).collect();
+            let grapheme_col_offset = reset_grapheme_col_offset(&contents, pane.main_cursor_start);
+            Pane {
+                cursors,
+                grapheme_col_offset,
+                ..pane
+            }
+        }).collect();
 
         let file = if let Some(fi) = &self.file {
             let mut file = fi.clone();
@@ -303,31 +636,107 @@ impl TextBuffer {
         } else {
             None
         };
+        let version = self.version + 1;
+        let buf = Self {file, contents, version, ..self.clone()};
+        (buf, panes, edits)
+    }
+
+    // the buffer with `text` (the file's new contents) instead of what it had. It's
+    // one edit covering just the part that's different, so cursors and observers
+    // outside of it don't notice anything
+    pub fn reloaded(&self, text: &str, file_time: SystemTime, encoding: TextEncoding, line_ending: LineEnding) -> (Self, Vec<Edit>) {
+        let old = self.contents.to_string();
+        let mut prefix = old.bytes().zip(text.bytes()).take_while(|(a, b)| a == b).count();
+        while !old.is_char_boundary(prefix) {
+            prefix -= 1;
+        }
+        let max_suffix = old.len().min(text.len()) - prefix;
+        let mut suffix = old.bytes().rev().zip(text.bytes().rev()).take(max_suffix).take_while(|(a, b)| a == b).count();
+        while !old.is_char_boundary(old.len() - suffix) {
+            suffix -= 1;
+        }
 
-        let incr = text.len();
         let mut contents = self.contents.clone();
-        let mut main_cursor_start = usize::MAX;
-        let mut cursors = OrdMap::new();
-        for (i, s) in pane.cursors_iter().enumerate() {
-            let adjusted_start = s.start + incr*i;
-            contents.insert(adjusted_start, text);
-            let start = adjusted_start + incr;
-            cursors.insert(start, Selection{start, offset: 0});
-            if s.start == pane.main_cursor_start {
-                main_cursor_start = start;
-            }
-        }
-        assert!(main_cursor_start != usize::MAX);
-
-        let grapheme_col_offset = reset_grapheme_col_offset(&contents, main_cursor_start);
-        let buf = Self {file, contents, ..*self};
-        let pane = Pane {
-            cursors,
-            main_cursor_start,
-            grapheme_col_offset,
-            ..*pane
-        };
-        (buf, vec![pane])
+        let mut edits = vec![];
+        if old != text {
+            let edit = Edit::replace(&contents, prefix..old.len() - suffix, &text[prefix..text.len() - suffix]);
+            edit.apply(&mut contents);
+            edits.push(edit);
+        }
+        let file = self.file.as_ref().map(|fi| FileInfo {file_time, is_modified: false, is_new: false, encoding, line_ending, ..fi.clone()});
+        let version = self.version + if edits.is_empty() { 0 } else { 1 };
+        (Self {file, contents, version, ..self.clone()}, edits)
+    }
+
+    // the byte offset of (0 indexed) `line` and `col` (in graphemes), or as near
+    // as the buffer goes
+    pub fn offset_of(&self, line: usize, col: usize) -> usize {
+        let line = line.min(self.contents.line_len());
+        let (graphemes, start) = self.graphemes_from_line(line);
+        start + graphemes.take_while(|g| g != "\n").take(col).map(|g| g.len()).sum::<usize>()
+    }
+
+    // the buffer after an undo (or redo) made `edits`, `history` is from then.
+    // Undoing back to what was saved leaves it unmodified
+    pub fn undone(&self, history: History, edits: &[Edit]) -> Self {
+        let mut contents = self.contents.clone();
+        for edit in edits {
+            edit.apply(&mut contents);
+        }
+        let file = self.file.as_ref().map(|fi| FileInfo {is_modified: !history.is_saved(), ..fi.clone()});
+        Self {file, contents, version: self.version + 1, history, ..self.clone()}
+    }
+
+    // hex buffers (see `hex`): the cursors of the active panes move `nibbles` hex
+    // digits, and only ever sit on one
+    pub fn hex_move(&self, nibbles: i64, panes: Vec<Pane>, active: Vec<PaneId>) -> Vec<Pane> {
+        let len = self.contents.byte_len();
+        let to = |start| hex::move_by(start, nibbles, len).unwrap_or(0);
+        panes.into_iter().filter(|pane| active.contains(&pane.id)).map(|pane| {
+            let cursors = pane.cursors_iter().map(|s| (to(s.start), Selection{start: to(s.start), offset: 0})).collect();
+            let main_cursor_start = to(pane.main_cursor_start);
+            Pane {
+                cursors,
+                main_cursor_start,
+                grapheme_col_offset: reset_grapheme_col_offset(&self.contents, main_cursor_start),
+                ..pane
+            }
+        }).collect()
+    }
+
+    // typing in a hex buffer overwrites the digit under each cursor (and the byte
+    // in the ascii column) and moves on to the next one
+    pub fn hex_overwrite(&self, text: &str, panes: Vec<Pane>, active: Vec<PaneId>) -> Result<(Self, Vec<Pane>, Vec<Edit>), String> {
+        if let Some(c) = text.chars().find(|c| !c.is_ascii_hexdigit()) {
+            return Err(format!("{c:?} isn't a hex digit"));
+        }
+        let len = self.contents.byte_len();
+        if hex::byte_count(len) == 0 {
+            return Err("the file is empty, there's nothing to overwrite".to_string());
+        }
+        let mut contents = self.contents.clone();
+        let mut edits = vec![];
+        // nothing changes length, so the order doesn't matter
+        for start in active_cursor_starts(&panes, &active) {
+            let mut at = hex::move_by(start, 0, len).unwrap();
+            for digit in text.chars() {
+                let nibble = hex::nibble_at(at, len).unwrap();
+                let edit = Edit::replace(&contents, at..at + 1, &digit.to_ascii_lowercase().to_string());
+                edit.apply(&mut contents);
+                edits.push(edit);
+                let first = hex::offset_of_nibble(nibble & !1);
+                let ascii = hex::ascii_of(contents.byte(first), contents.byte(first + 1)).unwrap();
+                let offset = hex::ascii_offset(nibble);
+                let edit = Edit::replace(&contents, offset..offset + 1, &ascii.to_string());
+                edit.apply(&mut contents);
+                edits.push(edit);
+                at = hex::move_by(at, 1, len).unwrap();
+            }
+        }
+        let panes = self.hex_move(text.len() as i64, panes, active);
+        let file = self.file.clone().map(|fi| FileInfo {is_modified: true, ..fi});
+        let version = self.version + 1;
+        Ok((Self {file, contents, version, ..self.clone()}, panes, edits))
     }
 
     pub fn lines(&self) -> crop::iter::Lines {
@@ -335,7 +744,29 @@ impl TextBuffer {
     }
 }
 
-fn reset_grapheme_col_offset(contents: &Rope, start: usize) -> usize {
+// a file's bytes as buffer text (or a hex dump of them), and how to turn it back.
+// `lossy` if saving the text can't give back the bytes (it's read only), `mixed`
+// if it would, but with all the line endings the same (it's modified)
+fn decode(bytes: &[u8], hex: bool) -> (String, TextEncoding, LineEnding, bool, bool) {
+    if hex {
+        return (hex::dump(bytes), TextEncoding::default(), LineEnding::default(), false, false);
+    }
+    let (text, encoding, lossy) = TextEncoding::decode(bytes);
+    let (text, line_ending, mixed) = LineEnding::normalize(text);
+    (text, encoding, line_ending, lossy, mixed)
+}
+
+// the cursors of all the active panes, sorted and without duplicates
+fn active_cursor_starts(panes: &[Pane], active: &[PaneId]) -> Vec<usize> {
+    let starts: BTreeSet<usize> = panes.iter()
+        .filter(|pane| active.contains(&pane.id))
+        .flat_map(|pane| pane.cursors_iter().map(|s| s.start))
+        .collect();
+    assert!(!starts.is_empty(), "the active panes must be in the involved panes");
+    starts.into_iter().collect()
+}
+
+pub fn reset_grapheme_col_offset(contents: &Rope, start: usize) -> usize {
     let line_start = contents.byte_of_line(contents.line_of_byte(start));
     contents.byte_slice(line_start..start).graphemes().count()
 }
@@ -343,6 +774,7 @@ fn reset_grapheme_col_offset(contents: &Rope, start: usize) -> usize {
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::file::TestDir;
     use crate::pane::Mode;
     fn create_buffer(s: &str, cursors: Vec<Selection>) -> (TextBuffer, Vec<Pane>) {
         let start = cursors[0].start;
@@ -356,6 +788,7 @@ mod tests {
             id: 0,
             y_offset: 0.,
             mode: Mode::Normal,
+            ..Pane::new(0, 0)
         }];
         let buffer = TextBuffer {
             file: None, 
@@ -368,7 +801,7 @@ mod tests {
     #[test]
     fn test_text_buffer_insertion() {
         let (buffer, panes) = create_buffer("abcdefghigh", vec![Selection {start: 1, offset: 1}, Selection {start: 5, offset: 1}, Selection {start: 8, offset: 1}]);
-        let (buffer, panes) = buffer.insert("xz", panes, vec![0]);
+        let (buffer, panes, edits) = buffer.insert("xz", panes, vec![0]);
 
         let yee = buffer.contents.chunks().collect::<String>();
 
@@ -377,6 +810,56 @@ mod tests {
         for (a, b) in panes[0].cursors_iter().zip(&[Selection{start: 3, offset: 0}, Selection{start: 9, offset: 0}, Selection{start: 14, offset: 0}]) {
             assert_eq!(a, b);
         }
+
+        // replaying the edits gets the same result
+        assert_eq!(buffer.version, 1);
+        let mut contents = Rope::from("abcdefghigh");
+        for e in edits.iter() {
+            e.apply(&mut contents);
+        }
+        assert_eq!(contents.to_string(), yee);
+        assert_eq!(edits.iter().map(|e| e.start).collect::<Vec<_>>(), vec![1, 7, 12]);
+    }
+
+    #[test]
+    fn test_other_panes_shift() {
+        let (buffer, mut panes) = create_buffer("ab\ncd\nef\ngh", vec![Selection {start: 1, offset: 0}]);
+        let mut other = panes[0].clone();
+        other.id = 1;
+        other.cursors = vec![Selection {start: 0, offset: 1}, Selection {start: 4, offset: 2}].into_iter().map(|s| (s.start, s)).collect();
+        other.main_cursor_start = 4;
+        other.y_offset = 2.5;
+        panes.push(other);
+
+        let (buffer, panes, _) = buffer.insert("x\n", panes, vec![0]);
+        assert_eq!(buffer.contents.to_string(), "ax\nb\ncd\nef\ngh");
+        assert_eq!(panes[0].cursors_iter().collect::<Vec<_>>(), vec![&Selection {start: 3, offset: 0}]);
+        // the selection before the insertion stays put, the one after moves
+        assert_eq!(panes[1].cursors_iter().collect::<Vec<_>>(), vec![&Selection {start: 0, offset: 1}, &Selection {start: 6, offset: 2}]);
+        assert_eq!(panes[1].main_cursor_start, 6);
+        assert_eq!(panes[1].y_offset, 3.5);
+
+        let (buffer, panes, _) = buffer.backdelete_cursor(panes, vec![1]);
+        assert_eq!(buffer.contents.to_string(), "ax\nb\nd\nef\ngh");
+        assert_eq!(panes[0].cursors_iter().collect::<Vec<_>>(), vec![&Selection {start: 3, offset: 0}]);
+        assert_eq!(panes[1].main_cursor_start, 5);
+        assert_eq!(panes[1].y_offset, 3.5);
+    }
+
+    #[test]
+    fn test_is_last_view() {
+        let panes = Registry::new();
+        let a = panes.insert_with(|id| Pane::new(0, id));
+        let b = panes.insert_with(|id| Pane::new(1, id));
+        assert!(panes.is_last_view(a));
+
+        // remembering the buffer counts, it can still go back to it
+        let buffer = TextBuffer::from_blank();
+        panes.store(b, panes.get()[&b].show(0, &buffer).show(1, &buffer));
+        assert!(!panes.is_last_view(a));
+        assert!(panes.is_last_view(b));
+        panes.remove(b);
+        assert!(panes.is_last_view(a));
     }
 
     #[test]
@@ -395,7 +878,7 @@ mod tests {
         let cursors = vec![Selection {start: 1, offset: 0}, Selection {start: 5, offset: 0}, Selection {start: 8, offset: 0}];
         let s = "abcdef\njfkdsalfjads\nkadsjlfla\nalskdjflasd\nasdjkflsda\naghigh";
         let (buffer, panes) = create_buffer(s, cursors);
-        let (buffer, panes) = buffer.backdelete_cursor(panes, vec![0]);
+        let (buffer, panes, edits) = buffer.backdelete_cursor(panes, vec![0]);
 
         let yee = buffer.contents.chunks().collect::<String>();
 
@@ -403,6 +886,8 @@ mod tests {
         for (a, b) in panes[0].cursors_iter().zip(&[Selection{start: 0, offset: 0}, Selection{start: 3, offset: 0}, Selection{start: 5, offset: 0}]) {
             assert_eq!(a, b);
         }
+        assert_eq!(edits.len(), 3);
+        assert_eq!(affected_lines(&edits), 0..2);
     }
 
     #[test]
@@ -465,134 +950,1111 @@ mod tests {
     }
 
     #[test]
-    fn test_file_too_large() {
-        let filename = "/Users/jason/src/benchmarks/fastest-grep/folded.txt";
-        match TextBuffer::from_filename(filename) {
-            Ok(_) => assert!(false),
-            Err(e) => assert_eq!(e.to_string().as_str(), "File was larger than 3GB"),
-        }
+    fn test_open_new_file() {
+        let dir = TestDir::new("new");
+        let path = dir.join("new.txt");
+        let buffer = TextBuffer::open(path.to_str().unwrap()).unwrap();
+        assert!(buffer.file.as_ref().unwrap().is_new);
+        assert!(!buffer.has_unsaved_changes());
+        assert!(!path.exists());
+
+        let buffer = buffer.write(&path).unwrap();
+        assert!(!buffer.file.as_ref().unwrap().is_new);
+        assert!(path.exists());
+    }
+
+    #[test]
+    fn test_round_trip_file_format() {
+        let dir = TestDir::new("crlf");
+        let path = dir.join("crlf.txt");
+        let bytes = b"\xFF\xFEa\0\r\0\n\0b\0\r\0\n\0";
+        std::fs::write(&path, bytes).unwrap();
+        let buffer = TextBuffer::from_filename(path.to_str().unwrap()).unwrap();
+        assert_eq!(buffer.contents.to_string(), "a\nb\n");
+        assert_eq!(buffer.file.as_ref().unwrap().line_ending, LineEnding::Crlf);
+
+        buffer.write(&path).unwrap();
+        assert_eq!(std::fs::read(&path).unwrap(), bytes);
+    }
+
+    #[test]
+    fn test_reloaded() {
+        let (buffer, panes) = create_buffer("héllo\nworld\nbye", vec![Selection {start: 13, offset: 0}]);
+        let (buffer, edits) = buffer.reloaded("héllo\nthere world\nbye", SystemTime::now(), TextEncoding::default(), LineEnding::Lf);
+        assert_eq!(buffer.contents.to_string(), "héllo\nthere world\nbye");
+        // only the part that changed
+        assert_eq!(edits, vec![Edit::insert(&Rope::from("héllo\nworld\nbye"), 7, "there ")]);
+        assert_eq!(buffer.version, 1);
+        assert_eq!(panes[0].shift(&edits, false).main_cursor_start, 19);
+
+        // a change in the middle of a multibyte char still splits on char boundaries
+        let (buffer, edits) = buffer.reloaded("hèllo\nthere world\nbye", SystemTime::now(), TextEncoding::default(), LineEnding::Lf);
+        assert_eq!(buffer.contents.to_string(), "hèllo\nthere world\nbye");
+        assert_eq!((edits[0].start, edits[0].old_end, edits[0].text.as_str()), (1, 3, "è"));
+        let (_, edits) = buffer.reloaded("hèllo\nthere world\nbye", SystemTime::now(), TextEncoding::default(), LineEnding::Lf);
+        assert!(edits.is_empty());
+    }
+
+    #[test]
+    fn test_large_file() {
+        // sparse, so it doesn't take up the space
+        let dir = TestDir::new("large");
+        let path = dir.join("large.log");
+        std::fs::write(&path, "first line\r\nsecond\r\n").unwrap();
+        std::fs::OpenOptions::new().write(true).open(&path).unwrap().set_len(LARGE_FILE + 10).unwrap();
+
+        let buffer = TextBuffer::from_filename(path.to_str().unwrap()).unwrap();
+        assert!(buffer.is_large());
+        assert!(!buffer.is_read_only());
+        // only up to the last line end in the first chunk
+        assert_eq!(buffer.contents.to_string(), "first line\nsecond\n");
+        assert_eq!(buffer.file.as_ref().unwrap().line_ending, LineEnding::Crlf);
+        assert_eq!(buffer.loading, Some(Progress {read: 20, total: LARGE_FILE + 10}));
+    }
+
+    #[test]
+    fn test_can_autosave() {
+        let dir = TestDir::new("autosave");
+        let path = dir.join("a.txt");
+        std::fs::write(&path, "hi").unwrap();
+        let buffer = TextBuffer::from_filename(path.to_str().unwrap()).unwrap();
+        assert!(!buffer.can_autosave());
+        let (buffer, _, _) = buffer.insert("!", create_buffer("hi", vec![Selection {start: 2, offset: 0}]).1, vec![0]);
+        assert!(buffer.can_autosave());
+
+        // someone else saved it since
+        let file_time = SystemTime::now() - Duration::from_secs(60);
+        let conflict = TextBuffer {file: buffer.file.clone().map(|fi| FileInfo {file_time, ..fi}), ..buffer.clone()};
+        assert!(!conflict.can_autosave());
+        assert!(!TextBuffer::new(None, Rope::from("no file")).can_autosave());
+    }
+
+    #[test]
+    fn test_scratch_has_nothing_to_lose() {
+        assert!(TextBuffer::new(None, Rope::from("typed")).has_unsaved_changes());
+        assert!(!TextBuffer::scratch("a diff".to_string()).has_unsaved_changes());
+    }
+
+    #[test]
+    fn test_compressed_file() {
+        let dir = TestDir::new("compressed");
+        let path = dir.join("a.json.gz");
+        std::fs::write(&path, Compression::Gzip.compress(b"{\"a\": 1}\r\n").unwrap()).unwrap();
+        let buffer = TextBuffer::from_filename(path.to_str().unwrap()).unwrap();
+        assert_eq!(buffer.contents.to_string(), "{\"a\": 1}\n");
+        assert_eq!(buffer.file.as_ref().unwrap().compression, Some(Compression::Gzip));
+
+        let buffer = TextBuffer {contents: Rope::from("{\"a\": 2}\n"), ..buffer};
+        buffer.write(&path).unwrap();
+        let (bytes, compression) = compress::read(&path).unwrap();
+        assert_eq!((bytes.as_slice(), compression), (&b"{\"a\": 2}\r\n"[..], Some(Compression::Gzip)));
+    }
+
+    #[test]
+    fn test_hex_overwrite() {
+        let dir = TestDir::new("hex");
+        let path = dir.join("a.bin");
+        let bytes: Vec<u8> = (0..20).collect();
+        std::fs::write(&path, &bytes).unwrap();
+        let buffer = TextBuffer::from_filename(path.to_str().unwrap()).unwrap();
+        assert!(buffer.is_hex());
+        let panes = create_buffer("", vec![Selection {start: 0, offset: 0}]).1;
+
+        // the cursor starts in the offset column, so it snaps to the first digit
+        let (buffer, panes, edits) = buffer.hex_overwrite("4", panes, vec![0]).unwrap();
+        assert_eq!(edits.len(), 2);
+        let (buffer, panes, _) = buffer.hex_overwrite("1", panes, vec![0]).unwrap();
+        assert_eq!(panes[0].main_cursor_start, hex::offset_of_nibble(2));
+        assert!(buffer.contents.to_string().starts_with("00000000: 41 01 02"));
+        assert!(buffer.contents.to_string().contains("  A..."));
+        assert!(buffer.hex_overwrite("g", panes.clone(), vec![0]).is_err());
+        // going back a line from the first stops at the start
+        let panes = buffer.hex_move(-(hex::NIBBLES_PER_LINE as i64), panes, vec![0]);
+        assert_eq!(panes[0].main_cursor_start, hex::offset_of_nibble(0));
+
+        buffer.write(&path).unwrap();
+        let mut expected = bytes;
+        expected[0] = 0x41;
+        assert_eq!(std::fs::read(&path).unwrap(), expected);
     }
 }
 
-pub struct SyncList<T> {
-    list: ArcSwap<Vec<T>>,
+impl Registry<Pane> {
+    // `f` gets the panes on the buffer and gives back the ones it changed. It's all
+    // one compare-and-swap, so if the UI thread changes one of them in the meantime
+    // (scrolls, changes mode) `f` is run again on that instead of undoing it
+    fn update_involved<R>(&self, buf_id: BufferId, mut f: impl FnMut(Vec<Pane>) -> (Vec<Pane>, R)) -> R {
+        self.update(|map| {
+            let involved = map.values().filter(|pane| pane.buffer_id == buf_id).cloned().collect();
+            let (new_panes, r) = f(involved);
+            let mut map = map.clone();
+            for pane in new_panes {
+                map.insert(pane.id, pane);
+            }
+            (map, r)
+        })
+    }
+
+    // whether `pane_id` is the only pane showing (or remembering) its buffer,
+    // so closing it closes the buffer too
+    pub fn is_last_view(&self, pane_id: PaneId) -> bool {
+        let panes = self.get();
+        let buf_id = panes[&pane_id].buffer_id;
+        !panes.values().any(|p| p.id != pane_id && (p.buffer_id == buf_id || p.views.contains_key(&buf_id)))
+    }
+
+    // panes that aren't showing the buffer but remember a place in it
+    fn shift_hidden(&self, buf_id: BufferId, edits: &[Edit]) {
+        self.update(|map| {
+            let hidden = map.values().filter(|pane| pane.buffer_id != buf_id && pane.views.contains_key(&buf_id));
+            let shifted = hidden.map(|pane| (pane.id, pane.shift_hidden(buf_id, edits)));
+            // `union` keeps the left side's value when both have the key
+            (shifted.collect::<OrdMap<_, _>>().union(map.clone()), ())
+        });
+    }
 }
 
-impl<T> SyncList<T> where T: Clone {
-    pub fn new() -> Self {
-        Self {
-            list: ArcSwap::new(Arc::new(vec![])),
-        }
+// how the buffer thread gets things to the UI thread. Without an event loop
+// (in tests) nobody's woken up, the events just queue
+#[derive(Clone)]
+pub struct Ui {
+    render_tx: mpsc::Sender<CustomEvent>,
+    event_loop_proxy: Option<EventLoopProxy>,
+}
+
+impl Ui {
+    pub fn new(render_tx: mpsc::Sender<CustomEvent>, event_loop_proxy: EventLoopProxy) -> Self {
+        Self {render_tx, event_loop_proxy: Some(event_loop_proxy)}
     }
 
-    pub fn store(&self, index: usize, buffer: T) {
-        let mut list: Vec<T> = (**self.list.load()).to_owned();
-        if index == list.len() {
-            list.push(buffer);
-        } else {
-            list[index] = buffer;
+    pub fn headless(render_tx: mpsc::Sender<CustomEvent>) -> Self {
+        Self {render_tx, event_loop_proxy: None}
+    }
+
+    pub fn send(&self, event: CustomEvent) {
+        if let Err(e) = self.render_tx.send(event) {
+            log::error!("failed to send to the UI thread: {}", e);
+        } else if let Some(event_loop_proxy) = &self.event_loop_proxy {
+            event_loop_proxy.wake_up();
         }
-        self.list.store(Arc::new(list));
     }
 
-    pub fn get(&self) -> arc_swap::Guard<Arc<Vec<T>>> {
-        self.list.load()
+    fn message(&self, msg: String) {
+        self.send(CustomEvent::Message(msg));
     }
 
-    pub fn len(&self) -> usize {
-        self.get().len()
+    fn prompt(&self, prompt: Prompt) {
+        self.send(CustomEvent::Prompt(prompt));
     }
-}
 
-impl SyncList<Pane> {
-    fn involved_panes(&self, buf_id: BufferId) -> Vec<Pane> {
-        self.get().iter().filter(|pane| pane.buffer_id == buf_id).cloned().collect()
+    fn redraw(&self, buf_id: BufferId) {
+        self.send(CustomEvent::BufferRequestedRedraw(buf_id));
     }
+}
+
+// what the buffer thread shares with everything else. `buffer_tx` is the
+// sending side of its `buffer_rx`, for the threads it starts
+#[derive(Clone)]
+pub struct Context {
+    pub buffers: Arc<Registry<TextBuffer>>,
+    pub panes: Arc<Registry<Pane>>,
+    pub observers: Arc<Observers>,
+    pub buffer_tx: mpsc::Sender<BufferMsg>,
+    pub ui: Ui,
+}
 
-    fn store_all(&self, new_panes: Vec<Pane>) {
-        for pane in new_panes {
-            self.store(pane.id, pane);
+// what the buffer thread keeps between messages
+struct State {
+    watcher: FileWatcher,
+    // what needs its buffer to have finished loading (`RestoreView`, `GoTo`, ...)
+    waiting: HashMap<BufferId, Vec<BufferMsg>>,
+    last_redraw: Instant,
+}
+
+pub fn buffer_op_handler(cx: Context, buffer_rx: mpsc::Receiver<BufferMsg>) -> impl FnOnce() {
+    move || {
+        let Context {buffers, buffer_tx, ui, ..} = &cx;
+        let mut state = State {watcher: FileWatcher::new(buffer_tx.clone()), waiting: HashMap::new(), last_redraw: Instant::now()};
+        state.watcher.sync(&buffers.get());
+        for (id, buffer) in buffers.get().iter() {
+            start_loading(buffers, buffer_tx, *id, buffer);
+            // the rest are offered once they're loaded
+            if buffer.loading.is_none() {
+                offer_recovery(buffers, ui, *id);
+            }
+        }
+        while let Ok(msg) = buffer_rx.recv() {
+            match msg {
+                BufferMsg::Op(buf_op, active_panes) => pane_op(&cx, &mut state, buf_op, active_panes),
+                BufferMsg::Load(event) => load_event(&cx, &mut state, event),
+                BufferMsg::FileChanged(path) => file_changed(&cx, &path),
+                BufferMsg::Autosave => autosave(&cx),
+                BufferMsg::SetReadOnly(id) => set_read_only(&cx, &mut state, id),
+                BufferMsg::StartLoading(id) => load_new(&cx, &mut state, id),
+                BufferMsg::Ack(ack) => ack.done(),
+                BufferMsg::Exit => exit(&cx),
+            }
         }
     }
 }
 
-pub fn buffer_op_handler(buffer_rx: mpsc::Receiver<(BufferOp, Vec<PaneId>)>, buffers: Arc<SyncList<TextBuffer>>, panes: Arc<SyncList<Pane>>, render_tx: mpsc::Sender<CustomEvent>, event_loop_proxy: EventLoopProxy) -> impl FnOnce() {
-    move || {
-        while let Ok((buf_op, active_panes)) = buffer_rx.recv() {
+fn pane_op(cx: &Context, state: &mut State, buf_op: BufferOp, active_panes: Vec<PaneId>) {
+    let Context {buffers, panes, observers, buffer_tx, ui} = cx;
+    if let BufferOp::GoTo { buffer: id, .. } = buf_op {
+        if is_loading(buffers, id) {
+            state.waiting.entry(id).or_default().push(BufferMsg::Op(buf_op, active_panes));
+            return;
+        }
+    }
+    let Some(&pane_id) = active_panes.first() else {
+        log::warn!("ignoring {buf_op:?}, it wasn't sent to a pane");
+        return;
+    };
+    // it can be closed by the time its op gets here: a prompt answered late,
+    // a `SaveThen`, an op that waited for its file to load, or `rpc`'s.
+    // Only this thread removes panes, so it's there for the rest of the op
+    let Some(buf_id) = panes.get().get(&pane_id).map(|pane| pane.buffer_id) else {
+        return;
+    };
+    // a large file's rest is still being appended, so it can't be edited until it's all in
+    let buffer = buffers.get()[&buf_id].clone();
+    if matches!(buf_op, BufferOp::Insert(_) | BufferOp::Delete | BufferOp::Undo | BufferOp::Redo | BufferOp::RestoreVersion(_) | BufferOp::Replace { .. }) && (buffer.is_read_only() || buffer.loading.is_some()) {
+        let why = if buffer.loading.is_some() { "is still loading" } else { "is read only" };
+        let msg = format!("{} {why}", buffer.name());
+        match &buf_op {
+            BufferOp::Replace { ack, .. } => ack.failed(msg),
+            _ => ui.message(msg),
+        }
+        return;
+    }
+    let is_hex = buffer.is_hex();
+    match buf_op {
+        // backspace just goes back a digit, bytes can only be overwritten
+        BufferOp::Delete if is_hex => {
+            let buffer = &buffers.get()[&buf_id];
+            panes.update_involved(buf_id, |involved_panes| (buffer.hex_move(-1, involved_panes, active_panes.clone()), ()));
+        },
+        BufferOp::Delete => {
+            let buffer = &buffers.get()[&buf_id];
+            let before = Cursors::of(&panes.get()[&pane_id]);
+            let (new_buffer, edits) = panes.update_involved(buf_id, |involved_panes| {
+                let (new_buffer, new_panes, edits) = buffer.backdelete_cursor(involved_panes, active_panes.clone());
+                (new_panes, (new_buffer, edits))
+            });
+            let cursors = Some((before, Cursors::of(&panes.get()[&pane_id])));
+            let new_buffer = TextBuffer {history: buffer.history.record(&buffer.contents, &edits, cursors, true), ..new_buffer};
+            panes.shift_hidden(buf_id, &edits);
+            buffers.store(buf_id, new_buffer.clone());
+            notify_edits(observers, buf_id, &new_buffer, edits);
+        },
+        BufferOp::Insert(s) if is_hex => {
+            let buffer = &buffers.get()[&buf_id];
+            let before = Cursors::of(&panes.get()[&pane_id]);
+            let result = panes.update_involved(buf_id, |involved_panes| {
+                match buffer.hex_overwrite(&s, involved_panes, active_panes.clone()) {
+                    Ok((new_buffer, new_panes, edits)) => (new_panes, Ok((new_buffer, edits))),
+                    Err(msg) => (vec![], Err(msg)),
+                }
+            });
+            match result {
+                Ok((new_buffer, edits)) => {
+                    let cursors = Some((before, Cursors::of(&panes.get()[&pane_id])));
+                    let new_buffer = TextBuffer {history: buffer.history.record(&buffer.contents, &edits, cursors, true), ..new_buffer};
+                    buffers.store(buf_id, new_buffer.clone());
+                    notify_edits(observers, buf_id, &new_buffer, edits);
+                },
+                Err(msg) => ui.message(msg),
+            }
+        },
+        BufferOp::Insert(s) => {
+            // the buffer only has `\n`, whatever the file uses
+            let s = s.replace("\r\n", "\n").replace('\r', "\n");
+            let buffer = &buffers.get()[&buf_id];
+            let before = Cursors::of(&panes.get()[&pane_id]);
+            let (new_buffer, edits) = panes.update_involved(buf_id, |involved_panes| {
+                let (new_buffer, new_panes, edits) = buffer.insert(&s, involved_panes, active_panes.clone());
+                (new_panes, (new_buffer, edits))
+            });
+            let cursors = Some((before, Cursors::of(&panes.get()[&pane_id])));
+            let new_buffer = TextBuffer {history: buffer.history.record(&buffer.contents, &edits, cursors, true), ..new_buffer};
+            panes.shift_hidden(buf_id, &edits);
+            buffers.store(buf_id, new_buffer.clone());
+            notify_edits(observers, buf_id, &new_buffer, edits);
+        },
+        BufferOp::MoveHorizontal(n) | BufferOp::MoveVertical(n) if is_hex => {
+            let nibbles = if matches!(buf_op, BufferOp::MoveVertical(_)) { n * hex::NIBBLES_PER_LINE as i64 } else { n };
+            let buffer = &buffers.get()[&buf_id];
+            panes.update_involved(buf_id, |involved_panes| (buffer.hex_move(nibbles, involved_panes, active_panes.clone()), ()));
+        },
+        BufferOp::MoveHorizontal(n) => {
+            let buffer = &buffers.get()[&buf_id];
+            panes.update_involved(buf_id, |involved_panes| {
+                let (_, new_panes) = buffer.move_horizontal(n, involved_panes, active_panes.clone());
+                (new_panes, ())
+            });
+        },
+        BufferOp::MoveVertical(n) => {
+            let buffer = &buffers.get()[&buf_id];
+            panes.update_involved(buf_id, |involved_panes| {
+                let (_, new_panes) = buffer.move_vertical(n, involved_panes, active_panes.clone());
+                (new_panes, ())
+            });
+        },
+        BufferOp::Save => {
+            save_file(cx, buf_id);
+        },
+        BufferOp::SaveThen(ops) => {
+            // a failed save has said why already, and nothing's run
+            if save_file(cx, buf_id) {
+                ui.send(CustomEvent::RunOps(pane_id, ops, None));
+            }
+        },
+        BufferOp::SaveAs { path, overwrite, create_dirs } => {
+            save_as(cx, buf_id, path, overwrite, create_dirs);
+        },
+        BufferOp::SetMainCursor(i) => { // if mouse is clicked for ex
+            assert!(active_panes.len() == 1);
+            let buffer = &buffers.get()[&buf_id];
+            let i = if is_hex { hex::move_by(i, 0, buffer.contents.byte_len()).unwrap_or(0) } else { i };
+            panes.modify(pane_id, |pane| {
+                let mut cursors = pane.cursors.clone();
+                let key = &pane.main_cursor_start;
+                cursors.remove(key);
+                cursors.insert(i, Selection{start: i, offset: 0});
+                (Pane {
+                    main_cursor_start: i,
+                    cursors,
+                    grapheme_col_offset: reset_grapheme_col_offset(&buffer.contents, i),
+                    ..pane.clone()
+                }, ())
+            });
+        },
+        BufferOp::AddCursor(start) => {
             assert!(active_panes.len() == 1);
-            let buf_id = panes.get()[active_panes[0]].buffer_id;
-            match buf_op {
-                BufferOp::Delete => {
-                    let involved_panes = panes.involved_panes(buf_id);
-                    let buffer = &buffers.get()[buf_id];
-                    let (new_buffer, new_panes) = buffer.backdelete_cursor(involved_panes, active_panes);
-                    panes.store_all(new_panes);
-                    buffers.store(buf_id, new_buffer);
+            let start = if is_hex { hex::move_by(start, 0, buffers.get()[&buf_id].contents.byte_len()).unwrap_or(0) } else { start };
+            panes.modify(pane_id, |pane| {
+                let mut cursors = pane.cursors.clone();
+                cursors.insert(start, Selection{start, offset: 0});
+                (Pane {
+                    cursors,
+                    ..pane.clone()
+                }, ())
+            });
+        },
+        BufferOp::Open(path) => {
+            assert!(active_panes.len() == 1);
+            // reuse the buffer if it's already open
+            let open = buffers.get().iter().find(|(_, b)| {
+                b.file.as_ref().is_some_and(|fi| *fi.filename == *Path::new(&path))
+            }).map(|(id, _)| *id);
+            let id = match open {
+                Some(id) => Some(id),
+                None => match TextBuffer::pending(&path) {
+                    Ok(buffer) => {
+                        let id = buffers.insert_with(|_| buffer.clone());
+                        start_loading(buffers, buffer_tx, id, &buffer);
+                        if buffer.loading.is_none() {
+                            offer_recovery(buffers, ui, id);
+                        }
+                        Some(id)
+                    },
+                    Err(e) => {
+                        ui.message(format!("can't open {path}: {e}"));
+                        None
+                    },
                 },
-                BufferOp::Insert(s) => {
-                    let buffer = &buffers.get()[buf_id];
-                    let involved_panes = panes.involved_panes(buf_id);
-                    let (new_buffer, new_panes) = buffer.insert(&s, involved_panes, active_panes);
-                    panes.store_all(new_panes);
-                    buffers.store(buf_id, new_buffer);
+            };
+            if let Some(id) = id {
+                let buffer = &buffers.get()[&id];
+                panes.modify(pane_id, |pane| (pane.show(id, buffer), ()));
+            }
+        },
+        BufferOp::SwitchBuffer(to) => {
+            assert!(active_panes.len() == 1);
+            let pane = &panes.get()[&pane_id];
+            let all = buffers.get();
+            // ids have gaps once buffers are closed, so wrap around what's there
+            let id = match to {
+                SwitchTo::Next => Ok(all.range(buf_id + 1..).next().or(all.iter().next()).map(|(id, _)| *id).unwrap()),
+                SwitchTo::Prev => Ok(all.range(..buf_id).next_back().or(all.iter().next_back()).map(|(id, _)| *id).unwrap()),
+                SwitchTo::Alternate => pane.alternate.ok_or("no alternate buffer".to_string()),
+                SwitchTo::Id(id) if all.contains_key(&id) => Ok(id),
+                SwitchTo::Id(id) => Err(format!("buffer {id} does not exist")),
+            };
+            match id {
+                Ok(id) => {
+                    let buffer = &all[&id];
+                    panes.modify(pane_id, |pane| (pane.show(id, buffer), ()));
                 },
-                BufferOp::MoveHorizontal(n) => {
-                    let buffer = &buffers.get()[buf_id];
-                    let involved_panes = panes.involved_panes(buf_id);
-                    let (new_buffer, new_panes) = buffer.move_horizontal(n, involved_panes, active_panes);
-                    panes.store_all(new_panes);
-                    buffers.store(buf_id, new_buffer);
+                Err(msg) => ui.message(msg),
+            }
+        },
+        BufferOp::ClosePane { discard } => {
+            let last = panes.is_last_view(pane_id);
+            panes.remove(pane_id);
+            if last {
+                let buffer = &buffers.get()[&buf_id];
+                let unsaved = buffer.has_unsaved_changes();
+                if !discard && unsaved {
+                    // probably the save before this failed
+                    ui.message(format!("{} has unsaved changes, it's still open as buffer {buf_id}", buffer.name()));
+                } else {
+                    buffers.remove(buf_id);
+                    observers.notify(BufferEvent::Closed {buffer_id: buf_id, discarded: unsaved});
+                }
+            }
+            state.watcher.sync(&buffers.get());
+            // the UI already stopped drawing it
+            return;
+        },
+        BufferOp::Reload(id) => {
+            if buffers.get().contains_key(&id) {
+                if let Err(msg) = reload(buffers, panes, observers, id) {
+                    ui.message(msg);
+                }
+                ui.redraw(id);
+            }
+        },
+        BufferOp::KeepLocal(id) => {
+            let all = buffers.get();
+            if let Some(fi) = all.get(&id).and_then(|b| b.file.as_ref()) {
+                // as if we'd read what's there now, so saving doesn't ask again
+                let file_time = fi.disk_time().unwrap_or(fi.file_time);
+                let file = Some(FileInfo {file_time, ..fi.clone()});
+                buffers.store(id, TextBuffer {file, ..all[&id].clone()});
+            }
+        },
+        BufferOp::DiffDisk(id) => {
+            let all = buffers.get();
+            let Some(buffer) = all.get(&id) else {
+                return;
+            };
+            let Some(fi) = &buffer.file else {
+                return;
+            };
+            if fi.large {
+                ui.message(format!("{} is too big to diff", buffer.name()));
+                return;
+            }
+            match compress::read(&fi.filename) {
+                Ok((bytes, _)) => {
+                    let (disk, ..) = decode(&bytes, fi.hex);
+                    let name = buffer.name();
+                    let diff = diff::unified(&format!("{name} (on disk)"), &disk, &format!("{name} (buffer)"), &buffer.contents.to_string(), 3);
+                    let diff_id = buffers.insert_with(|_| TextBuffer::scratch(diff));
+                    let diff_buffer = &buffers.get()[&diff_id];
+                    panes.modify(pane_id, |pane| (pane.show(diff_id, diff_buffer), ()));
+                    // it still needs an answer
+                    ui.prompt(changed_prompt(id, &name));
                 },
-                BufferOp::MoveVertical(n) => {
-                    let buffer = buffers.get()[buf_id].clone();
-                    let involved_panes = panes.involved_panes(buf_id);
-                    let (new_buffer, new_panes) = buffer.move_vertical(n, involved_panes, active_panes);
-                    panes.store_all(new_panes);
-                    buffers.store(buf_id, new_buffer);
+                Err(e) => ui.message(format!("couldn't read {name}: {e}", name = buffer.name())),
+            }
+        },
+        BufferOp::SetEncoding(label) => {
+            let buffer = &buffers.get()[&buf_id];
+            match (&buffer.file, TextEncoding::for_label(&label)) {
+                (None, _) => ui.message("no file to set the encoding of".to_string()),
+                (_, None) => ui.message(format!("unknown encoding: {label}")),
+                (Some(fi), Some(encoding)) => {
+                    // keep the BOM if the new one can have it too
+                    let encoding = TextEncoding {bom: fi.encoding.bom && encoding.can_have_bom(), ..encoding};
+                    set_format(buffers, buf_id, fi, FileInfo {encoding, ..fi.clone()});
                 },
-                BufferOp::Save => {
-                    println!("saving");
-                    let buffer = buffers.get()[buf_id].clone();
-                    let filepath = &buffer.file.as_ref().unwrap().filename;
-                    match buffer.write(filepath) {
-                        Err(e) => log::error!("tried to save buffer, but {}", e),
-                        Ok(b) => buffers.store(buf_id, b),
+            }
+        },
+        BufferOp::SetBom(bom) => {
+            let buffer = &buffers.get()[&buf_id];
+            match &buffer.file {
+                Some(fi) if !bom || fi.encoding.can_have_bom() => set_format(buffers, buf_id, fi, FileInfo {encoding: TextEncoding {bom, ..fi.encoding}, ..fi.clone()}),
+                Some(fi) => ui.message(format!("{} doesn't have a byte order mark", fi.encoding.name())),
+                None => ui.message("no file to set the byte order mark of".to_string()),
+            }
+        },
+        BufferOp::SetLineEnding(line_ending) => {
+            let buffer = &buffers.get()[&buf_id];
+            match &buffer.file {
+                Some(fi) => set_format(buffers, buf_id, fi, FileInfo {line_ending, ..fi.clone()}),
+                None => ui.message("no file to set the line endings of".to_string()),
+            }
+        },
+        BufferOp::SetHex(on) => {
+            if let Err(msg) = set_hex(buffers, panes, observers, buf_id, on) {
+                ui.message(msg);
+            }
+        },
+        BufferOp::Undo | BufferOp::Redo => {
+            let undo = buf_op == BufferOp::Undo;
+            let buffer = &buffers.get()[&buf_id];
+            let changed = if undo { buffer.history.undo(&buffer.contents) } else { buffer.history.redo(&buffer.contents) };
+            let Some((history, edits, cursors)) = changed else {
+                ui.message(format!("nothing to {}", if undo { "undo" } else { "redo" }));
+                return;
+            };
+            let new_buffer = buffer.undone(history, &edits);
+            let contents = new_buffer.contents.clone();
+            store_edited(buffers, panes, observers, buf_id, new_buffer, edits);
+            // back where they were when it was done
+            if let Some(cursors) = cursors {
+                panes.modify(pane_id, |pane| (Pane {y_offset: pane.y_offset, ..pane.restore(&cursors.selections(), cursors.main, 0, &contents)}, ()));
+            }
+        },
+        BufferOp::ListVersions => {
+            let buffer = &buffers.get()[&buf_id];
+            let msg = match (versions::dir(), &buffer.file) {
+                (Some(dir), Some(fi)) => {
+                    let all = versions::list(&dir, &fi.filename);
+                    let shown: Vec<String> = all.iter().take(10).enumerate().map(|(i, v)| format!("{} {}", i + 1, versions::describe(v.time))).collect();
+                    match all.len() {
+                        0 => format!("{} hasn't been saved yet", buffer.name()),
+                        n if n > shown.len() => format!("{}  (and {} older)", shown.join("  "), n - shown.len()),
+                        _ => shown.join("  "),
                     }
                 },
-                BufferOp::SetMainCursor(i) => { // if mouse is clicked for ex
-                    assert!(active_panes.len() == 1);
-                    let pane = &panes.get()[active_panes[0]];
-                    let mut cursors = pane.cursors.clone();
-                    let key = &pane.main_cursor_start;
-                    cursors.remove(key);
-                    cursors.insert(i, Selection{start: i, offset: 0});
-                    let buffer = buffers.get()[buf_id].clone();
-                    panes.store(pane.id, Pane {
-                        main_cursor_start: i,
-                        cursors,
-                        grapheme_col_offset: reset_grapheme_col_offset(&buffer.contents, i),
-                        ..*pane
-                    });
+                _ => format!("{} has no saved versions", buffer.name()),
+            };
+            ui.message(msg);
+        },
+        BufferOp::DiffVersion(n) => {
+            let buffer = &buffers.get()[&buf_id];
+            match saved_version(buffer, n) {
+                Ok((version, bytes)) => {
+                    // the way it'd be read from the file
+                    let (text, ..) = decode(&bytes, buffer.is_hex());
+                    let name = buffer.name();
+                    let old_name = format!("{name} (saved {})", versions::describe(version.time));
+                    let diff = diff::unified(&old_name, &text, &format!("{name} (buffer)"), &buffer.contents.to_string(), 3);
+                    let diff_id = buffers.insert_with(|_| TextBuffer::scratch(diff));
+                    let diff_buffer = &buffers.get()[&diff_id];
+                    panes.modify(pane_id, |pane| (pane.show(diff_id, diff_buffer), ()));
+                },
+                Err(msg) => ui.message(msg),
+            }
+        },
+        BufferOp::RestoreVersion(n) => {
+            let buffer = &buffers.get()[&buf_id];
+            match saved_version(buffer, n) {
+                Ok((_, bytes)) => {
+                    let fi = buffer.file.as_ref().unwrap();
+                    // and how it was encoded, so saving it writes it back the same
+                    let (text, encoding, line_ending, ..) = decode(&bytes, fi.hex);
+                    let (new_buffer, edits) = buffer.reloaded(&text, fi.file_time, encoding, line_ending);
+                    // the file still has what it had
+                    let history = buffer.history.record(&buffer.contents, &edits, None, false);
+                    let file = Some(FileInfo {is_modified: !history.is_saved(), ..fi.clone()});
+                    store_edited(buffers, panes, observers, buf_id, TextBuffer {file, history, ..new_buffer}, edits);
                 },
-                BufferOp::AddCursor(start) => {
-                    assert!(active_panes.len() == 1);
-                    let pane = &panes.get()[active_panes[0]];
-                    let mut cursors = pane.cursors.clone();
-                    cursors.insert(start, Selection{start, offset: 0});
-                    panes.store(pane.id, Pane {
-                        cursors,
-                        ..*pane
-                    });
+                Err(msg) => ui.message(msg),
+            }
+        },
+        BufferOp::Replace { ack, .. } if is_hex => {
+            ack.failed(format!("{} is a hex dump, it can't be edited as text", buffers.get()[&buf_id].name()));
+        },
+        BufferOp::Replace { start, end, text, ack } => {
+            // the buffer only has `\n`, whatever the file uses
+            let text = text.replace("\r\n", "\n").replace('\r', "\n");
+            match buffers.get()[&buf_id].replaced(start..end, &text) {
+                Ok((new_buffer, edits)) => {
+                    store_edited(buffers, panes, observers, buf_id, new_buffer, edits);
+                    ack.done();
                 },
-                BufferOp::Exit => {
-                }
+                Err(msg) => ack.failed(msg),
             }
-            // TODO: sketchy, we should tell the renderer which buffer to redraw
-            if let Err(e) = render_tx.send(CustomEvent::BufferRequestedRedraw(buf_id)) {
-                log::error!("failed to send redraw event: {}", e);
+        },
+        BufferOp::SetCursors(cursors) => {
+            let contents = buffers.get()[&buf_id].contents.clone();
+            let main = cursors.first().map_or(0, |s| s.start);
+            panes.modify(pane_id, |pane| (Pane {y_offset: pane.y_offset, ..pane.restore(&cursors, main, 0, &contents)}, ()));
+        },
+        BufferOp::GoTo { buffer: id, line, col } => {
+            let Some(buffer) = buffers.get().get(&id).cloned() else {
+                return;
+            };
+            let offset = buffer.offset_of(line, col);
+            let cursor = Selection {start: offset, offset: 0};
+            if id == buf_id {
+                panes.modify(pane_id, |pane| (pane.restore(&[cursor], offset, line, &buffer.contents), ()));
             } else {
-                event_loop_proxy.wake_up();
+                let view = View {cursors: OrdMap::unit(offset, cursor), main_cursor_start: offset, y_offset: line.min(buffer.contents.line_len()) as f32};
+                panes.modify(pane_id, |pane| (pane.with_hidden_view(id, view.clone()), ()));
+            }
+        },
+        BufferOp::RestoreView { cursors, main_cursor_start, top_line } => {
+            let buffer = &buffers.get()[&buf_id];
+            if buffer.loading.is_some() {
+                state.waiting.entry(buf_id).or_default().push(BufferMsg::Op(BufferOp::RestoreView { cursors, main_cursor_start, top_line }, vec![pane_id]));
+                return;
+            }
+            panes.modify(pane_id, |pane| (pane.restore(&cursors, main_cursor_start, top_line, &buffer.contents), ()));
+        },
+        BufferOp::CancelLoad => {
+            let buffer = &buffers.get()[&buf_id];
+            if buffer.loading.is_some() {
+                // the loader stops when it sees it's no longer loading
+                let file = buffer.file.clone().map(|fi| FileInfo {read_only: true, ..fi});
+                buffers.store(buf_id, TextBuffer {file, loading: None, ..buffer.clone()});
+                ui.message(format!("stopped loading {}, it's read only", buffer.name()));
+            }
+        },
+        BufferOp::Recover(id) => {
+            if buffers.get().contains_key(&id) {
+                if let Err(msg) = recover(buffers, panes, observers, id) {
+                    ui.message(msg);
+                }
+                ui.redraw(id);
+            }
+        },
+        BufferOp::DiffRecovery(id) => {
+            let all = buffers.get();
+            let Some(buffer) = all.get(&id) else {
+                return;
+            };
+            let name = buffer.name();
+            match orphan(buffer).map(|path| journal::recover(&path)) {
+                Some(Ok(recovered)) => {
+                    let diff = diff::unified(&format!("{name} (buffer)"), &buffer.contents.to_string(), &format!("{name} (recovered)"), &recovered, 3);
+                    let diff_id = buffers.insert_with(|_| TextBuffer::scratch(diff));
+                    let diff_buffer = &buffers.get()[&diff_id];
+                    panes.modify(pane_id, |pane| (pane.show(diff_id, diff_buffer), ()));
+                    // it still needs an answer
+                    ui.prompt(recovery_prompt(id, &name));
+                },
+                Some(Err(e)) => ui.message(format!("couldn't read the journal of {name}: {e}")),
+                None => ui.message(format!("{name} has nothing to recover")),
+            }
+        },
+        BufferOp::DiscardRecovery(id) => {
+            if let Some(path) = buffers.get().get(&id).and_then(orphan) {
+                if let Err(e) = std::fs::remove_file(&path) {
+                    ui.message(format!("couldn't delete {}: {e}", path.display()));
+                }
+            }
+        },
+    }
+    state.watcher.sync(&buffers.get());
+    // the pane might be showing a different buffer now
+    if let Some(pane) = panes.get().get(&pane_id) {
+        // TODO: sketchy, we should tell the renderer which buffer to redraw
+        ui.redraw(pane.buffer_id);
+    }
+}
+
+fn load_event(cx: &Context, state: &mut State, event: LoadEvent) {
+    let Context {buffers, observers, buffer_tx, ui, ..} = cx;
+    match event {
+        LoadEvent::Chunk { buffer: id, text, len, lossy, mixed } => {
+            let Some(buffer) = buffers.get().get(&id).cloned() else {
+                return;
+            };
+            if mixed && buffer.file.as_ref().is_some_and(|fi| !fi.is_modified) {
+                ui.message(mixed_message(&buffer));
+            }
+            // appended, so no cursors move
+            let mut contents = buffer.contents.clone();
+            contents.insert(contents.byte_len(), &text);
+            let loading = buffer.loading.map(|p| Progress {read: p.read + len, ..p});
+            let file = buffer.file.map(|fi| FileInfo {read_only: fi.read_only || lossy, is_modified: fi.is_modified || mixed, ..fi});
+            buffers.store(id, TextBuffer {file, contents, version: buffer.version + 1, loading, ..buffer});
+            // the status line shows the progress, but that's no reason to draw every chunk
+            if state.last_redraw.elapsed() > Duration::from_millis(100) {
+                state.last_redraw = Instant::now();
+                ui.redraw(id);
+            }
+        },
+        LoadEvent::Loaded { buffer: id, loaded } => {
+            // it might have been cancelled (or closed) since
+            let Some(placeholder) = buffers.get().get(&id).filter(|b| b.loading.is_some()).cloned() else {
+                return;
+            };
+            let loaded = TextBuffer {version: placeholder.version + 1, ..*loaded};
+            // to anything watching, it's as if it was all typed in at once
+            let edit = Edit::insert(&placeholder.contents, 0, &loaded.contents.to_string());
+            buffers.store(id, loaded.clone());
+            notify_edits(observers, id, &loaded, vec![edit]);
+            // when it wasn't read as plain UTF-8, say what it was read as
+            match &loaded.file {
+                Some(fi) if fi.read_only && !fi.large && !fi.hex => ui.message(format!("{} isn't all UTF-8, it's read only (the bad bytes are shown as �)", loaded.name())),
+                // nothing's been typed, so that's why
+                Some(fi) if fi.is_modified => ui.message(mixed_message(&loaded)),
+                Some(fi) if !fi.encoding.is_utf8() => ui.message(format!("read {} as {}", loaded.name(), fi.encoding.name())),
+                _ => {},
+            }
+            ui.redraw(id);
+        },
+        LoadEvent::Done { buffer: id, error } => {
+            // everything's there (or as much as there's going to be)
+            for msg in state.waiting.remove(&id).unwrap_or_default() {
+                let _ = buffer_tx.send(msg);
+            }
+            let Some(buffer) = buffers.get().get(&id).cloned() else {
+                return;
+            };
+            if buffer.loading.is_none() {
+                // cancelled
+                return;
+            }
+            let mut file = buffer.file.clone();
+            if let (Some(e), Some(fi)) = (&error, &mut file) {
+                // saving the part we've got would cut the file short
+                fi.read_only = true;
+                ui.message(format!("couldn't read all of {}, it's read only: {e}", buffer.name()));
+            }
+            buffers.store(id, TextBuffer {file, loading: None, ..buffer});
+            ui.redraw(id);
+            if error.is_none() {
+                offer_recovery(buffers, ui, id);
+            }
+        },
+    }
+}
+
+fn is_loading(buffers: &Registry<TextBuffer>, id: BufferId) -> bool {
+    buffers.get().get(&id).is_some_and(|b| b.loading.is_some())
+}
+
+fn set_read_only(cx: &Context, state: &mut State, id: BufferId) {
+    let Context {buffers, ui, ..} = cx;
+    if is_loading(buffers, id) {
+        state.waiting.entry(id).or_default().push(BufferMsg::SetReadOnly(id));
+        return;
+    }
+    if let Some(buffer) = buffers.get().get(&id).filter(|b| b.file.is_some()) {
+        let file = buffer.file.clone().map(|fi| FileInfo {read_only: true, ..fi});
+        buffers.store(id, TextBuffer {file, ..buffer.clone()});
+        ui.redraw(id);
+    }
+}
+
+// a buffer the UI thread made, it has to be read and watched like one opened here
+fn load_new(cx: &Context, state: &mut State, id: BufferId) {
+    let Context {buffers, buffer_tx, ui, ..} = cx;
+    if let Some(buffer) = buffers.get().get(&id).cloned() {
+        start_loading(buffers, buffer_tx, id, &buffer);
+        if buffer.loading.is_none() {
+            offer_recovery(buffers, ui, id);
+        }
+        state.watcher.sync(&buffers.get());
+    }
+}
+
+fn autosave(cx: &Context) {
+    let Context {buffers, observers, ui, ..} = cx;
+    for (id, buffer) in buffers.get().iter().filter(|(_, b)| b.can_autosave()) {
+        if let Err(msg) = save(buffers, observers, *id, &buffer.file.as_ref().unwrap().filename) {
+            ui.message(msg);
+        }
+        ui.redraw(*id);
+    }
+}
+
+fn exit(cx: &Context) {
+    let Context {buffers, observers, ui, ..} = cx;
+    // anything unsaved was meant to be thrown away, as if it was closed
+    // (so its journal goes too)
+    for (id, _) in buffers.get().iter().filter(|(_, b)| b.has_unsaved_changes()) {
+        observers.notify(BufferEvent::Closed {buffer_id: *id, discarded: true});
+    }
+    ui.send(CustomEvent::Exit);
+}
+
+// the watcher saw the file change, but it might have been us saving it
+fn file_changed(cx: &Context, path: &Path) {
+    let Context {buffers, panes, observers, ui, ..} = cx;
+    let all = buffers.get();
+    let Some((&buf_id, buffer)) = all.iter().find(|(_, b)| b.file.as_ref().is_some_and(|fi| *fi.filename == *path)) else {
+        return;
+    };
+    let fi = buffer.file.as_ref().unwrap();
+    match fi.disk_time() {
+        None if fi.is_new => {},
+        None => {
+            // the buffer is all that's left, the next save makes the file again
+            let file = Some(FileInfo {is_new: true, ..fi.clone()});
+            buffers.store(buf_id, TextBuffer {file, ..buffer.clone()});
+            ui.message(format!("{} was deleted", buffer.name()));
+            ui.redraw(buf_id);
+        },
+        // our own save, or a change we've already dealt with
+        Some(time) if time <= fi.file_time => {},
+        // it's being read now anyway
+        Some(_) if buffer.loading.is_some() => {},
+        // reading it all again would take a while, it's up to them
+        Some(_) if fi.large => ui.message(format!("{} changed on disk", buffer.name())),
+        Some(_) if !buffer.is_modified() => {
+            match reload(buffers, panes, observers, buf_id) {
+                Ok(()) => ui.message(format!("reloaded {}", buffer.name())),
+                Err(msg) => ui.message(msg),
             }
+            ui.redraw(buf_id);
+        },
+        Some(_) => ui.prompt(changed_prompt(buf_id, &buffer.name())),
+    }
+}
+
+// the file will be different when it's saved, so the buffer counts as modified
+fn set_format(buffers: &Registry<TextBuffer>, buf_id: BufferId, old: &FileInfo, new: FileInfo) {
+    if (new.encoding, new.line_ending) != (old.encoding, old.line_ending) {
+        let file = Some(FileInfo {is_modified: true, ..new});
+        buffers.store(buf_id, TextBuffer {file, ..buffers.get()[&buf_id].clone()});
+    }
+}
+
+fn mixed_message(buffer: &TextBuffer) -> String {
+    let ending = buffer.file.as_ref().map(|fi| fi.line_ending).unwrap_or_default();
+    format!("{} has mixed line endings, saving makes them all {}", buffer.name(), ending.name())
+}
+
+fn changed_prompt(buf_id: BufferId, name: &str) -> Prompt {
+    let question = format!("{name} changed on disk and has unsaved changes: [r]eload [k]eep yours [d]iff");
+    Prompt::new(question, vec![
+        ('r', vec![BufferOp::Reload(buf_id).into()]),
+        ('k', vec![BufferOp::KeepLocal(buf_id).into()]),
+        ('d', vec![BufferOp::DiffDisk(buf_id).into()]),
+    ])
+}
+
+// a buffer that's just been saved
+fn write_history(buffer: &TextBuffer) {
+    let (Some(dir), Some(fi)) = (undo::dir(), &buffer.file) else {
+        return;
+    };
+    if fi.large || buffer.history.is_empty() {
+        return;
+    }
+    if let Err(e) = undo::write(&dir, &fi.filename, &buffer.history, &buffer.contents) {
+        log::error!("couldn't save the undo history of {}: {e}", buffer.name());
+    }
+}
+
+// keep a copy of what a buffer's just been saved as
+fn snapshot(buffer: &TextBuffer) {
+    let (Some(dir), Some(fi)) = (versions::dir(), &buffer.file) else {
+        return;
+    };
+    if fi.large {
+        return;
+    }
+    let kept = buffer.to_bytes().and_then(|bytes| versions::snapshot(&dir, &fi.filename, &bytes, versions::KEEP).map_err(|e| e.to_string()));
+    if let Err(e) = kept {
+        log::error!("couldn't keep a copy of {}: {e}", buffer.name());
+    }
+}
+
+// the `n`th newest saved version of the buffer's file
+fn saved_version(buffer: &TextBuffer, n: usize) -> Result<(versions::Version, Vec<u8>), String> {
+    let (Some(dir), Some(fi)) = (versions::dir(), &buffer.file) else {
+        return Err(format!("{} has no saved versions", buffer.name()));
+    };
+    versions::read(&dir, &fi.filename, n)
+}
+
+// the journal a crash left for the buffer's file
+fn orphan(buffer: &TextBuffer) -> Option<PathBuf> {
+    journal::find_orphan(&journal::dir()?, &buffer.file.as_ref()?.filename)
+}
+
+fn offer_recovery(buffers: &Registry<TextBuffer>, ui: &Ui, buf_id: BufferId) {
+    let buffer = &buffers.get()[&buf_id];
+    if orphan(buffer).is_some() {
+        ui.prompt(recovery_prompt(buf_id, &buffer.name()));
+    }
+}
+
+fn recovery_prompt(buf_id: BufferId, name: &str) -> Prompt {
+    let question = format!("{name} has unsaved changes from an editor that crashed: [r]ecover [d]iff [x] delete them");
+    Prompt::new(question, vec![
+        ('r', vec![BufferOp::Recover(buf_id).into()]),
+        ('d', vec![BufferOp::DiffRecovery(buf_id).into()]),
+        ('x', vec![BufferOp::DiscardRecovery(buf_id).into()]),
+    ])
+}
+
+// replace the buffer's contents with the journal's. It's modified (the file
+// doesn't have them), and the journal's gone, ours takes over from here
+fn recover(buffers: &Registry<TextBuffer>, panes: &Registry<Pane>, observers: &Observers, buf_id: BufferId) -> Result<(), String> {
+    let buffer = &buffers.get()[&buf_id];
+    let (Some(fi), Some(path)) = (&buffer.file, orphan(buffer)) else {
+        return Err(format!("{} has nothing to recover", buffer.name()));
+    };
+    let text = journal::recover(&path).map_err(|e| format!("couldn't recover {}: {e}", buffer.name()))?;
+    let (new_buffer, edits) = buffer.reloaded(&text, fi.file_time, fi.encoding, fi.line_ending);
+    let file = Some(FileInfo {is_modified: true, ..fi.clone()});
+    let history = buffer.history.record(&buffer.contents, &edits, None, false);
+    store_edited(buffers, panes, observers, buf_id, TextBuffer {file, history, ..new_buffer}, edits);
+    std::fs::remove_file(&path).map_err(|e| format!("recovered {}, but couldn't delete {}: {e}", buffer.name(), path.display()))
+}
+
+// `new_buffer` is the buffer after `edits`, which weren't typed (so nothing's
+// cursors follow them)
+fn store_edited(buffers: &Registry<TextBuffer>, panes: &Registry<Pane>, observers: &Observers, buf_id: BufferId, new_buffer: TextBuffer, edits: Vec<Edit>) {
+    panes.update_involved(buf_id, |involved| (involved.iter().map(|pane| pane.shift(&edits, false)).collect(), ()));
+    panes.shift_hidden(buf_id, &edits);
+    buffers.store(buf_id, new_buffer.clone());
+    notify_edits(observers, buf_id, &new_buffer, edits);
+}
+
+// swap the buffer between its text and a hex dump of the bytes it'd be saved as.
+// Either way the file stays the same, so it's no more (or less) modified
+fn set_hex(buffers: &Registry<TextBuffer>, panes: &Registry<Pane>, observers: &Observers, buf_id: BufferId, on: bool) -> Result<(), String> {
+    let buffer = &buffers.get()[&buf_id];
+    let Some(fi) = &buffer.file else {
+        return Err("no file to show as hex".to_string());
+    };
+    if fi.hex == on {
+        return Ok(());
+    }
+    if fi.large || buffer.loading.is_some() {
+        return Err(format!("{} is too big to show as hex", buffer.name()));
+    }
+    let bytes = buffer.to_bytes().map_err(|e| format!("can't switch {} to or from hex, {e}", buffer.name()))?;
+    let (text, encoding, line_ending, lossy, mixed) = decode(&bytes, on);
+    let mut contents = buffer.contents.clone();
+    let edit = Edit::replace(&contents, 0..contents.byte_len(), &text);
+    edit.apply(&mut contents);
+    let edits = vec![edit];
+    let file = Some(FileInfo {hex: on, encoding, line_ending, read_only: fi.read_only || lossy, is_modified: fi.is_modified || mixed, ..fi.clone()});
+    // the history is of the other one
+    let new_buffer = TextBuffer {file, contents, version: buffer.version + 1, history: History::default(), ..buffer.clone()};
+    store_edited(buffers, panes, observers, buf_id, new_buffer, edits);
+    Ok(())
+}
+
+// replace the buffer's contents with the file's
+fn reload(buffers: &Registry<TextBuffer>, panes: &Registry<Pane>, observers: &Observers, buf_id: BufferId) -> Result<(), String> {
+    let buffer = &buffers.get()[&buf_id];
+    let Some(fi) = &buffer.file else {
+        return Err(format!("{} has no file to reload from", buffer.name()));
+    };
+    if fi.large {
+        return Err(format!("{} is too big to reload", buffer.name()));
+    }
+    // before reading, so a write in between gets noticed as a newer change
+    let file_time = fi.disk_time().unwrap_or_else(SystemTime::now);
+    let (bytes, _) = compress::read(&fi.filename).map_err(|e| format!("couldn't reload {}: {e}", buffer.name()))?;
+    let (text, encoding, line_ending, lossy, mixed) = decode(&bytes, fi.hex);
+    let (mut new_buffer, edits) = buffer.reloaded(&text, file_time, encoding, line_ending);
+    if let Some(fi) = new_buffer.file.as_mut() {
+        fi.read_only |= lossy;
+        fi.is_modified = mixed;
+    }
+    // it's the file again, but what it was is only an undo away
+    let history = buffer.history.record(&buffer.contents, &edits, None, false).mark_saved();
+    store_edited(buffers, panes, observers, buf_id, TextBuffer {history, ..new_buffer}, edits);
+    Ok(())
+}
+
+// save the buffer to its file, false if it wasn't (it failed, or it has to ask first)
+fn save_file(cx: &Context, buf_id: BufferId) -> bool {
+    let Context {buffers, observers, ui, ..} = cx;
+    let buffer = &buffers.get()[&buf_id];
+    let Some(fi) = &buffer.file else {
+        ui.message("no file name (use :w <path>)".to_string());
+        return false;
+    };
+    let path = fi.filename.to_string_lossy().into_owned();
+    if fi.is_new {
+        // creating it, so check like Save As does (its directory might not exist,
+        // or someone else might have made the file since)
+        return save_as(cx, buf_id, path, false, false);
+    }
+    if fi.changed_on_disk() {
+        // saving would throw away whatever they wrote
+        let question = format!("{} changed on disk since it was read, overwrite it? [y]es [n]o [d]iff", buffer.name());
+        let overwrite = BufferOp::SaveAs { path, overwrite: true, create_dirs: false };
+        ui.prompt(Prompt::new(question, vec![('y', vec![overwrite.into()]), ('n', vec![]), ('d', vec![BufferOp::DiffDisk(buf_id).into()])]));
+        return false;
+    }
+    match save(buffers, observers, buf_id, &fi.filename) {
+        Ok(()) => {
+            snapshot(&buffers.get()[&buf_id]);
+            true
+        },
+        Err(msg) => {
+            ui.message(msg);
+            false
+        },
+    }
+}
+
+fn save_as(cx: &Context, buf_id: BufferId, path: String, overwrite: bool, create_dirs: bool) -> bool {
+    let Context {buffers, observers, ui, ..} = cx;
+    let buffer = &buffers.get()[&buf_id];
+    let filepath = Path::new(&path);
+    let same_file = buffer.file.as_ref().is_some_and(|fi| *fi.filename == *filepath);
+    let ask_again = |question: String, overwrite, create_dirs| {
+        let again = BufferOp::SaveAs { path: path.clone(), overwrite, create_dirs };
+        ui.prompt(Prompt::new(question, vec![('y', vec![again.into()]), ('n', vec![])]));
+    };
+    // an existing file that isn't ours (or is, but was made after we opened it as new)
+    let is_new = buffer.file.as_ref().is_some_and(|fi| fi.is_new);
+    if !overwrite && (!same_file || is_new) && filepath.exists() {
+        ask_again(format!("{path} already exists, overwrite? [y]es [n]o"), true, create_dirs);
+        return false;
+    }
+    let parent = filepath.parent().filter(|p| *p != Path::new(""));
+    if let Some(parent) = parent.filter(|p| !p.exists()) {
+        if !create_dirs {
+            ask_again(format!("{} doesn't exist, create it? [y]es [n]o", parent.display()), overwrite, true);
+            return false;
         }
+        if let Err(e) = std::fs::create_dir_all(parent) {
+            ui.message(format!("couldn't create {}: {e}", parent.display()));
+            return false;
+        }
+    }
+    match save(buffers, observers, buf_id, filepath) {
+        Ok(()) => {
+            snapshot(&buffers.get()[&buf_id]);
+            ui.message(format!("wrote {path}"));
+            true
+        },
+        Err(msg) => {
+            ui.message(msg);
+            false
+        },
+    }
+}
+
+// write the buffer to `path`, which it's attached to from now on
+fn save(buffers: &Registry<TextBuffer>, observers: &Observers, buf_id: BufferId, path: &Path) -> Result<(), String> {
+    let buffer = &buffers.get()[&buf_id];
+    if buffer.loading.is_some() {
+        return Err(format!("{} is still loading", buffer.name()));
+    }
+    // somewhere else is fine, it's the original we'd be damaging
+    if buffer.is_read_only() && buffer.file.as_ref().is_some_and(|fi| *fi.filename == *path) {
+        return Err(format!("{} is read only, save it somewhere else with :w <path>", buffer.name()));
+    }
+    match buffer.write(path) {
+        Err(e) => {
+            log::error!("tried to save buffer, but {}", e);
+            Err(format!("couldn't save {}: {e}", path.display()))
+        },
+        Ok(b) => {
+            let version = b.version;
+            let b = TextBuffer {history: b.history.mark_saved(), ..b};
+            write_history(&b);
+            buffers.store(buf_id, b);
+            observers.notify(BufferEvent::Saved {buffer_id: buf_id, version, path: Arc::from(path)});
+            Ok(())
+        },
+    }
+}
+
+// read the file of a `pending` buffer on its own thread
+fn start_loading(buffers: &Arc<Registry<TextBuffer>>, buffer_tx: &mpsc::Sender<BufferMsg>, buf_id: BufferId, buffer: &TextBuffer) {
+    let (Some(_), Some(fi)) = (buffer.loading, &buffer.file) else {
+        return;
+    };
+    let (path, buffers, buffer_tx) = (fi.filename.clone(), buffers.clone(), buffer_tx.clone());
+    std::thread::spawn(move || loader::load(path, buf_id, buffers, buffer_tx));
+}
+
+// `buffer` is after the edits. Large files are left out, anything watching
+// for changes would have to deal with the whole thing
+fn notify_edits(observers: &Observers, buffer_id: BufferId, buffer: &TextBuffer, edits: Vec<Edit>) {
+    if edits.is_empty() || buffer.is_large() {
+        return;
     }
+    let lines = affected_lines(&edits);
+    observers.notify(BufferEvent::Changed(BufferChange {buffer_id, version: buffer.version, edits, lines}));
 }
@@ -0,0 +1,169 @@
+// The command line chop is started with. Files are opened in the order given,
+// the first one shown and the rest as buffers to switch to. Where to start in a
+// file can go on the end of it, like compilers print (`src/main.rs:42:7`), or
+// before it like vim (`+42 src/main.rs`, `+42:7`). Lines and columns count
+// from 1, columns in graphemes.
+
+use std::path::{Path, PathBuf};
+use serde::{Deserialize, Serialize};
+
+pub const USAGE: &str = "\
+usage: chop [options] [file[:line[:col]] | +line[:col] file | -]...
+
+  -            read a buffer from stdin (also the default when it's piped in
+               and there are no files)
+  +line[:col]  start the next file at that position
+  -R, --readonly
+               open the files read only
+  -w, --wait   don't exit until the files are closed, for $EDITOR (exits
+               with 1 if one's closed without saving it, or with :cq)
+  -n, --new    start another chop, rather than opening the files in the one
+               that's running
+  --log FILE   write the log to FILE instead of stderr
+  -h, --help   show this and exit
+  -V, --version
+               show the version and exit
+";
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    Run(Options),
+    Help,
+    Version,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub struct Options {
+    pub files: Vec<Input>,
+    pub read_only: bool,
+    pub wait: bool,
+    // don't hand the files to the chop that's running (see `server`)
+    pub new_instance: bool,
+    pub log: Option<PathBuf>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Input {
+    // `line` and `col` are as given, from 1
+    File { path: String, line: Option<usize>, col: Option<usize> },
+    Stdin,
+    // what was piped into another chop, that sent it here
+    Text(String),
+}
+
+// `args` doesn't include the program's name. `piped` is whether stdin is a
+// pipe or a file, not just anything that isn't a terminal
+pub fn parse(args: impl IntoIterator<Item = String>, piped: bool) -> Result<Action, String> {
+    let mut options = Options::default();
+    let mut args = args.into_iter();
+    let mut position = None;
+    let mut only_files = false;
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            _ if only_files => options.files.push(file(&arg, position.take())),
+            "--" => only_files = true,
+            "-h" | "--help" => return Ok(Action::Help),
+            "-V" | "--version" => return Ok(Action::Version),
+            "-R" | "--readonly" => options.read_only = true,
+            "-w" | "--wait" => options.wait = true,
+            "-n" | "--new" => options.new_instance = true,
+            "--log" => options.log = Some(args.next().ok_or("--log needs a file")?.into()),
+            "-" => options.files.push(Input::Stdin),
+            _ if arg.starts_with("--log=") => options.log = Some(arg["--log=".len()..].into()),
+            _ if arg.starts_with('+') => match line_col(&arg[1..]) {
+                Some(at) => position = Some(at),
+                None => return Err(format!("not a line (or line:col): {arg}")),
+            },
+            _ if arg.starts_with('-') => return Err(format!("unknown option: {arg}")),
+            _ => options.files.push(file(&arg, position.take())),
+        }
+    }
+    if let Some((line, _)) = position {
+        return Err(format!("+{line} isn't followed by a file"));
+    }
+    if options.files.is_empty() && piped {
+        options.files.push(Input::Stdin);
+    }
+    Ok(Action::Run(options))
+}
+
+// a `:line:col` on the end is a position, unless there's a file with that name
+fn file(arg: &str, position: Option<(usize, Option<usize>)>) -> Input {
+    let (path, at) = match split_position(arg) {
+        Some((path, at)) if !Path::new(arg).exists() => (path, Some(at)),
+        _ => (arg, None),
+    };
+    let (line, col) = match position.or(at) {
+        Some((line, col)) => (Some(line), col),
+        None => (None, None),
+    };
+    Input::File { path: path.to_string(), line, col }
+}
+
+fn split_position(arg: &str) -> Option<(&str, (usize, Option<usize>))> {
+    let (rest, last) = arg.rsplit_once(':')?;
+    let last: usize = last.parse().ok()?;
+    match rest.rsplit_once(':').and_then(|(path, line)| Some((path, line.parse().ok()?))) {
+        Some((path, line)) if !path.is_empty() => Some((path, (line, Some(last)))),
+        _ if !rest.is_empty() => Some((rest, (last, None))),
+        _ => None,
+    }
+}
+
+fn line_col(s: &str) -> Option<(usize, Option<usize>)> {
+    match s.split_once(':') {
+        Some((line, col)) => Some((line.parse().ok()?, Some(col.parse().ok()?))),
+        None => Some((s.parse().ok()?, None)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::TestDir;
+
+    fn run(args: &[&str], piped: bool) -> Result<Options, String> {
+        match parse(args.iter().map(|a| a.to_string()), piped)? {
+            Action::Run(options) => Ok(options),
+            action => panic!("expected to run, got {action:?}"),
+        }
+    }
+
+    fn at(path: &str, line: Option<usize>, col: Option<usize>) -> Input {
+        Input::File { path: path.to_string(), line, col }
+    }
+
+    #[test]
+    fn test_parse() {
+        let options = run(&["a.rs", "no/such/file.rs:42:7", "+3", "b.rs", "c.rs:9", "-R"], false).unwrap();
+        assert_eq!(options.files, vec![
+            at("a.rs", None, None),
+            at("no/such/file.rs", Some(42), Some(7)),
+            at("b.rs", Some(3), None),
+            at("c.rs", Some(9), None),
+        ]);
+        assert!(options.read_only);
+        assert!(run(&["--wait", "COMMIT_EDITMSG"], false).unwrap().wait);
+        assert!(run(&["-n"], false).unwrap().new_instance);
+        assert_eq!(run(&["--log", "/tmp/chop.log", "+2:5", "--", "-x"], false).unwrap(), Options {
+            files: vec![at("-x", Some(2), Some(5))],
+            read_only: false,
+            wait: false,
+            new_instance: false,
+            log: Some("/tmp/chop.log".into()),
+        });
+        assert_eq!(run(&[], true).unwrap().files, vec![Input::Stdin]);
+        assert_eq!(run(&["-", "a.rs"], false).unwrap().files, vec![Input::Stdin, at("a.rs", None, None)]);
+        // a file that's really called that
+        let dir = TestDir::new("cli");
+        let path = dir.join("a.rs:3");
+        std::fs::write(&path, "").unwrap();
+        assert_eq!(run(&[path.to_str().unwrap()], false).unwrap().files, vec![at(path.to_str().unwrap(), None, None)]);
+
+        assert_eq!(parse(["--help".to_string()], false), Ok(Action::Help));
+        assert!(run(&["--bogus"], false).is_err());
+        assert!(run(&["a.rs", "+3"], false).is_err());
+        assert!(run(&["+x", "a.rs"], false).is_err());
+        assert!(run(&["--log"], false).is_err());
+    }
+}
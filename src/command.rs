@@ -0,0 +1,122 @@
+// The command line (what's typed after `:` in normal mode). Like vim, the
+// first word is the command and the rest are its arguments.
+
+use std::time::Duration;
+
+use crate::buffer::{BufferOp, Op, SwitchTo, WindowOp};
+use crate::layout::LayoutOp;
+use crate::encoding::LineEnding;
+use crate::autosave::Setting;
+
+pub fn parse(line: &str) -> Result<Vec<Op>, String> {
+    let line = line.trim();
+    let (cmd, arg) = match line.split_once(char::is_whitespace) {
+        Some((cmd, arg)) => (cmd, arg.trim()),
+        None => (line, ""),
+    };
+    // `:b#` and `:b3` don't need the space
+    let (cmd, arg) = match cmd.strip_prefix('b') {
+        Some(rest) if arg.is_empty() && (rest == "#" || (!rest.is_empty() && rest.chars().all(|c| c.is_ascii_digit()))) => ("b", rest),
+        _ => (cmd, arg),
+    };
+    let op: Op = match (cmd, arg) {
+        ("", _) => return Ok(vec![]),
+        ("e" | "edit", "") => return Err("no file name".to_string()),
+        ("e" | "edit", path) => BufferOp::Open(path.to_string()).into(),
+        ("ls" | "buffers", "") => WindowOp::ListBuffers.into(),
+        ("bn" | "bnext", "") => BufferOp::SwitchBuffer(SwitchTo::Next).into(),
+        ("bp" | "bprev" | "bprevious", "") => BufferOp::SwitchBuffer(SwitchTo::Prev).into(),
+        ("b" | "buffer", "#") => BufferOp::SwitchBuffer(SwitchTo::Alternate).into(),
+        ("b" | "buffer", n) => match n.parse() {
+            Ok(id) => BufferOp::SwitchBuffer(SwitchTo::Id(id)).into(),
+            Err(_) => return Err(format!("not a buffer number: {n}")),
+        },
+        ("w" | "write" | "w!" | "write!", "") => BufferOp::Save.into(),
+        ("w" | "write" | "saveas", path) => BufferOp::SaveAs { path: path.to_string(), overwrite: false, create_dirs: false }.into(),
+        ("w!" | "write!" | "saveas!", path) => BufferOp::SaveAs { path: path.to_string(), overwrite: true, create_dirs: false }.into(),
+        ("q" | "quit", "") => WindowOp::Layout(LayoutOp::Close).into(),
+        ("q!" | "quit!", "") => WindowOp::Layout(LayoutOp::ForceClose).into(),
+        ("qa" | "qall", "") => WindowOp::Exit.into(),
+        ("qa!" | "qall!", "") => WindowOp::ForceExit.into(),
+        ("cq" | "cquit", "") => WindowOp::Abort.into(),
+        ("u" | "undo", "") => BufferOp::Undo.into(),
+        ("red" | "redo", "") => BufferOp::Redo.into(),
+        ("history", "") => BufferOp::ListVersions.into(),
+        ("history", arg) => history(arg)?.into(),
+        ("set" | "se", option) => set(option)?,
+        _ => return Err(format!("not an editor command: {line}")),
+    };
+    Ok(vec![op])
+}
+
+// `:set` with a buffer option
+fn set(option: &str) -> Result<Op, String> {
+    let op: Op = match option.split_once('=') {
+        Some(("fenc" | "fileencoding", encoding)) => BufferOp::SetEncoding(encoding.to_string()).into(),
+        Some(("ff" | "fileformat", format)) => match LineEnding::for_label(format) {
+            Some(line_ending) => BufferOp::SetLineEnding(line_ending).into(),
+            None => return Err(format!("unknown file format: {format}")),
+        },
+        None if option == "bomb" => BufferOp::SetBom(true).into(),
+        None if option == "nobomb" => BufferOp::SetBom(false).into(),
+        Some(("autosave_idle", ms)) => WindowOp::SetAutosave(Setting::Idle(millis(ms)?)).into(),
+        Some(("autosave_interval", ms)) => WindowOp::SetAutosave(Setting::Interval(millis(ms)?)).into(),
+        None if option == "autosave_focus" => WindowOp::SetAutosave(Setting::FocusLoss(true)).into(),
+        None if option == "noautosave_focus" => WindowOp::SetAutosave(Setting::FocusLoss(false)).into(),
+        None if option == "hex" => BufferOp::SetHex(true).into(),
+        None if option == "nohex" => BufferOp::SetHex(false).into(),
+        _ => return Err(format!("unknown option: {option}")),
+    };
+    Ok(op)
+}
+
+// `:history diff <n>` and `:history restore <n>`
+fn history(arg: &str) -> Result<BufferOp, String> {
+    let (what, n) = arg.split_once(char::is_whitespace).unwrap_or((arg, ""));
+    let n = n.trim().parse().map_err(|_| format!("which version? :history {what} <n>, 1 is the newest"))?;
+    match what {
+        "diff" => Ok(BufferOp::DiffVersion(n)),
+        "restore" => Ok(BufferOp::RestoreVersion(n)),
+        _ => Err(format!("not a history command: {what}")),
+    }
+}
+
+// 0 is off
+fn millis(ms: &str) -> Result<Option<Duration>, String> {
+    match ms.parse() {
+        Ok(0) => Ok(None),
+        Ok(ms) => Ok(Some(Duration::from_millis(ms))),
+        Err(_) => Err(format!("not a number of milliseconds: {ms}")),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse() {
+        assert_eq!(parse(" e  src/main.rs "), Ok(vec![BufferOp::Open("src/main.rs".to_string()).into()]));
+        assert_eq!(parse("bn"), Ok(vec![BufferOp::SwitchBuffer(SwitchTo::Next).into()]));
+        assert_eq!(parse("b#"), Ok(vec![BufferOp::SwitchBuffer(SwitchTo::Alternate).into()]));
+        assert_eq!(parse("b 2"), parse("b2"));
+        assert_eq!(parse("b2"), Ok(vec![BufferOp::SwitchBuffer(SwitchTo::Id(2)).into()]));
+        assert_eq!(parse(""), Ok(vec![]));
+        assert_eq!(parse("w! a/b.rs"), Ok(vec![BufferOp::SaveAs { path: "a/b.rs".to_string(), overwrite: true, create_dirs: false }.into()]));
+        assert!(parse("e").is_err());
+        assert!(parse("b x").is_err());
+        assert!(parse("bogus").is_err());
+        assert_eq!(parse("set fenc=latin1"), Ok(vec![BufferOp::SetEncoding("latin1".to_string()).into()]));
+        assert_eq!(parse("se nobomb"), Ok(vec![BufferOp::SetBom(false).into()]));
+        assert!(parse("set bogus").is_err());
+        assert_eq!(parse("set ff=dos"), Ok(vec![BufferOp::SetLineEnding(LineEnding::Crlf).into()]));
+        assert!(parse("set ff=amiga").is_err());
+        assert_eq!(parse("set nohex"), Ok(vec![BufferOp::SetHex(false).into()]));
+        assert_eq!(parse("set autosave_idle=1500"), Ok(vec![WindowOp::SetAutosave(Setting::Idle(Some(Duration::from_millis(1500)))).into()]));
+        assert_eq!(parse("set autosave_interval=0"), Ok(vec![WindowOp::SetAutosave(Setting::Interval(None)).into()]));
+        assert!(parse("set autosave_idle=soon").is_err());
+        assert_eq!(parse("redo"), Ok(vec![BufferOp::Redo.into()]));
+        assert_eq!(parse("history restore 2"), Ok(vec![BufferOp::RestoreVersion(2).into()]));
+        assert!(parse("history diff").is_err());
+    }
+}
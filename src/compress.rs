@@ -0,0 +1,115 @@
+// Compressed files are decompressed when they're read, so the buffer is just
+// the text, and compressed the same way again when they're saved.
+
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Compression {
+    Gzip,
+    Zstd,
+    Xz,
+}
+
+impl Compression {
+    // from the magic bytes, not the name
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        // the third byte is the method, deflate is the only one there is
+        if bytes.starts_with(&[0x1f, 0x8b, 0x08]) {
+            Some(Self::Gzip)
+        } else if bytes.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Self::Zstd)
+        } else if bytes.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0x00]) {
+            Some(Self::Xz)
+        } else {
+            None
+        }
+    }
+
+    // without reading the whole file
+    pub fn of_file(path: &Path) -> io::Result<Option<Self>> {
+        let mut magic = vec![];
+        File::open(path)?.take(6).read_to_end(&mut magic)?;
+        Ok(Self::detect(&magic))
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Gzip => "gzip",
+            Self::Zstd => "zstd",
+            Self::Xz => "xz",
+        }
+    }
+
+    pub fn decompress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out = vec![];
+        match self {
+            // there can be more than one member, `gzip -d` reads them all
+            Self::Gzip => flate2::read::MultiGzDecoder::new(bytes).read_to_end(&mut out)?,
+            Self::Zstd => zstd::stream::read::Decoder::new(bytes)?.read_to_end(&mut out)?,
+            Self::Xz => xz2::read::XzDecoder::new_multi_decoder(bytes).read_to_end(&mut out)?,
+        };
+        Ok(out)
+    }
+
+    // at each tool's default level
+    pub fn compress(&self, bytes: &[u8]) -> io::Result<Vec<u8>> {
+        match self {
+            Self::Gzip => {
+                let mut encoder = flate2::write::GzEncoder::new(vec![], flate2::Compression::default());
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            },
+            Self::Zstd => zstd::stream::encode_all(bytes, 3),
+            Self::Xz => {
+                let mut encoder = xz2::write::XzEncoder::new(vec![], 6);
+                encoder.write_all(bytes)?;
+                encoder.finish()
+            },
+        }
+    }
+}
+
+// the file's bytes, decompressed if they were compressed
+pub fn read(path: &Path) -> io::Result<(Vec<u8>, Option<Compression>)> {
+    Ok(decompressed(std::fs::read(path)?))
+}
+
+// just the bytes if they only look compressed
+fn decompressed(bytes: Vec<u8>) -> (Vec<u8>, Option<Compression>) {
+    let Some(compression) = Compression::detect(&bytes) else {
+        return (bytes, None);
+    };
+    match compression.decompress(&bytes) {
+        Ok(decompressed) => (decompressed, Some(compression)),
+        Err(e) => {
+            log::warn!("looks like {} but couldn't decompress it, opening it as it is: {e}", compression.name());
+            (bytes, None)
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_round_trip() {
+        let text = b"{\"a\": 1}\n".repeat(100);
+        for compression in [Compression::Gzip, Compression::Zstd, Compression::Xz] {
+            let compressed = compression.compress(&text).unwrap();
+            assert_eq!(Compression::detect(&compressed), Some(compression));
+            assert_eq!(compression.decompress(&compressed).unwrap(), text, "{}", compression.name());
+        }
+        assert_eq!(Compression::detect(b"plain"), None);
+    }
+
+    #[test]
+    fn test_only_looks_compressed() {
+        // not deflate, so not gzip
+        assert_eq!(Compression::detect(b"\x1f\x8bnot gzip"), None);
+        let broken = b"\x1f\x8b\x08broken".to_vec();
+        assert_eq!(decompressed(broken.clone()), (broken, None));
+    }
+}
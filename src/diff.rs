@@ -0,0 +1,109 @@
+// Line diffs, for showing what changed on disk under a modified buffer.
+// Nothing fancy: the common start and end are trimmed and what's left in the
+// middle gets an LCS table (or, if it's huge, is shown as all removed/added).
+
+use std::fmt::Write;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Line<'a> {
+    Same(&'a str),
+    Removed(&'a str),
+    Added(&'a str),
+}
+
+// past this many cells in the table we stop looking for matching lines
+const MAX_TABLE: usize = 4_000_000;
+
+pub fn diff_lines<'a>(old: &'a str, new: &'a str) -> Vec<Line<'a>> {
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..].iter().rev().zip(new[prefix..].iter().rev()).take_while(|(a, b)| a == b).count();
+    let (a, b) = (&old[prefix..old.len() - suffix], &new[prefix..new.len() - suffix]);
+
+    let mut out: Vec<Line> = old[..prefix].iter().map(|l| Line::Same(l)).collect();
+    if a.len() * b.len() > MAX_TABLE {
+        out.extend(a.iter().map(|l| Line::Removed(l)));
+        out.extend(b.iter().map(|l| Line::Added(l)));
+    } else {
+        // lcs[i][j] is the longest common subsequence of a[i..] and b[j..]
+        let mut lcs = vec![vec![0u32; b.len() + 1]; a.len() + 1];
+        for i in (0..a.len()).rev() {
+            for j in (0..b.len()).rev() {
+                lcs[i][j] = if a[i] == b[j] {
+                    lcs[i + 1][j + 1] + 1
+                } else {
+                    lcs[i + 1][j].max(lcs[i][j + 1])
+                };
+            }
+        }
+        let (mut i, mut j) = (0, 0);
+        while i < a.len() || j < b.len() {
+            if i < a.len() && j < b.len() && a[i] == b[j] {
+                out.push(Line::Same(a[i]));
+                i += 1;
+                j += 1;
+            } else if j == b.len() || (i < a.len() && lcs[i + 1][j] >= lcs[i][j + 1]) {
+                out.push(Line::Removed(a[i]));
+                i += 1;
+            } else {
+                out.push(Line::Added(b[j]));
+                j += 1;
+            }
+        }
+    }
+    out.extend(old[old.len() - suffix..].iter().map(|l| Line::Same(l)));
+    out
+}
+
+// like `diff -u`, with `context` unchanged lines around each change
+pub fn unified(old_name: &str, old: &str, new_name: &str, new: &str, context: usize) -> String {
+    let lines = diff_lines(old, new);
+    let mut out = format!("--- {old_name}\n+++ {new_name}\n");
+    let changed: Vec<usize> = lines.iter().enumerate().filter(|(_, l)| !matches!(l, Line::Same(_))).map(|(i, _)| i).collect();
+    let mut i = 0;
+    while i < changed.len() {
+        // grow the hunk while the next change is close enough to share context
+        let start = changed[i].saturating_sub(context);
+        let mut end = changed[i];
+        while i < changed.len() && changed[i] <= end + 2 * context {
+            end = changed[i];
+            i += 1;
+        }
+        let end = (end + context + 1).min(lines.len());
+
+        let count = |keep: fn(&Line) -> bool| lines[start..end].iter().filter(|l| keep(l)).count();
+        let old_len = count(|l| !matches!(l, Line::Added(_)));
+        let new_len = count(|l| !matches!(l, Line::Removed(_)));
+        let old_start = lines[..start].iter().filter(|l| !matches!(l, Line::Added(_))).count() + 1;
+        let new_start = lines[..start].iter().filter(|l| !matches!(l, Line::Removed(_))).count() + 1;
+        let _ = writeln!(out, "@@ -{old_start},{old_len} +{new_start},{new_len} @@");
+        for line in &lines[start..end] {
+            let _ = match line {
+                Line::Same(l) => writeln!(out, " {l}"),
+                Line::Removed(l) => writeln!(out, "-{l}"),
+                Line::Added(l) => writeln!(out, "+{l}"),
+            };
+        }
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_diff_lines() {
+        let lines = diff_lines("a\nb\nc\nd", "a\nc\nx\nd");
+        assert_eq!(lines, vec![Line::Same("a"), Line::Removed("b"), Line::Same("c"), Line::Added("x"), Line::Same("d")]);
+    }
+
+    #[test]
+    fn test_unified() {
+        let old = "1\n2\n3\n4\n5\n6\n7\n8\n9\n";
+        let new = "1\n2\n3\n4\nfive\n6\n7\n8\n9\n";
+        assert_eq!(unified("a", old, "b", new, 1), "--- a\n+++ b\n@@ -4,3 +4,3 @@\n 4\n-5\n+five\n 6\n");
+        assert_eq!(unified("a", old, "b", old, 3), "--- a\n+++ b\n");
+    }
+}
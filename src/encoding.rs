@@ -0,0 +1,287 @@
+// Files are decoded into a UTF-8 buffer when they're read and encoded back the
+// same way when they're saved, so opening and saving a file without editing it
+// never changes its bytes.
+//
+// Detection is a BOM if there is one, then UTF-16 if it looks like it (lots of
+// zero bytes in every other position), then UTF-8. A file that's mostly UTF-8
+// with a few bad bytes is still read as UTF-8, with the bad bytes as U+FFFD
+// (so it can't be saved over, that would lose them). Anything else is read as
+// Windows-1252, which has a character for every byte, so even a file that's
+// in some other encoding (or isn't text at all) comes back out unchanged.
+//
+// Line endings are the same idea: whichever one the file mostly uses is
+// remembered, the buffer only ever has `\n`, and it's put back on save.
+
+use std::borrow::Cow;
+use encoding_rs::{Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextEncoding {
+    pub encoding: &'static Encoding,
+    // whether the file starts with a byte order mark
+    pub bom: bool,
+}
+
+impl Default for TextEncoding {
+    fn default() -> Self {
+        Self {encoding: UTF_8, bom: false}
+    }
+}
+
+impl TextEncoding {
+    // from a name like `latin1` or `utf-16le`, only ones we can save in
+    pub fn for_label(label: &str) -> Option<Self> {
+        let encoding = Encoding::for_label(label.trim().as_bytes())?;
+        // `replacement` and friends decode to something but can't be written back
+        if encoding.output_encoding() != encoding && !is_utf16(encoding) {
+            return None;
+        }
+        Some(Self {encoding, bom: false})
+    }
+
+    pub fn is_utf8(&self) -> bool {
+        self.encoding == UTF_8 && !self.bom
+    }
+
+    // only the unicode encodings have one
+    pub fn can_have_bom(&self) -> bool {
+        self.encoding == UTF_8 || is_utf16(self.encoding)
+    }
+
+    pub fn name(&self) -> String {
+        let name = self.encoding.name().to_lowercase();
+        if self.bom { format!("{name} bom") } else { name }
+    }
+
+    // the text, and true if some of the file didn't make it into it
+    pub fn decode(bytes: &[u8]) -> (String, Self, bool) {
+        if let Some((encoding, bom_len)) = Encoding::for_bom(bytes) {
+            if let Some(text) = encoding.decode_without_bom_handling_and_without_replacement(&bytes[bom_len..]) {
+                return (text.into_owned(), Self {encoding, bom: true}, false);
+            }
+        } else {
+            let encoding = guess_utf16(bytes).unwrap_or(UTF_8);
+            if let Some(text) = encoding.decode_without_bom_handling_and_without_replacement(bytes) {
+                return (text.into_owned(), Self {encoding, bom: false}, false);
+            }
+            // probably not UTF-16 after all
+            if let Ok(text) = std::str::from_utf8(bytes) {
+                return (text.to_string(), Self::default(), false);
+            }
+            if is_mostly_utf8(bytes) {
+                return (String::from_utf8_lossy(bytes).into_owned(), Self::default(), true);
+            }
+        }
+        let (text, _) = WINDOWS_1252.decode_without_bom_handling(bytes);
+        (text.into_owned(), Self {encoding: WINDOWS_1252, bom: false}, false)
+    }
+
+    // Err if the text has characters the encoding can't represent
+    pub fn encode(&self, text: &str) -> Result<Vec<u8>, String> {
+        let mut out = vec![];
+        if self.bom {
+            out.extend_from_slice(match self.encoding.name() {
+                "UTF-16LE" => &[0xFF, 0xFE],
+                "UTF-16BE" => &[0xFE, 0xFF],
+                _ => &[0xEF, 0xBB, 0xBF],
+            });
+        }
+        // encoding_rs only decodes UTF-16, so that's done by hand
+        if self.encoding == UTF_16LE {
+            out.extend(text.encode_utf16().flat_map(u16::to_le_bytes));
+        } else if self.encoding == UTF_16BE {
+            out.extend(text.encode_utf16().flat_map(u16::to_be_bytes));
+        } else {
+            let (bytes, _, had_errors) = self.encoding.encode(text);
+            if had_errors {
+                return Err(format!("it has characters that can't be written in {}", self.name()));
+            }
+            out.extend_from_slice(&bytes);
+        }
+        Ok(out)
+    }
+}
+
+// more characters that only make sense as UTF-8 than bytes that don't. Latin-1
+// text has its accents on their own, which are never valid UTF-8
+fn is_mostly_utf8(bytes: &[u8]) -> bool {
+    let (mut valid, mut invalid) = (0, 0);
+    for chunk in bytes.utf8_chunks() {
+        valid += chunk.valid().chars().filter(|c| !c.is_ascii()).count();
+        invalid += !chunk.invalid().is_empty() as usize;
+    }
+    valid > invalid
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum LineEnding {
+    #[default]
+    Lf,
+    Crlf,
+    // old Macs
+    Cr,
+}
+
+impl LineEnding {
+    // from vim's `fileformat` names or the obvious ones
+    pub fn for_label(label: &str) -> Option<Self> {
+        match label.trim().to_lowercase().as_str() {
+            "unix" | "lf" => Some(Self::Lf),
+            "dos" | "crlf" => Some(Self::Crlf),
+            "mac" | "cr" => Some(Self::Cr),
+            _ => None,
+        }
+    }
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Self::Lf => "lf",
+            Self::Crlf => "crlf",
+            Self::Cr => "cr",
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            Self::Lf => "\n",
+            Self::Crlf => "\r\n",
+            Self::Cr => "\r",
+        }
+    }
+
+    // the ending most lines in `text` use (LF if there's a tie or no lines at
+    // all), and `text` with those endings turned into `\n`. The last is true if
+    // it had a mix, so saving it would change the ones that were different
+    pub fn normalize(text: String) -> (String, Self, bool) {
+        if !text.contains('\r') {
+            return (text, Self::Lf, false);
+        }
+        let bytes = text.as_bytes();
+        let (mut lf, mut crlf, mut cr) = (0, 0, 0);
+        for (i, b) in bytes.iter().enumerate() {
+            match b {
+                b'\n' if i > 0 && bytes[i - 1] == b'\r' => crlf += 1,
+                b'\n' => lf += 1,
+                b'\r' if bytes.get(i + 1) != Some(&b'\n') => cr += 1,
+                _ => {},
+            }
+        }
+        let ending = if crlf > lf && crlf >= cr {
+            Self::Crlf
+        } else if cr > lf && cr > crlf {
+            Self::Cr
+        } else {
+            Self::Lf
+        };
+        let normalized = ending.unapply(&text).into_owned();
+        let mixed = ending.apply(&normalized) != text;
+        (normalized, ending, mixed)
+    }
+
+    // `text` from the file with only these endings turned into `\n`. A `\r` on
+    // its own isn't one in an LF or CRLF file, it stays (vim's `^M`)
+    pub fn unapply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match self {
+            Self::Lf => Cow::Borrowed(text),
+            Self::Crlf => Cow::Owned(text.replace("\r\n", "\n")),
+            Self::Cr => Cow::Owned(text.replace('\r', "\n")),
+        }
+    }
+
+    // `text` (with `\n` endings) the way it goes in the file
+    pub fn apply<'a>(&self, text: &'a str) -> Cow<'a, str> {
+        match self {
+            Self::Lf => Cow::Borrowed(text),
+            _ => Cow::Owned(text.replace('\n', self.as_str())),
+        }
+    }
+}
+
+fn is_utf16(encoding: &'static Encoding) -> bool {
+    encoding == UTF_16LE || encoding == UTF_16BE
+}
+
+// mostly ASCII text in UTF-16 has a zero in every other byte
+pub(crate) fn guess_utf16(bytes: &[u8]) -> Option<&'static Encoding> {
+    let sample = &bytes[..bytes.len().min(4096) & !1];
+    if sample.is_empty() || !bytes.len().is_multiple_of(2) {
+        return None;
+    }
+    let pairs = sample.len() / 2;
+    let even = sample.iter().step_by(2).filter(|b| **b == 0).count();
+    let odd = sample.iter().skip(1).step_by(2).filter(|b| **b == 0).count();
+    if odd * 2 > pairs && even == 0 {
+        Some(UTF_16LE)
+    } else if even * 2 > pairs && odd == 0 {
+        Some(UTF_16BE)
+    } else {
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(bytes: &[u8]) -> (String, TextEncoding) {
+        let (text, encoding, lossy) = TextEncoding::decode(bytes);
+        assert!(!lossy);
+        assert_eq!(encoding.encode(&text).unwrap(), bytes, "{} didn't round trip", encoding.name());
+        (text, encoding)
+    }
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(round_trip("héllo".as_bytes()), ("héllo".to_string(), TextEncoding::default()));
+        assert_eq!(round_trip(b"\xEF\xBB\xBFhi").1, TextEncoding {encoding: UTF_8, bom: true});
+        assert_eq!(round_trip(b"\xFF\xFEh\0i\0"), ("hi".to_string(), TextEncoding {encoding: UTF_16LE, bom: true}));
+        assert_eq!(round_trip(b"\0h\0i\0\n"), ("hi\n".to_string(), TextEncoding {encoding: UTF_16BE, bom: false}));
+        // latin-1
+        assert_eq!(round_trip(b"caf\xE9"), ("café".to_string(), TextEncoding {encoding: WINDOWS_1252, bom: false}));
+    }
+
+    #[test]
+    fn test_invalid_bytes_round_trip() {
+        // not valid in anything, including the bytes 1252 leaves undefined
+        let bytes = [0x81, 0x00, 0xFF, 0xC3, 0x28, 0x8D, 0x90, 0x9D, 0x8F];
+        assert_eq!(round_trip(&bytes).1.encoding, WINDOWS_1252);
+        // a BOM followed by broken UTF-16 still comes back the same
+        round_trip(b"\xFF\xFE\x00\xD8");
+    }
+
+    #[test]
+    fn test_mostly_utf8() {
+        // one bad byte in a UTF-8 file doesn't make the rest mojibake
+        let bytes = ["naïve café — ".as_bytes(), b"\xFF"].concat();
+        let (text, encoding, lossy) = TextEncoding::decode(&bytes);
+        assert_eq!((text.as_str(), encoding, lossy), ("naïve café — \u{FFFD}", TextEncoding::default(), true));
+        // but a latin-1 one is still latin-1
+        assert_eq!(round_trip(b"na\xEFve caf\xE9").1.encoding, WINDOWS_1252);
+    }
+
+    #[test]
+    fn test_encode_unrepresentable() {
+        let latin1 = TextEncoding::for_label("latin1").unwrap();
+        assert_eq!(latin1.encode("café").unwrap(), b"caf\xE9");
+        assert!(latin1.encode("日本").is_err());
+        assert!(TextEncoding::for_label("replacement").is_none());
+        assert!(TextEncoding::for_label("nonsense").is_none());
+    }
+
+    #[test]
+    fn test_line_endings() {
+        assert_eq!(LineEnding::normalize("a\nb\n".to_string()), ("a\nb\n".to_string(), LineEnding::Lf, false));
+        let (text, ending, mixed) = LineEnding::normalize("a\r\nb\r\n".to_string());
+        assert_eq!((text.as_str(), ending, mixed), ("a\nb\n", LineEnding::Crlf, false));
+        assert_eq!(ending.apply(&text), "a\r\nb\r\n");
+        assert_eq!(LineEnding::normalize("a\rb\r".to_string()), ("a\nb\n".to_string(), LineEnding::Cr, false));
+        // a stray `\r` is just text, so it's saved as it was
+        let (text, ending, mixed) = LineEnding::normalize("a\r\nb\rc\r\n".to_string());
+        assert_eq!((text.as_str(), ending, mixed), ("a\nb\rc\n", LineEnding::Crlf, false));
+        let (text, ending, mixed) = LineEnding::normalize("a\nb\r\nc\n".to_string());
+        assert_eq!((ending.apply(&text).as_ref(), ending, mixed), ("a\nb\r\nc\n", LineEnding::Lf, false));
+        // mostly CRLF, the stray LF would become one too
+        let (text, ending, mixed) = LineEnding::normalize("a\r\nb\nc\r\n".to_string());
+        assert_eq!((ending.apply(&text).as_ref(), ending, mixed), ("a\r\nb\r\nc\r\n", LineEnding::Crlf, true));
+    }
+}
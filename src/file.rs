@@ -0,0 +1,187 @@
+// Saving writes a temp file next to the real one and renames it over the top,
+// so a crash or full disk halfway through never leaves a half written file.
+
+use std::fs::{self, File, OpenOptions};
+use std::io::{self, Write};
+use std::os::unix::fs::MetadataExt;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use sha2::{Digest, Sha256};
+
+static TEMP_COUNTER: AtomicUsize = AtomicUsize::new(0);
+
+// the longest `path_key`, a file name can be 255 bytes and this leaves room
+// for whatever's put after it (`.json`, `.<pid>`)
+const MAX_KEY: usize = 255 - 16;
+
+// write `chunks` to `path`. If `path` is a symlink the file it points at is
+// replaced (the link stays a link), and the old file's permissions and owner
+// are kept
+pub fn atomic_write(path: &Path, chunks: impl Iterator<Item = impl AsRef<[u8]>>) -> io::Result<()> {
+    let target = resolve_symlinks(path)?;
+    let dir = match target.parent() {
+        Some(dir) if dir != Path::new("") => dir.to_path_buf(),
+        _ => PathBuf::from("."),
+    };
+    let old = fs::metadata(&target).ok();
+
+    let tmp = temp_path(&dir, &target);
+    let result = (|| {
+        let mut file = OpenOptions::new().write(true).create_new(true).open(&tmp)?;
+        for chunk in chunks {
+            file.write_all(chunk.as_ref())?;
+        }
+        if let Some(old) = &old {
+            file.set_permissions(old.permissions())?;
+            // only root (or the owner, for the group) can do this, a file we can
+            // write but not chown just ends up owned by us
+            if let Err(e) = std::os::unix::fs::fchown(&file, Some(old.uid()), Some(old.gid())) {
+                log::warn!("couldn't keep the owner of {}: {}", target.display(), e);
+            }
+        }
+        file.sync_all()?;
+        fs::rename(&tmp, &target)
+    })();
+    if result.is_err() {
+        let _ = fs::remove_file(&tmp);
+    }
+    result?;
+
+    // make the rename itself durable
+    File::open(&dir)?.sync_all()
+}
+
+// follow the chain of links to the real file, which doesn't have to exist yet
+fn resolve_symlinks(path: &Path) -> io::Result<PathBuf> {
+    let mut path = path.to_path_buf();
+    // same limit as the kernel, so a loop is an error instead of a hang
+    for _ in 0..40 {
+        match fs::symlink_metadata(&path) {
+            Ok(meta) if meta.file_type().is_symlink() => {
+                let link = fs::read_link(&path)?;
+                path = match path.parent() {
+                    Some(parent) => parent.join(link),
+                    None => link,
+                };
+            },
+            Ok(_) => return Ok(path),
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return Ok(path),
+            Err(e) => return Err(e),
+        }
+    }
+    Err(io::Error::other(format!("too many levels of symbolic links: {}", path.display())))
+}
+
+fn temp_path(dir: &Path, target: &Path) -> PathBuf {
+    let name = target.file_name().map(|n| n.to_string_lossy().into_owned()).unwrap_or_default();
+    let n = TEMP_COUNTER.fetch_add(1, Ordering::Relaxed);
+    dir.join(format!(".{name}.chop-{}-{n}.tmp", std::process::id()))
+}
+
+// where chop keeps `what` between runs (journals, sessions, ...), None if
+// there's no home to put it in
+pub fn state_dir(what: &str) -> Option<PathBuf> {
+    Some(dirs::state_dir().or_else(dirs::data_local_dir)?.join("chop").join(what))
+}
+
+// a file name for `path` (made absolute), to keep something about it in a
+// `state_dir`: the path percent-encoded (just % and /), so it can still be read
+// and two paths never get the same one. One that's too long is cut short, and
+// ends with a hash of the whole thing instead
+pub fn path_key(path: &Path) -> String {
+    let path = std::path::absolute(path).unwrap_or_else(|_| path.to_path_buf());
+    let key = path.to_string_lossy().replace('%', "%25").replace('/', "%2F");
+    if key.len() <= MAX_KEY {
+        return key;
+    }
+    let hash = format!("{:x}", Sha256::digest(key.as_bytes()));
+    let mut end = MAX_KEY - hash.len() - 1;
+    while !key.is_char_boundary(end) {
+        end -= 1;
+    }
+    format!("{}-{hash}", &key[..end])
+}
+
+// a fresh directory for a test's files, gone again once the test's done with
+// it (even if it failed)
+#[cfg(test)]
+pub(crate) struct TestDir(PathBuf);
+
+#[cfg(test)]
+impl TestDir {
+    pub(crate) fn new(name: &str) -> Self {
+        let dir = std::env::temp_dir().join(format!("chop-test-{name}-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        fs::create_dir_all(&dir).unwrap();
+        TestDir(dir)
+    }
+}
+
+#[cfg(test)]
+impl std::ops::Deref for TestDir {
+    type Target = Path;
+
+    fn deref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl AsRef<Path> for TestDir {
+    fn as_ref(&self) -> &Path {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+impl Drop for TestDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::{symlink, PermissionsExt};
+
+    #[test]
+    fn test_atomic_write() {
+        let dir = TestDir::new("atomic-write");
+        let path = dir.join("a.txt");
+        fs::write(&path, "a much longer file than what we save").unwrap();
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        atomic_write(&path, ["short", "er"].into_iter()).unwrap();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "shorter");
+        assert_eq!(fs::metadata(&path).unwrap().permissions().mode() & 0o777, 0o640);
+        // no temp files left behind
+        assert_eq!(fs::read_dir(&dir).unwrap().count(), 1);
+    }
+
+    #[test]
+    fn test_write_through_symlink() {
+        let dir = TestDir::new("symlink");
+        fs::create_dir(dir.join("real")).unwrap();
+        fs::write(dir.join("real/b.txt"), "old").unwrap();
+        symlink("real/b.txt", dir.join("link")).unwrap();
+
+        atomic_write(&dir.join("link"), ["new"].into_iter()).unwrap();
+        assert!(fs::symlink_metadata(dir.join("link")).unwrap().file_type().is_symlink());
+        assert_eq!(fs::read_to_string(dir.join("real/b.txt")).unwrap(), "new");
+    }
+
+    #[test]
+    fn test_path_key() {
+        assert_eq!(path_key(Path::new("/tmp/a.txt")), "%2Ftmp%2Fa.txt");
+        // a % in the name isn't a /
+        assert_ne!(path_key(Path::new("/a%b")), path_key(Path::new("/a/b")));
+        assert_ne!(path_key(Path::new("/a%/b")), path_key(Path::new("/a/%b")));
+        assert_ne!(path_key(Path::new("/a%2Fb")), path_key(Path::new("/a/b")));
+        let deep = |last: &str| PathBuf::from(format!("/{}/{last}", "é".repeat(200)));
+        let (a, b) = (path_key(&deep("a")), path_key(&deep("b")));
+        assert!(a.len() <= MAX_KEY && b.len() <= MAX_KEY);
+        assert!(a.starts_with("%2Féé"));
+        assert_ne!(a, b);
+    }
+}
@@ -0,0 +1,147 @@
+// Binary files are opened as a hex dump, which is what's in the buffer. Typing
+// overwrites the nibble under the cursor, and saving parses the bytes back out.
+
+use crate::encoding;
+
+pub const BYTES_PER_LINE: usize = 16;
+// "00000010: "
+const HEX_START: usize = 10;
+// after "xx " for each byte and a space
+const ASCII_START: usize = HEX_START + BYTES_PER_LINE * 3 + 1;
+// a full line, with its newline
+const LINE_LEN: usize = ASCII_START + BYTES_PER_LINE + 1;
+pub const NIBBLES_PER_LINE: usize = BYTES_PER_LINE * 2;
+
+// lots of control characters, or any zero bytes in what isn't UTF-16
+pub fn is_binary(bytes: &[u8]) -> bool {
+    if encoding_rs::Encoding::for_bom(bytes).is_some() || encoding::guess_utf16(bytes).is_some() {
+        return false;
+    }
+    let sample = &bytes[..bytes.len().min(8192)];
+    let control = sample.iter().filter(|b| **b < 0x20 && !matches!(b, b'\t' | b'\n' | b'\r' | 0x0c | 0x1b)).count();
+    sample.contains(&0) || control * 10 > sample.len()
+}
+
+// every line is laid out the same (the last one is padded to line up), so a
+// position in the buffer maps straight to a nibble of the file and back:
+//
+//   00000010: 7f 45 4c 46 02 01 01 00 00 00 00 00 00 00 00 00  .ELF............
+pub fn dump(bytes: &[u8]) -> String {
+    let mut out = String::with_capacity(bytes.len().div_ceil(BYTES_PER_LINE) * LINE_LEN);
+    for (i, line) in bytes.chunks(BYTES_PER_LINE).enumerate() {
+        out.push_str(&format!("{:08x}: ", i * BYTES_PER_LINE));
+        for j in 0..BYTES_PER_LINE {
+            match line.get(j) {
+                Some(b) => out.push_str(&format!("{b:02x} ")),
+                None => out.push_str("   "),
+            }
+        }
+        out.push(' ');
+        out.extend(line.iter().map(|b| ascii(*b)));
+        out.push('\n');
+    }
+    out
+}
+
+// the bytes back out of a dump
+pub fn parse(text: &str) -> Result<Vec<u8>, String> {
+    let mut bytes = Vec::with_capacity(text.len() / LINE_LEN * BYTES_PER_LINE);
+    for (i, line) in text.lines().enumerate() {
+        let hex = line.get(HEX_START..line.len().min(ASCII_START)).unwrap_or("");
+        for digits in hex.split_whitespace() {
+            let b = u8::from_str_radix(digits, 16).map_err(|_| format!("line {} of the hex dump has {digits:?} in it", i + 1))?;
+            bytes.push(b);
+        }
+    }
+    Ok(bytes)
+}
+
+fn ascii(b: u8) -> char {
+    if b.is_ascii_graphic() || b == b' ' { b as char } else { '.' }
+}
+
+// how many bytes are in a dump `len` long
+pub fn byte_count(len: usize) -> usize {
+    let partial = len % LINE_LEN;
+    len / LINE_LEN * BYTES_PER_LINE + partial.saturating_sub(ASCII_START + 1)
+}
+
+// the nibble at (or nearest to) an offset in a dump `len` long, None if it's empty
+pub fn nibble_at(offset: usize, len: usize) -> Option<usize> {
+    let last = (byte_count(len) * 2).checked_sub(1)?;
+    let (line, col) = (offset / LINE_LEN, offset % LINE_LEN);
+    let in_line = match col.checked_sub(HEX_START) {
+        None => 0,
+        Some(rel) if rel / 3 >= BYTES_PER_LINE => NIBBLES_PER_LINE - 1,
+        Some(rel) => rel / 3 * 2 + (rel % 3).min(1),
+    };
+    Some((line * NIBBLES_PER_LINE + in_line).min(last))
+}
+
+pub fn offset_of_nibble(nibble: usize) -> usize {
+    let (line, in_line) = (nibble / NIBBLES_PER_LINE, nibble % NIBBLES_PER_LINE);
+    line * LINE_LEN + HEX_START + in_line / 2 * 3 + in_line % 2
+}
+
+// where the ascii column shows the byte of the nibble
+pub fn ascii_offset(nibble: usize) -> usize {
+    let (line, in_line) = (nibble / NIBBLES_PER_LINE, nibble % NIBBLES_PER_LINE);
+    line * LINE_LEN + ASCII_START + in_line / 2
+}
+
+// the offset of the nibble `nibbles` away from the one at `offset` (stopping at
+// either end), so 0 snaps an offset to its nibble
+pub fn move_by(offset: usize, nibbles: i64, len: usize) -> Option<usize> {
+    let last = (byte_count(len) * 2).checked_sub(1)?;
+    let n = nibble_at(offset, len)? as i64 + nibbles;
+    Some(offset_of_nibble(n.clamp(0, last as i64) as usize))
+}
+
+// the ascii column's char for the byte `hi` `lo` (two hex digits)
+pub fn ascii_of(hi: u8, lo: u8) -> Option<char> {
+    let digits = [hi, lo];
+    let b = u8::from_str_radix(std::str::from_utf8(&digits).ok()?, 16).ok()?;
+    Some(ascii(b))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_dump_round_trip() {
+        let bytes: Vec<u8> = (0..=255u8).chain([0x7f, b'A', 0]).collect();
+        let text = dump(&bytes);
+        assert_eq!(text.lines().nth(4).unwrap(), "00000040: 40 41 42 43 44 45 46 47 48 49 4a 4b 4c 4d 4e 4f  @ABCDEFGHIJKLMNO");
+        assert_eq!(text.lines().last().unwrap(), format!("00000100: 7f 41 00{}  .A.", " ".repeat(39)));
+        assert_eq!(parse(&text).unwrap(), bytes);
+        assert_eq!(byte_count(text.len()), bytes.len());
+        assert_eq!(dump(&[]), "");
+    }
+
+    #[test]
+    fn test_nibbles() {
+        let text = dump(&[0xab; 20]);
+        let len = text.len();
+        assert_eq!(offset_of_nibble(3), 14);
+        assert_eq!(&text[offset_of_nibble(3)..offset_of_nibble(3) + 1], "b");
+        assert_eq!(nibble_at(14, len), Some(3));
+        // the space after a byte goes to its second digit, the offset column to the first
+        assert_eq!(nibble_at(15, len), Some(3));
+        assert_eq!(nibble_at(LINE_LEN + 2, len), Some(32));
+        // past the last byte
+        assert_eq!(nibble_at(len, len), Some(39));
+        assert_eq!(nibble_at(0, 0), None);
+        assert_eq!(&text[ascii_offset(33)..ascii_offset(33) + 1], ".");
+        assert_eq!(move_by(0, 0, len), Some(HEX_START));
+        assert_eq!(move_by(offset_of_nibble(3), NIBBLES_PER_LINE as i64, len), Some(offset_of_nibble(35)));
+        assert_eq!(move_by(offset_of_nibble(3), -5, len), Some(offset_of_nibble(0)));
+    }
+
+    #[test]
+    fn test_is_binary() {
+        assert!(is_binary(b"\x7fELF\x02\x01\x01\0\0\0"));
+        assert!(!is_binary(b"plain text\n\twith a tab\n"));
+        assert!(!is_binary(b"h\0i\0\n\0"));
+    }
+}
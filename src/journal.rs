@@ -0,0 +1,201 @@
+// Unsaved edits are journaled to disk as they're made, so if we crash the next
+// open of the file can offer them back.
+
+use std::collections::HashMap;
+use std::fs::{self, File};
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread::JoinHandle;
+use im::OrdMap;
+
+use crate::buffer::{BufferId, TextBuffer};
+use crate::file;
+use crate::observer::BufferEvent;
+use crate::registry::Registry;
+
+// where the journals go, None if there's no home to put them in
+pub fn dir() -> Option<PathBuf> {
+    file::state_dir("journal")
+}
+
+// the journal's name without the pid
+fn prefix(path: &Path) -> String {
+    format!("{}.", file::path_key(path))
+}
+
+struct Open {
+    path: PathBuf,
+    file: File,
+    // the buffer's version the journal is up to
+    version: u64,
+}
+
+// A journal is `<dir>/<the file's path_key>.<pid>`. It's the file's path on a
+// line, then records:
+//
+//   S <len>\n<text>\n                what the buffer had
+//   E <start> <end> <len>\n<text>\n  replace start..end with the text
+//
+// It's deleted when the buffer is saved, goes back to unmodified or is closed,
+// so one whose process is gone means that process crashed
+pub struct Writer {
+    dir: PathBuf,
+    open: HashMap<BufferId, Open>,
+}
+
+impl Writer {
+    pub fn new(dir: PathBuf) -> Self {
+        Self {dir, open: HashMap::new()}
+    }
+
+    pub fn handle(&mut self, buffers: &OrdMap<BufferId, TextBuffer>, event: &BufferEvent) -> io::Result<()> {
+        let change = match event {
+            BufferEvent::Changed(change) => change,
+            BufferEvent::Saved { buffer_id, .. } | BufferEvent::Closed { buffer_id, .. } => return self.remove(*buffer_id),
+        };
+        let id = change.buffer_id;
+        // the buffer is probably past this change by now, but it's what it is now that matters
+        let Some(buffer) = buffers.get(&id) else {
+            return self.remove(id);
+        };
+        let Some(fi) = buffer.file.as_ref().filter(|_| buffer.is_modified()) else {
+            return self.remove(id);
+        };
+        if !self.open.contains_key(&id) {
+            fs::create_dir_all(&self.dir)?;
+            let path = self.dir.join(format!("{}{}", prefix(&fi.filename), std::process::id()));
+            let mut file = File::create(&path)?;
+            let text = buffer.contents.to_string();
+            // one write each, so a crash can only cut off the end
+            file.write_all(format!("{}\nS {}\n{text}\n", fi.filename.display(), text.len()).as_bytes())?;
+            self.open.insert(id, Open {path, file, version: buffer.version});
+        }
+        let open = self.open.get_mut(&id).unwrap();
+        // already in what was written when it was opened
+        if change.version <= open.version {
+            return Ok(());
+        }
+        let mut record = vec![];
+        for e in &change.edits {
+            record.extend_from_slice(format!("E {} {} {}\n{}\n", e.start, e.old_end, e.text.len(), e.text).as_bytes());
+        }
+        open.file.write_all(&record)?;
+        open.version = change.version;
+        Ok(())
+    }
+
+    fn remove(&mut self, id: BufferId) -> io::Result<()> {
+        let Some(open) = self.open.remove(&id) else {
+            return Ok(());
+        };
+        match fs::remove_file(&open.path) {
+            Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                // left behind it'd be offered as a crash once we've gone, so
+                // it's kept for the buffer's next save to try again
+                self.open.insert(id, open);
+                Err(e)
+            },
+            _ => Ok(()),
+        }
+    }
+}
+
+// write the journals from `events` until there are no more (see `Observers::close`)
+pub fn spawn(dir: PathBuf, events: mpsc::Receiver<Arc<BufferEvent>>, buffers: Arc<Registry<TextBuffer>>) -> JoinHandle<()> {
+    std::thread::spawn(move || {
+        let mut writer = Writer::new(dir);
+        for event in events {
+            if let Err(e) = writer.handle(&buffers.get(), &event) {
+                log::error!("couldn't write the journal for buffer {}: {e}", event.buffer_id());
+            }
+        }
+    })
+}
+
+// the newest journal of `path` left behind by a process that isn't running
+pub fn find_orphan(dir: &Path, path: &Path) -> Option<PathBuf> {
+    let prefix = prefix(path);
+    fs::read_dir(dir).ok()?
+        .filter_map(|entry| entry.ok())
+        .filter(|entry| {
+            let name = entry.file_name().to_string_lossy().into_owned();
+            name.strip_prefix(&prefix).and_then(|pid| pid.parse().ok()).is_some_and(|pid| !is_running(pid))
+        })
+        .max_by_key(|entry| entry.metadata().and_then(|m| m.modified()).ok())
+        .map(|entry| entry.path())
+}
+
+fn is_running(pid: i32) -> bool {
+    // signal 0 only checks it's there. EPERM means it is, it's just someone else's
+    let alive = unsafe { libc::kill(pid, 0) } == 0;
+    alive || io::Error::last_os_error().raw_os_error() == Some(libc::EPERM)
+}
+
+// what the buffer had when the journal was last written to
+pub fn recover(journal: &Path) -> io::Result<String> {
+    let bytes = fs::read(journal)?;
+    let invalid = || io::Error::new(io::ErrorKind::InvalidData, format!("{} isn't a journal", journal.display()));
+    let mut rest = &bytes[bytes.iter().position(|b| *b == b'\n').ok_or_else(invalid)? + 1..];
+    let mut text: Option<String> = None;
+    // a record that doesn't make sense is where the crash cut it off
+    while let Some(end) = rest.iter().position(|b| *b == b'\n') {
+        let header = String::from_utf8_lossy(&rest[..end]).into_owned();
+        let fields: Vec<usize> = header.split(' ').skip(1).filter_map(|n| n.parse().ok()).collect();
+        let body = &rest[end + 1..];
+        let Some(len) = fields.last().copied().filter(|len| body.len() > *len && body[*len] == b'\n') else {
+            break;
+        };
+        let Ok(s) = std::str::from_utf8(&body[..len]) else {
+            break;
+        };
+        match (header.split(' ').next(), fields.as_slice(), text.as_mut()) {
+            (Some("S"), [_], _) => text = Some(s.to_string()),
+            (Some("E"), [start, end, _], Some(text)) if start <= end && *end <= text.len() && text.is_char_boundary(*start) && text.is_char_boundary(*end) => {
+                text.replace_range(start..end, s);
+            },
+            _ => break,
+        }
+        rest = &body[len + 1..];
+    }
+    text.ok_or_else(invalid)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::TestDir;
+    use crop::Rope;
+    use crate::observer::{BufferChange, Edit};
+
+    #[test]
+    fn test_write_and_recover() {
+        let dir = TestDir::new("journal");
+        let path = Path::new("/tmp/some/file.txt");
+        let mut buffer = TextBuffer {contents: Rope::from("hello"), version: 1, ..TextBuffer::new_file(path.to_str().unwrap())};
+        buffer.file.as_mut().unwrap().is_modified = true;
+        let mut writer = Writer::new(dir.to_path_buf());
+
+        let edit = Edit::insert(&Rope::from("hello"), 5, " world");
+        let changed = |version, edits| BufferEvent::Changed(BufferChange {buffer_id: 0, version, edits, lines: 0..1});
+        // the snapshot is taken at version 1, so that change is already in it
+        writer.handle(&OrdMap::unit(0, buffer.clone()), &changed(1, vec![])).unwrap();
+        writer.handle(&OrdMap::unit(0, buffer.clone()), &changed(2, vec![edit])).unwrap();
+        let journal = writer.open[&0].path.clone();
+        assert_eq!(recover(&journal).unwrap(), "hello world");
+
+        // a crash in the middle of writing an edit
+        let mut file = fs::OpenOptions::new().append(true).open(&journal).unwrap();
+        file.write_all(b"E 0 5 3\nby").unwrap();
+        assert_eq!(recover(&journal).unwrap(), "hello world");
+
+        // ours, and we're still running
+        assert_eq!(find_orphan(&dir, path), None);
+        let orphan = dir.join(format!("{}{}", prefix(path), i32::MAX));
+        fs::rename(&journal, &orphan).unwrap();
+        assert_eq!(find_orphan(&dir, path), Some(orphan));
+        // it's already gone, that's fine
+        writer.handle(&OrdMap::unit(0, buffer), &BufferEvent::Saved {buffer_id: 0, version: 2, path: Arc::from(path)}).unwrap();
+        assert!(writer.open.is_empty());
+    }
+}
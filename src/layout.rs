@@ -0,0 +1,387 @@
+// The panes in a window are laid out as a tree of splits. Each split divides
+// its area between two children, `ratio` of it going to the first one.
+
+use crate::pane::PaneId;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SplitDir {
+    // one pane above the other (vim's `:split`)
+    Horizontal,
+    // side by side (vim's `:vsplit`)
+    Vertical,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Left,
+    Right,
+    Up,
+    Down,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LayoutOp {
+    Split(SplitDir),
+    Close,
+    // close even if it loses unsaved changes
+    ForceClose,
+    Focus(Direction),
+    FocusNext,
+    Swap(Direction),
+    // grow (or shrink if negative) the focused pane by some percent of its split
+    Resize(SplitDir, i32),
+    Equalize,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Viewport {
+    pub x: f32,
+    pub y: f32,
+    pub width: f32,
+    pub height: f32,
+}
+
+impl Viewport {
+    pub fn contains(&self, x: f32, y: f32) -> bool {
+        x >= self.x && x < self.x + self.width && y >= self.y && y < self.y + self.height
+    }
+
+    fn split(&self, dir: SplitDir, ratio: f32) -> (Viewport, Viewport) {
+        match dir {
+            SplitDir::Horizontal => {
+                let h = (self.height * ratio).round();
+                (Viewport {height: h, ..*self}, Viewport {y: self.y + h, height: self.height - h, ..*self})
+            },
+            SplitDir::Vertical => {
+                let w = (self.width * ratio).round();
+                (Viewport {width: w, ..*self}, Viewport {x: self.x + w, width: self.width - w, ..*self})
+            },
+        }
+    }
+}
+
+const MIN_RATIO: f32 = 0.1;
+const MAX_RATIO: f32 = 0.9;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Node {
+    Leaf(PaneId),
+    Split {
+        dir: SplitDir,
+        ratio: f32,
+        first: Box<Node>,
+        second: Box<Node>,
+    },
+}
+
+impl Node {
+    fn leaves(&self, out: &mut Vec<PaneId>) {
+        match self {
+            Node::Leaf(id) => out.push(*id),
+            Node::Split {first, second, ..} => {
+                first.leaves(out);
+                second.leaves(out);
+            },
+        }
+    }
+
+    fn first_leaf(&self) -> PaneId {
+        match self {
+            Node::Leaf(id) => *id,
+            Node::Split {first, ..} => first.first_leaf(),
+        }
+    }
+
+    fn contains(&self, pane: PaneId) -> bool {
+        match self {
+            Node::Leaf(id) => *id == pane,
+            Node::Split {first, second, ..} => first.contains(pane) || second.contains(pane),
+        }
+    }
+
+    fn viewports(&self, area: Viewport, out: &mut Vec<(PaneId, Viewport)>) {
+        match self {
+            Node::Leaf(id) => out.push((*id, area)),
+            Node::Split {dir, ratio, first, second} => {
+                let (a, b) = area.split(*dir, *ratio);
+                first.viewports(a, out);
+                second.viewports(b, out);
+            },
+        }
+    }
+
+    fn dividers(&self, area: Viewport, out: &mut Vec<(SplitDir, Viewport)>) {
+        if let Node::Split {dir, ratio, first, second} = self {
+            let (a, b) = area.split(*dir, *ratio);
+            out.push((*dir, b));
+            first.dividers(a, out);
+            second.dividers(b, out);
+        }
+    }
+
+    fn map_leaves(&mut self, f: &impl Fn(PaneId) -> PaneId) {
+        match self {
+            Node::Leaf(id) => *id = f(*id),
+            Node::Split {first, second, ..} => {
+                first.map_leaves(f);
+                second.map_leaves(f);
+            },
+        }
+    }
+
+    // replace the leaf `pane` with `node`, returns false if it isn't in the tree
+    fn replace(&mut self, pane: PaneId, node: Node) -> bool {
+        match self {
+            Node::Leaf(id) if *id == pane => {
+                *self = node;
+                true
+            },
+            Node::Leaf(_) => false,
+            Node::Split {first, second, ..} => {
+                if first.contains(pane) {
+                    first.replace(pane, node)
+                } else {
+                    second.replace(pane, node)
+                }
+            },
+        }
+    }
+
+    // take `pane` out of the tree, its sibling takes the place of their parent.
+    // Returns the new tree (None if `pane` was the whole tree)
+    fn remove(self, pane: PaneId) -> Option<Node> {
+        match self {
+            Node::Leaf(id) if id == pane => None,
+            Node::Leaf(id) => Some(Node::Leaf(id)),
+            Node::Split {dir, ratio, first, second} => {
+                match (first.remove(pane), second.remove(pane)) {
+                    (Some(first), Some(second)) => Some(Node::Split {dir, ratio, first: Box::new(first), second: Box::new(second)}),
+                    (Some(only), None) | (None, Some(only)) => Some(only),
+                    (None, None) => None,
+                }
+            },
+        }
+    }
+
+    // returns true once the resize has been done by some split on the way to `pane`
+    fn resize(&mut self, pane: PaneId, resize_dir: SplitDir, delta: f32) -> bool {
+        match self {
+            Node::Leaf(_) => false,
+            Node::Split {dir, ratio, first, second} => {
+                let in_first = first.contains(pane);
+                if !in_first && !second.contains(pane) {
+                    return false;
+                }
+                let child = if in_first { first } else { second };
+                if child.resize(pane, resize_dir, delta) {
+                    return true;
+                }
+                if *dir != resize_dir {
+                    return false;
+                }
+                let delta = if in_first { delta } else { -delta };
+                *ratio = (*ratio + delta).clamp(MIN_RATIO, MAX_RATIO);
+                true
+            },
+        }
+    }
+
+    fn equalize(&mut self) {
+        if let Node::Split {ratio, first, second, ..} = self {
+            *ratio = 0.5;
+            first.equalize();
+            second.equalize();
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Layout {
+    pub root: Node,
+    pub focused: PaneId,
+}
+
+impl Layout {
+    pub fn new(pane_id: PaneId) -> Self {
+        Self {
+            root: Node::Leaf(pane_id),
+            focused: pane_id,
+        }
+    }
+
+    // in order: top to bottom, left to right
+    pub fn panes(&self) -> Vec<PaneId> {
+        let mut out = vec![];
+        self.root.leaves(&mut out);
+        out
+    }
+
+    pub fn contains(&self, pane: PaneId) -> bool {
+        self.root.contains(pane)
+    }
+
+    pub fn viewports(&self, area: Viewport) -> Vec<(PaneId, Viewport)> {
+        let mut out = vec![];
+        self.root.viewports(area, &mut out);
+        out
+    }
+
+    // the area of the second child of every split, the divider is along its top/left edge
+    pub fn dividers(&self, area: Viewport) -> Vec<(SplitDir, Viewport)> {
+        let mut out = vec![];
+        self.root.dividers(area, &mut out);
+        out
+    }
+
+    pub fn pane_at(&self, area: Viewport, x: f32, y: f32) -> Option<PaneId> {
+        self.viewports(area).into_iter().find(|(_, v)| v.contains(x, y)).map(|(id, _)| id)
+    }
+
+    // the new pane goes below/right of the focused one and gets focus
+    pub fn split(&mut self, dir: SplitDir, new_pane: PaneId) {
+        let old = self.focused;
+        let node = Node::Split {
+            dir,
+            ratio: 0.5,
+            first: Box::new(Node::Leaf(old)),
+            second: Box::new(Node::Leaf(new_pane)),
+        };
+        self.root.replace(old, node);
+        self.focused = new_pane;
+    }
+
+    // returns false (and does nothing) if `pane` is the last one
+    pub fn close(&mut self, pane: PaneId) -> bool {
+        if self.root == Node::Leaf(pane) || !self.contains(pane) {
+            return false;
+        }
+        let root = std::mem::replace(&mut self.root, Node::Leaf(pane));
+        self.root = root.remove(pane).expect("there was more than one pane");
+        if self.focused == pane {
+            self.focused = self.root.first_leaf();
+        }
+        true
+    }
+
+    // the closest pane in `dir` from the focused one that it shares an edge with
+    pub fn neighbour(&self, area: Viewport, dir: Direction) -> Option<PaneId> {
+        let viewports = self.viewports(area);
+        let (_, cur) = *viewports.iter().find(|(id, _)| *id == self.focused)?;
+        let overlaps = |a0: f32, a1: f32, b0: f32, b1: f32| a0 < b1 && b0 < a1;
+        viewports.iter()
+            .filter(|(id, _)| *id != self.focused)
+            .filter_map(|(id, v)| {
+                let gap = match dir {
+                    Direction::Left if overlaps(v.y, v.y + v.height, cur.y, cur.y + cur.height) => cur.x - (v.x + v.width),
+                    Direction::Right if overlaps(v.y, v.y + v.height, cur.y, cur.y + cur.height) => v.x - (cur.x + cur.width),
+                    Direction::Up if overlaps(v.x, v.x + v.width, cur.x, cur.x + cur.width) => cur.y - (v.y + v.height),
+                    Direction::Down if overlaps(v.x, v.x + v.width, cur.x, cur.x + cur.width) => v.y - (cur.y + cur.height),
+                    _ => return None,
+                };
+                // prefer the pane lined up with our top left corner when there's a few
+                let misalign = match dir {
+                    Direction::Left | Direction::Right => (v.y - cur.y).abs(),
+                    Direction::Up | Direction::Down => (v.x - cur.x).abs(),
+                };
+                (gap >= -0.5).then_some((gap, misalign, *id))
+            })
+            .min_by(|a, b| (a.0, a.1).partial_cmp(&(b.0, b.1)).unwrap())
+            .map(|(_, _, id)| id)
+    }
+
+    pub fn focus(&mut self, area: Viewport, dir: Direction) {
+        if let Some(id) = self.neighbour(area, dir) {
+            self.focused = id;
+        }
+    }
+
+    pub fn focus_next(&mut self) {
+        let panes = self.panes();
+        let i = panes.iter().position(|id| *id == self.focused).unwrap_or(0);
+        self.focused = panes[(i + 1) % panes.len()];
+    }
+
+    // trade places with the neighbour in `dir`, focus stays with the pane
+    pub fn swap(&mut self, area: Viewport, dir: Direction) {
+        if let Some(other) = self.neighbour(area, dir) {
+            let cur = self.focused;
+            self.root.map_leaves(&|id| if id == cur { other } else if id == other { cur } else { id });
+        }
+    }
+
+    pub fn resize(&mut self, dir: SplitDir, percent: i32) {
+        self.root.resize(self.focused, dir, percent as f32 / 100.);
+    }
+
+    pub fn equalize(&mut self) {
+        self.root.equalize();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AREA: Viewport = Viewport {x: 0., y: 0., width: 800., height: 600.};
+
+    #[test]
+    fn test_split_and_close() {
+        let mut layout = Layout::new(0);
+        layout.split(SplitDir::Vertical, 1);
+        layout.split(SplitDir::Horizontal, 2);
+        assert_eq!(layout.panes(), vec![0, 1, 2]);
+        assert_eq!(layout.focused, 2);
+        assert_eq!(layout.viewports(AREA), vec![
+            (0, Viewport {x: 0., y: 0., width: 400., height: 600.}),
+            (1, Viewport {x: 400., y: 0., width: 400., height: 300.}),
+            (2, Viewport {x: 400., y: 300., width: 400., height: 300.}),
+        ]);
+        assert_eq!(layout.pane_at(AREA, 500., 450.), Some(2));
+
+        assert!(layout.close(1));
+        assert_eq!(layout.panes(), vec![0, 2]);
+        assert!(layout.close(2));
+        assert_eq!(layout.focused, 0);
+        assert!(!layout.close(0));
+        assert_eq!(layout, Layout::new(0));
+    }
+
+    #[test]
+    fn test_focus_and_swap() {
+        let mut layout = Layout::new(0);
+        layout.split(SplitDir::Vertical, 1);
+        layout.split(SplitDir::Horizontal, 2);
+        layout.focus(AREA, Direction::Up);
+        assert_eq!(layout.focused, 1);
+        layout.focus(AREA, Direction::Up);
+        assert_eq!(layout.focused, 1);
+        layout.focus(AREA, Direction::Left);
+        assert_eq!(layout.focused, 0);
+        layout.focus(AREA, Direction::Right);
+        assert_eq!(layout.focused, 1);
+
+        layout.swap(AREA, Direction::Left);
+        assert_eq!(layout.panes(), vec![1, 0, 2]);
+        assert_eq!(layout.focused, 1);
+        layout.focus_next();
+        assert_eq!(layout.focused, 0);
+    }
+
+    #[test]
+    fn test_resize() {
+        let mut layout = Layout::new(0);
+        layout.split(SplitDir::Vertical, 1);
+        layout.split(SplitDir::Horizontal, 2);
+        // the closest vertical split is the root, and 2 is on the right of it
+        layout.resize(SplitDir::Vertical, 10);
+        layout.resize(SplitDir::Horizontal, 10);
+        let viewports = layout.viewports(AREA);
+        assert_eq!(viewports[0].1.width, 320.);
+        assert_eq!(viewports[2].1.height, 360.);
+
+        layout.resize(SplitDir::Vertical, 1000);
+        assert_eq!(layout.viewports(AREA)[0].1.width, 80.);
+        layout.equalize();
+        assert_eq!(layout.viewports(AREA)[0].1.width, 400.);
+    }
+}
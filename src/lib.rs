@@ -3,3 +3,24 @@ pub mod renderer;
 pub mod filter_map;
 pub mod app;
 pub mod pane;
+pub mod observer;
+pub mod layout;
+pub mod command;
+pub mod registry;
+pub mod prompt;
+pub mod file;
+pub mod watcher;
+pub mod diff;
+pub mod encoding;
+pub mod loader;
+pub mod hex;
+pub mod compress;
+pub mod journal;
+pub mod autosave;
+pub mod session;
+pub mod undo;
+pub mod versions;
+pub mod cli;
+pub mod wait;
+pub mod server;
+pub mod rpc;
@@ -0,0 +1,212 @@
+// Files are read on their own thread, so nothing waits on the disk. Large ones
+// are read a chunk at a time and appended to their buffer as they arrive.
+
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::path::Path;
+use std::sync::{mpsc, Arc};
+
+use crate::buffer::{BufferId, BufferMsg, TextBuffer};
+use crate::encoding::LineEnding;
+use crate::registry::Registry;
+
+// files at least this big are large files
+pub const LARGE_FILE: u64 = 64 * 1024 * 1024;
+// enough for the first screen
+const FIRST_CHUNK: usize = 256 * 1024;
+const CHUNK: usize = 4 * 1024 * 1024;
+
+// how far through reading its file a buffer is
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Progress {
+    pub read: u64,
+    pub total: u64,
+}
+
+impl Progress {
+    pub fn percent(&self) -> u64 {
+        (self.read * 100).checked_div(self.total).unwrap_or(100)
+    }
+}
+
+// what the buffer thread hears from a loader, about the buffer it's reading the file of
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum LoadEvent {
+    // the file's been read, this replaces the placeholder from `TextBuffer::pending`
+    Loaded { buffer: BufferId, loaded: Box<TextBuffer> },
+    // the next part of a large file. `len` is how much of the file it was,
+    // `lossy` if it wasn't all UTF-8
+    Chunk { buffer: BufferId, text: String, len: u64, lossy: bool, mixed: bool },
+    // finished, with the error that stopped it if it didn't
+    Done { buffer: BufferId, error: Option<String> },
+}
+
+pub struct Chunk {
+    pub text: String,
+    // bytes of the file it took up (can be a few more or less than `text`)
+    pub len: u64,
+    // some of it wasn't UTF-8 and was replaced
+    pub lossy: bool,
+    // some of its lines don't end the way the file mostly does (see `LineEnding::normalize`)
+    pub mixed: bool,
+}
+
+// the first bit of the file, cut at a line so the rest starts on a new one.
+// The line ending it mostly uses is taken to be the whole file's
+pub fn first_chunk(path: &Path) -> io::Result<(Chunk, LineEnding)> {
+    let mut bytes = vec![];
+    File::open(path)?.take(FIRST_CHUNK as u64).read_to_end(&mut bytes)?;
+    let (chunk, _) = split_chunk(bytes, false);
+    let (text, line_ending, mixed) = LineEnding::normalize(chunk.text);
+    Ok((Chunk {text, mixed, ..chunk}, line_ending))
+}
+
+// read the file of the placeholder `buf_id` (empty and read only until then)
+// and send it to the buffer thread
+pub fn load(path: Arc<Path>, buf_id: BufferId, buffers: Arc<Registry<TextBuffer>>, buffer_tx: mpsc::Sender<BufferMsg>) {
+    // the history is only any use for exactly what's in the file, so it's read
+    // before anything can be typed
+    let loaded = match TextBuffer::from_filename(&path.to_string_lossy()) {
+        Ok(loaded) => loaded.with_saved_history(),
+        Err(e) => {
+            let _ = buffer_tx.send(BufferMsg::Load(LoadEvent::Done { buffer: buf_id, error: Some(e.to_string()) }));
+            return;
+        },
+    };
+    let rest = loaded.loading.map(|progress| progress.read);
+    if buffer_tx.send(BufferMsg::Load(LoadEvent::Loaded { buffer: buf_id, loaded: Box::new(loaded) })).is_err() {
+        return;
+    }
+    match rest {
+        Some(offset) => load_rest(path, offset, buf_id, buffers, buffer_tx),
+        None => {
+            let _ = buffer_tx.send(BufferMsg::Load(LoadEvent::Done { buffer: buf_id, error: None }));
+        },
+    }
+}
+
+// read the file from `offset` on, sending each chunk to the buffer thread. It
+// stops early if the buffer goes away or the load is cancelled
+pub fn load_rest(path: Arc<Path>, offset: u64, buf_id: BufferId, buffers: Arc<Registry<TextBuffer>>, buffer_tx: mpsc::Sender<BufferMsg>) {
+    // the first chunk's
+    let line_ending = buffers.get().get(&buf_id).and_then(|b| b.file.as_ref()).map(|fi| fi.line_ending).unwrap_or_default();
+    let result = (|| {
+        let mut file = File::open(&path)?;
+        file.seek(SeekFrom::Start(offset))?;
+        let mut carry = vec![];
+        loop {
+            if buffers.get().get(&buf_id).is_none_or(|b| b.loading.is_none()) {
+                return Ok(());
+            }
+            let mut bytes = std::mem::take(&mut carry);
+            let start = bytes.len();
+            bytes.resize(start + CHUNK, 0);
+            let n = file.read(&mut bytes[start..])?;
+            bytes.truncate(start + n);
+            let last = n == 0;
+            let (chunk, rest) = split_chunk(bytes, last);
+            carry = rest;
+            // the buffer only has `\n`
+            let text = line_ending.unapply(&chunk.text).into_owned();
+            let mixed = line_ending.apply(&text) != chunk.text;
+            let event = LoadEvent::Chunk { buffer: buf_id, text, len: chunk.len, lossy: chunk.lossy, mixed };
+            if buffer_tx.send(BufferMsg::Load(event)).is_err() || last {
+                return Ok(());
+            }
+        }
+    })();
+    let error = result.err().map(|e: io::Error| e.to_string());
+    let _ = buffer_tx.send(BufferMsg::Load(LoadEvent::Done { buffer: buf_id, error }));
+}
+
+// the part of `bytes` up to the end of its last line, and what's left over for
+// the next chunk. A chunk with no lines at all is cut wherever a char ends.
+// It's always UTF-8, detecting anything else would need the whole file
+fn split_chunk(mut bytes: Vec<u8>, last: bool) -> (Chunk, Vec<u8>) {
+    let cut = if last {
+        bytes.len()
+    } else if let Some(i) = bytes.iter().rposition(|b| *b == b'\n') {
+        i + 1
+    } else {
+        let valid = match std::str::from_utf8(&bytes) {
+            Ok(_) => bytes.len(),
+            // only cut off a char that's split, not one that's just invalid
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => bytes.len(),
+        };
+        // a `\r` might be half of a `\r\n`
+        if valid > 0 && bytes[valid - 1] == b'\r' { valid - 1 } else { valid }
+    };
+    let rest = bytes.split_off(cut);
+    let len = bytes.len() as u64;
+    let (text, lossy) = match String::from_utf8(bytes) {
+        Ok(text) => (text, false),
+        Err(e) => (String::from_utf8_lossy(e.as_bytes()).into_owned(), true),
+    };
+    (Chunk {text, len, lossy, mixed: false}, rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::TestDir;
+
+    #[test]
+    fn test_split_chunk() {
+        let (chunk, rest) = split_chunk(b"one\r\ntwo\r\nthr".to_vec(), false);
+        assert_eq!((chunk.text.as_str(), chunk.len, rest.as_slice()), ("one\r\ntwo\r\n", 10, &b"thr"[..]));
+        // no line end, so it's cut before the half of `é` and the `\r`
+        let (chunk, rest) = split_chunk(b"ab\r\xC3".to_vec(), false);
+        assert_eq!((chunk.text.as_str(), rest.as_slice()), ("ab", &b"\r\xC3"[..]));
+        let (chunk, rest) = split_chunk(b"a\xFFb".to_vec(), true);
+        assert!(chunk.lossy && rest.is_empty());
+        assert_eq!(chunk.len, 3);
+    }
+
+    #[test]
+    fn test_load_rest() {
+        let dir = TestDir::new("load-rest");
+        let path: Arc<Path> = Arc::from(dir.join("a.txt"));
+        std::fs::write(&path, "skipped\r\nrest\r\nof it\n").unwrap();
+        let buffers = Arc::new(Registry::new());
+        let loading = Some(Progress {read: 9, total: 21});
+        let mut buffer = TextBuffer {loading, ..TextBuffer::new_file(path.to_str().unwrap())};
+        buffer.file.as_mut().unwrap().line_ending = LineEnding::Crlf;
+        let id = buffers.insert_with(|_| buffer);
+        let (tx, rx) = mpsc::channel();
+        load_rest(path.clone(), 9, id, buffers, tx);
+
+        let events: Vec<BufferMsg> = rx.iter().collect();
+        let text: String = events.iter().filter_map(|event| match event {
+            BufferMsg::Load(LoadEvent::Chunk { text, .. }) => Some(text.as_str()),
+            _ => None,
+        }).collect();
+        assert_eq!(text, "rest\nof it\n");
+        // the last line's LF would be saved as CRLF
+        assert!(events.iter().any(|event| matches!(event, BufferMsg::Load(LoadEvent::Chunk { mixed: true, .. }))));
+        assert_eq!(events.last(), Some(&BufferMsg::Load(LoadEvent::Done { buffer: id, error: None })));
+    }
+
+    #[test]
+    fn test_load() {
+        let dir = TestDir::new("load");
+        let path = dir.join("a.txt");
+        std::fs::write(&path, "hi\r\n").unwrap();
+        let buffers = Arc::new(Registry::new());
+        let pending = TextBuffer::pending(path.to_str().unwrap()).unwrap();
+        assert!(pending.is_read_only() && pending.contents.byte_len() == 0);
+        let id = buffers.insert_with(|_| pending);
+        let (tx, rx) = mpsc::channel();
+        load(Arc::from(path.as_path()), id, buffers, tx);
+
+        match rx.recv().unwrap() {
+            BufferMsg::Load(LoadEvent::Loaded { buffer, loaded }) => {
+                assert_eq!(buffer, id);
+                assert_eq!(loaded.contents.to_string(), "hi\n");
+                assert!(!loaded.is_read_only());
+            },
+            event => panic!("expected the buffer, got {event:?}"),
+        }
+        assert_eq!(rx.recv().unwrap(), BufferMsg::Load(LoadEvent::Done { buffer: id, error: None }));
+    }
+}
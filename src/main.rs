@@ -2,6 +2,9 @@
 use std::env;
 use std::fs::File;
 use std::io::Write;
+use std::os::fd::AsFd;
+use std::os::unix::fs::FileTypeExt;
+use std::path::Path;
 use chrono;
 use log::LevelFilter;
 use env_logger::Builder;
@@ -12,8 +15,10 @@ use objc2_foundation::MainThreadMarker;
 
 use chop::app::App;
 use chop::app::AppDelegate;
+use chop::cli::{self, Action};
+use chop::server;
 
-fn init_logging(log_file: Option<&str>) {
+fn init_logging(log_file: Option<&Path>) {
     if let None = log_file {
         env_logger::init();
         return;
@@ -39,30 +44,54 @@ fn init_logging(log_file: Option<&str>) {
     builder.init();
 }
 
-fn main() {
-    println!("PID: {}", std::process::id());
+// a pipe, or a file redirected in. Started from Finder (or cron) stdin isn't a
+// terminal either, but it's /dev/null or a socket with nothing to read
+fn stdin_is_piped() -> bool {
+    let Ok(fd) = std::io::stdin().as_fd().try_clone_to_owned() else {
+        return false;
+    };
+    File::from(fd).metadata().is_ok_and(|m| m.file_type().is_fifo() || m.file_type().is_file())
+}
 
-    // let file = Some("/tmp/app.log");
-    let file = None;
-    init_logging(file);
-    let args: Vec<String> = env::args().collect();
-    let filename = if args.len() <= 1 {
-        None
-    } else {
-        Some(args[1].clone())
+fn main() {
+    let options = match cli::parse(env::args().skip(1), stdin_is_piped()) {
+        Ok(Action::Run(options)) => options,
+        Ok(Action::Help) => {
+            print!("{}", cli::USAGE);
+            return;
+        },
+        Ok(Action::Version) => {
+            println!("chop {}", env!("CARGO_PKG_VERSION"));
+            return;
+        },
+        Err(e) => {
+            eprintln!("chop: {e}\n\n{}", cli::USAGE);
+            std::process::exit(2);
+        },
     };
+    // there's one running already, it opens them
+    if let Some(stream) = server::connect(&server::socket_path()).filter(|_| !options.new_instance) {
+        let code = server::Request::of(&options).and_then(|request| server::send(stream, &request)).unwrap_or_else(|e| {
+            eprintln!("chop: couldn't hand the files to the chop that's running: {e}");
+            1
+        });
+        std::process::exit(code);
+    }
+    init_logging(options.log.as_deref());
 
     let event_loop = EventLoop::new().unwrap();
 
+    log::info!("calling App::new()");
+    let mut app = App::new(options, event_loop.create_proxy());
+
     // =================================== weird objc stuff
     let mtm = MainThreadMarker::new().unwrap();
-    let delegate = AppDelegate::new(mtm);
+    // after the app, it's who the delegate sends files to
+    let delegate = AppDelegate::new(mtm, app.ui());
     // Important: Call `sharedApplication` after `EventLoop::new`, doing it before is not yet supported.
     let ns_app = NSApplication::sharedApplication(mtm);
     ns_app.setDelegate(Some(ProtocolObject::from_ref(&*delegate)));
     // ===================================
-
-    log::info!("calling App::new()");
-    let mut app = App::new(filename, event_loop.create_proxy());
     event_loop.run_app(&mut app).unwrap();
+    std::process::exit(app.exit_code());
 }
@@ -0,0 +1,218 @@
+// Changes to buffers, sent from the buffer thread to whatever subscribes to
+// `Observers` (syntax highlighting, lsp, autosave, ...).
+
+use std::ops::Range;
+use std::path::Path;
+use std::sync::{mpsc, Arc, Mutex};
+use crop::Rope;
+
+use crate::buffer::BufferId;
+
+// A single replacement of `start..old_end` with `text`.
+//
+// When several edits make up one change, they are in the order they were
+// applied, and each one's offsets are relative to the buffer after all the
+// previous edits were applied (so they can be replayed one at a time).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Edit {
+    // byte offsets
+    pub start: usize,
+    pub old_end: usize,
+    pub new_end: usize,
+    pub text: String,
+    // lines (0 indexed) of the same three positions
+    pub start_line: usize,
+    pub old_end_line: usize,
+    pub new_end_line: usize,
+}
+
+impl Edit {
+    // these have to be called with the contents *before* the edit is applied
+    pub fn insert(contents: &Rope, at: usize, text: &str) -> Self {
+        let start_line = contents.line_of_byte(at);
+        Self {
+            start: at,
+            old_end: at,
+            new_end: at + text.len(),
+            text: text.to_string(),
+            start_line,
+            old_end_line: start_line,
+            new_end_line: start_line + text.matches('\n').count(),
+        }
+    }
+
+    pub fn delete(contents: &Rope, range: Range<usize>) -> Self {
+        let start_line = contents.line_of_byte(range.start);
+        Self {
+            start: range.start,
+            old_end: range.end,
+            new_end: range.start,
+            text: String::new(),
+            start_line,
+            old_end_line: contents.line_of_byte(range.end),
+            new_end_line: start_line,
+        }
+    }
+
+    pub fn replace(contents: &Rope, range: Range<usize>, text: &str) -> Self {
+        let deleted = Self::delete(contents, range);
+        Self {
+            new_end: deleted.start + text.len(),
+            text: text.to_string(),
+            new_end_line: deleted.start_line + text.matches('\n').count(),
+            ..deleted
+        }
+    }
+
+    pub fn apply(&self, contents: &mut Rope) {
+        contents.replace(self.start..self.old_end, &self.text);
+    }
+
+    // where a byte that was at `offset` before this edit ends up after it.
+    // `after_insert` decides which side of text inserted exactly at `offset` it lands on
+    pub fn map_offset(&self, offset: usize, after_insert: bool) -> usize {
+        if offset < self.start || (offset == self.start && !after_insert) {
+            offset
+        } else if offset >= self.old_end {
+            offset - self.old_end + self.new_end
+        } else if after_insert {
+            // inside the replaced text
+            self.new_end
+        } else {
+            self.start
+        }
+    }
+
+    // where a line that was at `line` before this edit ends up after it
+    pub fn map_line(&self, line: usize) -> usize {
+        if line <= self.start_line {
+            line
+        } else if line > self.old_end_line {
+            line + self.new_end_line - self.old_end_line
+        } else {
+            self.new_end_line
+        }
+    }
+}
+
+pub fn map_offset(offset: usize, edits: &[Edit], after_insert: bool) -> usize {
+    edits.iter().fold(offset, |offset, e| e.map_offset(offset, after_insert))
+}
+
+// the lines (in the final buffer) touched by a sequence of edits, end is not included
+pub fn affected_lines(edits: &[Edit]) -> Range<usize> {
+    let mut lines: Option<Range<usize>> = None;
+    for e in edits {
+        lines = Some(match lines {
+            None => e.start_line..e.new_end_line + 1,
+            Some(r) => {
+                let start = e.map_line(r.start).min(e.start_line);
+                let end = (e.map_line(r.end - 1) + 1).max(e.new_end_line + 1);
+                start..end
+            },
+        });
+    }
+    lines.unwrap_or(0..0)
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BufferChange {
+    pub buffer_id: BufferId,
+    // `TextBuffer::version` after the change
+    pub version: u64,
+    pub edits: Vec<Edit>,
+    pub lines: Range<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BufferEvent {
+    Changed(BufferChange),
+    Saved {
+        buffer_id: BufferId,
+        version: u64,
+        path: Arc<Path>,
+    },
+    // it's gone, whatever it had is too. `discarded` is whether it had
+    // changes that weren't saved
+    Closed {
+        buffer_id: BufferId,
+        discarded: bool,
+    },
+}
+
+impl BufferEvent {
+    pub fn buffer_id(&self) -> BufferId {
+        match self {
+            BufferEvent::Changed(change) => change.buffer_id,
+            BufferEvent::Saved { buffer_id, .. } | BufferEvent::Closed { buffer_id, .. } => *buffer_id,
+        }
+    }
+}
+
+pub struct Observers {
+    subscribers: Mutex<Vec<mpsc::Sender<Arc<BufferEvent>>>>,
+}
+
+impl Observers {
+    pub fn new() -> Self {
+        Self {
+            subscribers: Mutex::new(vec![]),
+        }
+    }
+
+    // dropping the receiver unsubscribes. Read it on any thread but the UI's
+    pub fn subscribe(&self) -> mpsc::Receiver<Arc<BufferEvent>> {
+        let (tx, rx) = mpsc::channel();
+        self.subscribers.lock().unwrap().push(tx);
+        rx
+    }
+
+    pub fn notify(&self, event: BufferEvent) {
+        let event = Arc::new(event);
+        self.subscribers.lock().unwrap().retain(|tx| tx.send(event.clone()).is_ok());
+    }
+
+    // hang up on everyone, they get what's already been sent and then nothing
+    pub fn close(&self) {
+        self.subscribers.lock().unwrap().clear();
+    }
+}
+
+impl Default for Observers {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_affected_lines() {
+        // deleting from the back like `backdelete_cursor` does
+        let contents = Rope::from("ab\ncd\nef\ngh");
+        let first = Edit::delete(&contents, 6..7);
+        let mut contents = contents;
+        first.apply(&mut contents);
+        let second = Edit::delete(&contents, 2..3);
+        second.apply(&mut contents);
+        assert_eq!(contents.to_string(), "abcd\nf\ngh");
+        assert_eq!(affected_lines(&[first, second]), 0..2);
+
+        let contents = Rope::from("ab\ncd");
+        let edit = Edit::insert(&contents, 5, "\nx\ny");
+        assert_eq!(affected_lines(&[edit]), 1..4);
+    }
+
+    #[test]
+    fn test_unsubscribe() {
+        let observers = Observers::new();
+        let rx = observers.subscribe();
+        let dropped = observers.subscribe();
+        drop(dropped);
+        observers.notify(BufferEvent::Saved { buffer_id: 3, version: 1, path: Arc::from(Path::new("/tmp/x")) });
+        assert_eq!(rx.recv().unwrap().buffer_id(), 3);
+        assert_eq!(observers.subscribers.lock().unwrap().len(), 1);
+    }
+}
@@ -1,7 +1,12 @@
 use im::OrdMap;
+use crop::Rope;
 
 use crate::buffer::BufferId;
-use crate::buffer::BufferOp;
+use crate::buffer::{BufferOp, Op, WindowOp};
+use crate::buffer::{TextBuffer, SwitchTo, reset_grapheme_col_offset};
+use crate::command;
+use crate::layout::{LayoutOp, SplitDir, Direction};
+use crate::observer::{Edit, map_offset};
 use winit::keyboard::Key;
 use winit::keyboard::NamedKey;
 use winit::event::Modifiers;
@@ -13,7 +18,7 @@ use winit::keyboard::ModifiersKeyState;
 // "|\abcdjk" start: 0, offset: 0
 // "ab\cdj|k" start: 5, offset: -3
 
-#[derive(Debug, Copy, Clone, PartialEq)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Selection {
     // `start` is the location of the cursor is in the selection
     // 0 means before the first byte, and n means after the nth character
@@ -39,7 +44,55 @@ impl Selection {
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Mode {
     Normal,
-    Insert
+    Insert,
+    // after a Ctrl-w, waiting for which window command
+    Window,
+    // typing in the command line after a `:`
+    Command,
+}
+
+// where a pane was in a buffer, so it can go back to it after showing another one
+#[derive(Debug, Clone, PartialEq)]
+pub struct View {
+    pub cursors: OrdMap<usize, Selection>,
+    pub main_cursor_start: usize,
+    pub y_offset: f32,
+}
+
+impl View {
+    // move the cursors and scroll position to where they should be after `edits`.
+    // `after_insert` is where a cursor right where text gets inserted should go: the
+    // pane doing the typing wants it after the new text, everyone else wants it to stay put
+    pub fn shift(&self, edits: &[Edit], after_insert: bool) -> Self {
+        let mut cursors = OrdMap::new();
+        for s in self.cursors.values() {
+            let start = map_offset(s.start, edits, after_insert);
+            let end = map_offset(s.end(), edits, after_insert);
+            cursors.insert(start, Selection {start, offset: end as i64 - start as i64});
+        }
+        let main_cursor_start = map_offset(self.main_cursor_start, edits, after_insert);
+
+        let top = self.y_offset.floor();
+        let new_top = edits.iter().fold(top as usize, |line, e| e.map_line(line));
+        let y_offset = new_top as f32 + (self.y_offset - top);
+        View {
+            cursors,
+            main_cursor_start,
+            y_offset,
+        }
+    }
+}
+
+impl Default for View {
+    fn default() -> Self {
+        let mut cursors = OrdMap::new();
+        cursors.insert(0, Selection {start: 0, offset: 0});
+        View {
+            cursors,
+            main_cursor_start: 0,
+            y_offset: 0.,
+        }
+    }
 }
 
 pub type PaneId = usize;
@@ -54,8 +107,16 @@ pub struct Pane {
     pub grapheme_col_offset: usize,
     pub buffer_id: BufferId,
     pub id: PaneId,
+    // in lines (not pixels) so the buffer thread can keep it anchored when
+    // lines are added or removed above it
     pub y_offset: f32,
     pub mode: Mode,
+    // what's been typed after the `:` (in `Mode::Command`)
+    pub cmdline: String,
+    // where we were in the other buffers this pane has shown
+    pub views: OrdMap<BufferId, View>,
+    // the buffer shown before this one (vim's `#`)
+    pub alternate: Option<BufferId>,
 }
 
 impl Pane {
@@ -63,17 +124,103 @@ impl Pane {
         buffer_id: BufferId,
         pane_id: PaneId,
     ) -> Self {
-        let mut cursors = OrdMap::new();
-        let start = 0;
-        cursors.insert(start, Selection {start, offset: 0});
+        let View {cursors, main_cursor_start, y_offset} = View::default();
         Self {
             cursors,
-            main_cursor_start: 0,
+            main_cursor_start,
             grapheme_col_offset: 0,
             buffer_id,
             id: pane_id,
-            y_offset: 0.,
+            y_offset,
             mode: Mode::Normal,
+            cmdline: String::new(),
+            views: OrdMap::new(),
+            alternate: None,
+        }
+    }
+
+    pub fn view(&self) -> View {
+        View {
+            cursors: self.cursors.clone(),
+            main_cursor_start: self.main_cursor_start,
+            y_offset: self.y_offset,
+        }
+    }
+
+    fn with_view(&self, view: View) -> Self {
+        let View {cursors, main_cursor_start, y_offset} = view;
+        Pane {
+            cursors,
+            main_cursor_start,
+            y_offset,
+            ..self.clone()
+        }
+    }
+
+    // show `buffer_id` in this pane, back where we left it if we've shown it before
+    pub fn show(&self, buffer_id: BufferId, buffer: &TextBuffer) -> Self {
+        if buffer_id == self.buffer_id {
+            return self.clone();
+        }
+        let mut views = self.views.clone();
+        views.insert(self.buffer_id, self.view());
+        let view = views.remove(&buffer_id).unwrap_or_default();
+        let grapheme_col_offset = reset_grapheme_col_offset(&buffer.contents, view.main_cursor_start);
+        Pane {
+            buffer_id,
+            grapheme_col_offset,
+            views,
+            alternate: Some(self.buffer_id),
+            ..self.with_view(view)
+        }
+    }
+
+    // put the cursors and scroll back where a session had them, as far as the
+    // buffer still goes (the file could have changed since)
+    pub fn restore(&self, cursors: &[Selection], main_cursor_start: usize, top_line: usize, contents: &Rope) -> Self {
+        let clamp = |offset: i64| {
+            let mut offset = (offset.max(0) as usize).min(contents.byte_len());
+            while !contents.is_grapheme_boundary(offset) {
+                offset -= 1;
+            }
+            offset
+        };
+        let mut restored: OrdMap<usize, Selection> = cursors.iter().map(|s| {
+            let start = clamp(s.start as i64);
+            let end = clamp(s.start as i64 + s.offset);
+            (start, Selection {start, offset: end as i64 - start as i64})
+        }).collect();
+        if restored.is_empty() {
+            restored = View::default().cursors;
+        }
+        let main = clamp(main_cursor_start as i64);
+        let main_cursor_start = if restored.contains_key(&main) { main } else { *restored.keys().next().unwrap() };
+        Pane {
+            cursors: restored,
+            main_cursor_start,
+            grapheme_col_offset: reset_grapheme_col_offset(contents, main_cursor_start),
+            y_offset: top_line.min(contents.line_len()) as f32,
+            ..self.clone()
+        }
+    }
+
+    // where the pane is in a buffer it isn't showing, for when it's shown
+    pub fn with_hidden_view(&self, buffer_id: BufferId, view: View) -> Self {
+        Pane {
+            views: self.views.update(buffer_id, view),
+            ..self.clone()
+        }
+    }
+
+    // a buffer this pane isn't showing was edited, keep our place in it
+    pub fn shift_hidden(&self, buffer_id: BufferId, edits: &[Edit]) -> Self {
+        let mut views = self.views.clone();
+        if let Some(view) = views.get(&buffer_id) {
+            views.insert(buffer_id, view.shift(edits, false));
+        }
+        Pane {
+            views,
+            ..self.clone()
         }
     }
 
@@ -81,29 +228,34 @@ impl Pane {
         self.cursors.values()
     }
 
-    pub fn insert(&self, k: Key, mods: &Modifiers) -> (Mode, Vec<BufferOp>) {
+    pub fn insert(&self, k: Key, mods: &Modifiers) -> (Mode, Vec<Op>) {
         match k {
             Key::Named(n) => {
                 match n {
-                    NamedKey::Enter => (Mode::Insert, vec![BufferOp::Insert(String::from("\n"))]),
-                    NamedKey::ArrowLeft => (Mode::Insert, vec![BufferOp::MoveHorizontal(-1)]),
-                    NamedKey::ArrowRight => (Mode::Insert, vec![BufferOp::MoveHorizontal(1)]),
-                    NamedKey::ArrowUp => (Mode::Insert, vec![BufferOp::MoveVertical(-1)]),
-                    NamedKey::ArrowDown => (Mode::Insert, vec![BufferOp::MoveVertical(1)]),
-                    NamedKey::Space => (Mode::Insert, vec![BufferOp::Insert(String::from(" "))]),
-                    NamedKey::Backspace => (Mode::Insert, vec![BufferOp::Delete]),
+                    NamedKey::Enter => (Mode::Insert, vec![BufferOp::Insert(String::from("\n")).into()]),
+                    NamedKey::ArrowLeft => (Mode::Insert, vec![BufferOp::MoveHorizontal(-1).into()]),
+                    NamedKey::ArrowRight => (Mode::Insert, vec![BufferOp::MoveHorizontal(1).into()]),
+                    NamedKey::ArrowUp => (Mode::Insert, vec![BufferOp::MoveVertical(-1).into()]),
+                    NamedKey::ArrowDown => (Mode::Insert, vec![BufferOp::MoveVertical(1).into()]),
+                    NamedKey::Space => (Mode::Insert, vec![BufferOp::Insert(String::from(" ")).into()]),
+                    NamedKey::Backspace => (Mode::Insert, vec![BufferOp::Delete.into()]),
                     NamedKey::Escape => (Mode::Normal, vec![]),
                     _ => (Mode::Insert, vec![]),
                 }
             },
             Key::Character(s) => {
                 if !super_pressed(mods) {
-                    (Mode::Insert, vec![BufferOp::Insert(String::from(s.as_str()))])
+                    (Mode::Insert, vec![BufferOp::Insert(String::from(s.as_str())).into()])
                 } else {
                     let char = s.chars().nth(0).unwrap();
                     match char {
-                        'w' => (Mode::Insert, vec![BufferOp::Exit]),
-                        's' => (Mode::Insert, vec![BufferOp::Save]),
+                        'w' => (Mode::Insert, vec![WindowOp::Exit.into()]),
+                        's' if shift_pressed(mods) => (Mode::Insert, vec![WindowOp::EditCommand("w ".to_string()).into()]),
+                        'S' => (Mode::Insert, vec![WindowOp::EditCommand("w ".to_string()).into()]),
+                        's' => (Mode::Insert, vec![BufferOp::Save.into()]),
+                        'z' if shift_pressed(mods) => (Mode::Insert, vec![BufferOp::Redo.into()]),
+                        'Z' => (Mode::Insert, vec![BufferOp::Redo.into()]),
+                        'z' => (Mode::Insert, vec![BufferOp::Undo.into()]),
                         _ => (Mode::Insert, vec![])
                     }
                 }
@@ -114,15 +266,16 @@ impl Pane {
         }
     }
 
-    pub fn normal(&self, k: Key, mods: &Modifiers) -> (Mode, Vec<BufferOp>) {
+    pub fn normal(&self, k: Key, mods: &Modifiers) -> (Mode, Vec<Op>) {
         match k {
             Key::Named(n) => {
                 match n {
-                    NamedKey::ArrowLeft => (Mode::Normal, vec![BufferOp::MoveHorizontal(-1)]),
-                    NamedKey::ArrowRight => (Mode::Normal, vec![BufferOp::MoveHorizontal(1)]),
-                    NamedKey::ArrowUp => (Mode::Normal, vec![BufferOp::MoveVertical(-1)]),
-                    NamedKey::ArrowDown => (Mode::Normal, vec![BufferOp::MoveVertical(1)]),
-                    NamedKey::Enter => (Mode::Normal, vec![BufferOp::Save]),
+                    NamedKey::ArrowLeft => (Mode::Normal, vec![BufferOp::MoveHorizontal(-1).into()]),
+                    NamedKey::ArrowRight => (Mode::Normal, vec![BufferOp::MoveHorizontal(1).into()]),
+                    NamedKey::ArrowUp => (Mode::Normal, vec![BufferOp::MoveVertical(-1).into()]),
+                    NamedKey::ArrowDown => (Mode::Normal, vec![BufferOp::MoveVertical(1).into()]),
+                    NamedKey::Enter => (Mode::Normal, vec![BufferOp::Save.into()]),
+                    NamedKey::Escape => (Mode::Normal, vec![BufferOp::CancelLoad.into()]),
                     _ => (Mode::Normal, vec![]),
                 }
             },
@@ -131,17 +284,31 @@ impl Pane {
                 match char {
                     'w' => {
                         if super_pressed(mods) {
-                            (Mode::Normal, vec![BufferOp::Exit])
+                            (Mode::Normal, vec![WindowOp::Exit.into()])
+                        } else if ctrl_pressed(mods) {
+                            (Mode::Window, vec![])
                         } else {
                             (Mode::Normal, vec![])
                         }
                     },
-                    'h' => (Mode::Normal, vec![BufferOp::MoveHorizontal(-1)]),
-                    'l' => (Mode::Normal, vec![BufferOp::MoveHorizontal(1)]),
-                    'k' => (Mode::Normal, vec![BufferOp::MoveVertical(-1)]),
-                    'j' => (Mode::Normal, vec![BufferOp::MoveVertical(1)]),
+                    'h' => (Mode::Normal, vec![BufferOp::MoveHorizontal(-1).into()]),
+                    'l' => (Mode::Normal, vec![BufferOp::MoveHorizontal(1).into()]),
+                    'k' => (Mode::Normal, vec![BufferOp::MoveVertical(-1).into()]),
+                    'j' => (Mode::Normal, vec![BufferOp::MoveVertical(1).into()]),
+                    's' | 'S' if super_pressed(mods) => {
+                        if char == 'S' || shift_pressed(mods) {
+                            (Mode::Normal, vec![WindowOp::EditCommand("w ".to_string()).into()])
+                        } else {
+                            (Mode::Normal, vec![BufferOp::Save.into()])
+                        }
+                    },
                     'i' => (Mode::Insert, vec![]),
-                    'q' => (Mode::Normal, vec![BufferOp::Exit]),
+                    'u' => (Mode::Normal, vec![BufferOp::Undo.into()]),
+                    'r' if ctrl_pressed(mods) => (Mode::Normal, vec![BufferOp::Redo.into()]),
+                    ':' => (Mode::Command, vec![]),
+                    'c' if ctrl_pressed(mods) => (Mode::Normal, vec![BufferOp::CancelLoad.into()]),
+                    '6' | '^' if ctrl_pressed(mods) => (Mode::Normal, vec![BufferOp::SwitchBuffer(SwitchTo::Alternate).into()]),
+                    'q' => (Mode::Normal, vec![WindowOp::Exit.into()]),
                     _ => {
                         (Mode::Normal, vec![])
                     }
@@ -153,7 +320,73 @@ impl Pane {
         }
     }
 
-    pub fn key(&self, key: Key, mods: &Modifiers) -> (Self, Vec<BufferOp>) {
+    // the key after Ctrl-w, always goes back to normal mode
+    pub fn window(&self, k: Key, _mods: &Modifiers) -> (Mode, Vec<Op>) {
+        let op = match k {
+            Key::Character(s) => {
+                match s.chars().nth(0).unwrap() {
+                    's' => Some(LayoutOp::Split(SplitDir::Horizontal)),
+                    'v' => Some(LayoutOp::Split(SplitDir::Vertical)),
+                    'h' => Some(LayoutOp::Focus(Direction::Left)),
+                    'j' => Some(LayoutOp::Focus(Direction::Down)),
+                    'k' => Some(LayoutOp::Focus(Direction::Up)),
+                    'l' => Some(LayoutOp::Focus(Direction::Right)),
+                    'w' => Some(LayoutOp::FocusNext),
+                    'H' => Some(LayoutOp::Swap(Direction::Left)),
+                    'J' => Some(LayoutOp::Swap(Direction::Down)),
+                    'K' => Some(LayoutOp::Swap(Direction::Up)),
+                    'L' => Some(LayoutOp::Swap(Direction::Right)),
+                    'q' | 'c' => Some(LayoutOp::Close),
+                    '+' => Some(LayoutOp::Resize(SplitDir::Horizontal, 5)),
+                    '-' => Some(LayoutOp::Resize(SplitDir::Horizontal, -5)),
+                    '>' => Some(LayoutOp::Resize(SplitDir::Vertical, 5)),
+                    '<' => Some(LayoutOp::Resize(SplitDir::Vertical, -5)),
+                    '=' => Some(LayoutOp::Equalize),
+                    _ => None,
+                }
+            },
+            Key::Named(NamedKey::ArrowLeft) => Some(LayoutOp::Focus(Direction::Left)),
+            Key::Named(NamedKey::ArrowDown) => Some(LayoutOp::Focus(Direction::Down)),
+            Key::Named(NamedKey::ArrowUp) => Some(LayoutOp::Focus(Direction::Up)),
+            Key::Named(NamedKey::ArrowRight) => Some(LayoutOp::Focus(Direction::Right)),
+            _ => None,
+        };
+        (Mode::Normal, op.into_iter().map(|op| WindowOp::Layout(op).into()).collect())
+    }
+
+    // typing on the command line, returns what's on it after the key too
+    pub fn command(&self, k: Key, _mods: &Modifiers) -> (Mode, String, Vec<Op>) {
+        let mut cmdline = self.cmdline.clone();
+        match k {
+            Key::Named(NamedKey::Enter) => {
+                match command::parse(&cmdline) {
+                    Ok(ops) => (Mode::Normal, String::new(), ops),
+                    Err(msg) => (Mode::Normal, String::new(), vec![WindowOp::Message(msg).into()]),
+                }
+            },
+            Key::Named(NamedKey::Escape) => (Mode::Normal, String::new(), vec![]),
+            Key::Named(NamedKey::Backspace) => {
+                // backspacing past the `:` leaves the command line
+                if cmdline.pop().is_none() {
+                    (Mode::Normal, cmdline, vec![])
+                } else {
+                    (Mode::Command, cmdline, vec![])
+                }
+            },
+            Key::Named(NamedKey::Space) => {
+                cmdline.push(' ');
+                (Mode::Command, cmdline, vec![])
+            },
+            Key::Character(s) => {
+                cmdline.push_str(s.as_str());
+                (Mode::Command, cmdline, vec![])
+            },
+            _ => (Mode::Command, cmdline, vec![]),
+        }
+    }
+
+    pub fn key(&self, key: Key, mods: &Modifiers) -> (Self, Vec<Op>) {
+        let mut cmdline = String::new();
         let (mode, ops) = match self.mode {
             Mode::Normal => {
                 self.normal(key, mods)
@@ -161,17 +394,29 @@ impl Pane {
             Mode::Insert => {
                 self.insert(key, mods)
             },
+            Mode::Window => {
+                self.window(key, mods)
+            },
+            Mode::Command => {
+                let (mode, line, ops) = self.command(key, mods);
+                cmdline = line;
+                (mode, ops)
+            },
         };
-        let cursors = self.cursors.clone();
-        (Self { mode, cursors, ..*self}, ops)
+        (Self { mode, cmdline, ..self.clone()}, ops)
     }
 
+    // see `View::shift`
+    pub fn shift(&self, edits: &[Edit], after_insert: bool) -> Self {
+        self.with_view(self.view().shift(edits, after_insert))
+    }
+
+    // `y` and `end` are in lines
     pub fn scroll_y(&self, y: f32, end: f32) -> Self {
         let y_offset = (self.y_offset + y).max(0.).min(end);
         Pane {
             y_offset,
-            cursors: self.cursors.clone(),
-            ..*self
+            ..self.clone()
         }
 
     }
@@ -181,3 +426,42 @@ fn super_pressed(m: &Modifiers) -> bool {
     m.lsuper_state() == ModifiersKeyState::Pressed || m.rsuper_state() == ModifiersKeyState::Pressed
 }
 
+fn shift_pressed(m: &Modifiers) -> bool {
+    m.lshift_state() == ModifiersKeyState::Pressed || m.rshift_state() == ModifiersKeyState::Pressed
+}
+
+fn ctrl_pressed(m: &Modifiers) -> bool {
+    m.lcontrol_state() == ModifiersKeyState::Pressed || m.rcontrol_state() == ModifiersKeyState::Pressed
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crop::Rope;
+
+    #[test]
+    fn test_show_remembers_view() {
+        let a = TextBuffer::new(None, Rope::from("abc\ndef"));
+        let b = TextBuffer::new(None, Rope::from("xyz"));
+        let mut pane = Pane::new(0, 0);
+        pane.cursors = OrdMap::unit(5, Selection {start: 5, offset: 0});
+        pane.main_cursor_start = 5;
+        pane.y_offset = 1.;
+
+        let pane = pane.show(1, &b);
+        assert_eq!(pane.buffer_id, 1);
+        assert_eq!(pane.alternate, Some(0));
+        assert_eq!(pane.view(), View::default());
+
+        // an insert before our place in the hidden buffer moves it along
+        let edit = Edit::insert(&a.contents, 0, "12");
+        let pane = pane.shift_hidden(0, &[edit]);
+
+        let pane = pane.show(0, &a);
+        assert_eq!(pane.alternate, Some(1));
+        assert_eq!(pane.main_cursor_start, 7);
+        assert_eq!(pane.y_offset, 1.);
+        assert!(pane.views.contains_key(&1));
+    }
+}
@@ -0,0 +1,22 @@
+// A question in the status line that takes over the keyboard until it's
+// answered (or escaped, which answers nothing).
+
+use crate::buffer::Op;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Prompt {
+    pub question: String,
+    // the key for each answer, and the ops to run if it's picked
+    pub answers: Vec<(char, Vec<Op>)>,
+}
+
+impl Prompt {
+    pub fn new(question: String, answers: Vec<(char, Vec<Op>)>) -> Self {
+        Self {question, answers}
+    }
+
+    // None if `c` isn't one of the answers
+    pub fn answer(&self, c: char) -> Option<Vec<Op>> {
+        self.answers.iter().find(|(key, _)| *key == c).map(|(_, ops)| ops.clone())
+    }
+}
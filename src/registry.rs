@@ -0,0 +1,178 @@
+// Buffers and panes, shared between the UI thread and the buffer thread. Reads
+// are a snapshot that never blocks, and every write is a compare-and-swap.
+
+use std::ops::Deref;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use arc_swap::ArcSwap;
+use im::OrdMap;
+
+#[derive(Debug, Clone)]
+pub struct Snapshot<T> {
+    // bumped by every write
+    pub version: u64,
+    pub map: OrdMap<usize, T>,
+}
+
+impl<T> Deref for Snapshot<T> {
+    type Target = OrdMap<usize, T>;
+
+    fn deref(&self) -> &Self::Target {
+        &self.map
+    }
+}
+
+// the map is persistent, so a write only copies the path to the changed entry
+pub struct Registry<T> {
+    state: ArcSwap<Snapshot<T>>,
+    // ids are never reused, so a stale id finds nothing instead of the wrong thing
+    next_id: AtomicUsize,
+}
+
+impl<T> Registry<T> where T: Clone {
+    pub fn new() -> Self {
+        Self {
+            state: ArcSwap::new(Arc::new(Snapshot {version: 0, map: OrdMap::new()})),
+            next_id: AtomicUsize::new(0),
+        }
+    }
+
+    pub fn get(&self) -> arc_swap::Guard<Arc<Snapshot<T>>> {
+        self.state.load()
+    }
+
+    pub fn version(&self) -> u64 {
+        self.get().version
+    }
+
+    // replace the map only if nothing has been written since `current` was read.
+    // On success it's the new version, otherwise whatever is there now
+    pub fn compare_and_swap(&self, current: &Arc<Snapshot<T>>, map: OrdMap<usize, T>) -> Result<u64, Arc<Snapshot<T>>> {
+        let version = current.version + 1;
+        let prev = self.state.compare_and_swap(current, Arc::new(Snapshot {version, map}));
+        if Arc::ptr_eq(&prev, current) {
+            Ok(version)
+        } else {
+            Err(Arc::clone(&prev))
+        }
+    }
+
+    // `f` can be called more than once (if the other thread wrote first it's
+    // called again on theirs, so nobody's update gets clobbered), only the last call counts
+    pub fn update<R>(&self, mut f: impl FnMut(&OrdMap<usize, T>) -> (OrdMap<usize, T>, R)) -> R {
+        let mut current = self.state.load_full();
+        loop {
+            let (map, r) = f(&current.map);
+            match self.compare_and_swap(&current, map) {
+                Ok(_) => return r,
+                Err(now) => current = now,
+            }
+        }
+    }
+
+    // update a single entry, None (and `f` isn't called) if `id` isn't there
+    pub fn modify<R>(&self, id: usize, mut f: impl FnMut(&T) -> (T, R)) -> Option<R> {
+        self.update(|map| {
+            match map.get(&id) {
+                Some(value) => {
+                    let (value, r) = f(value);
+                    (map.update(id, value), Some(r))
+                },
+                None => (map.clone(), None),
+            }
+        })
+    }
+
+    // `f` gets the new id, for things that keep their own id
+    pub fn insert_with(&self, f: impl FnOnce(usize) -> T) -> usize {
+        let id = self.next_id.fetch_add(1, Ordering::Relaxed);
+        let value = f(id);
+        self.update(|map| (map.update(id, value.clone()), ()));
+        id
+    }
+
+    // replace an entry. Like `modify` nothing happens if `id` isn't there, so a
+    // write that lost the race with a `remove` doesn't bring it back
+    pub fn store(&self, id: usize, value: T) {
+        self.modify(id, |_| (value.clone(), ()));
+    }
+
+    pub fn remove(&self, id: usize) -> Option<T> {
+        self.update(|map| match map.extract(&id) {
+            Some((value, map)) => (map, Some(value)),
+            None => (map.clone(), None),
+        })
+    }
+
+    pub fn len(&self) -> usize {
+        self.get().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.get().is_empty()
+    }
+}
+
+impl<T> Default for Registry<T> where T: Clone {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ids_are_stable() {
+        let reg = Registry::new();
+        let a = reg.insert_with(|id| (id, "a"));
+        let b = reg.insert_with(|id| (id, "b"));
+        let before = reg.get();
+
+        assert_eq!(reg.remove(a), Some((a, "a")));
+        assert_eq!(reg.remove(a), None);
+        reg.store(a, (a, "late"));
+        assert_eq!(reg.get().get(&a), None);
+        let c = reg.insert_with(|id| (id, "c"));
+        assert_ne!(c, a);
+        assert_eq!(reg.get()[&b], (b, "b"));
+        assert_eq!(reg.len(), 2);
+        // old snapshots don't change
+        assert_eq!(before.len(), 2);
+        assert_eq!(before[&a], (a, "a"));
+    }
+
+    #[test]
+    fn test_compare_and_swap() {
+        let reg = Registry::new();
+        let id = reg.insert_with(|_| 0);
+        let stale = reg.state.load_full();
+        reg.store(id, 1);
+        // computed from an old snapshot, so it doesn't go in
+        let now = reg.compare_and_swap(&stale, stale.map.update(id, 2)).unwrap_err();
+        assert_eq!(now[&id], 1);
+        assert_eq!(reg.compare_and_swap(&now, now.map.update(id, 3)).ok(), Some(now.version + 1));
+        assert_eq!(reg.get()[&id], 3);
+    }
+
+    #[test]
+    fn test_concurrent_updates() {
+        let reg = Arc::new(Registry::new());
+        let a = reg.insert_with(|_| 0);
+        let b = reg.insert_with(|_| 0);
+        let threads: Vec<_> = [a, b, a, b].into_iter().map(|id| {
+            let reg = reg.clone();
+            std::thread::spawn(move || {
+                for _ in 0..1000 {
+                    reg.modify(id, |n| (n + 1, ()));
+                }
+            })
+        }).collect();
+        for t in threads {
+            t.join().unwrap();
+        }
+        assert_eq!(reg.get()[&a], 2000);
+        assert_eq!(reg.get()[&b], 2000);
+    }
+}
@@ -11,11 +11,15 @@ use vello::kurbo::Rect;
 use vello::Scene;
 use std::sync::mpsc;
 
-use crate::buffer::{TextBuffer, CustomEvent};
+use crate::buffer::{TextBuffer, BufferId, CustomEvent};
 use crate::filter_map::{FMTOption, filter_map_terminate};
 use crate::app::WindowState;
 use crate::pane::Mode;
-use crate::pane::Pane;
+use crate::pane::{Pane, PaneId};
+use crate::layout::{Layout, Viewport, SplitDir};
+use crate::prompt::Prompt;
+use crate::encoding::LineEnding;
+use im::OrdMap;
 
 pub struct Style {
     pub bg_color: peniko::Color,
@@ -34,6 +38,24 @@ pub struct Style {
     pub titlebar: Rect,
     pub color_scheme: HashMap<String, peniko::Color>,
     pub rust_syntax_map: HashMap<String, String>,
+    // the whole area of each pane in the window (padding included)
+    pub viewports: HashMap<PaneId, Viewport>,
+}
+
+impl Style {
+    // the part of the window the panes are laid out in
+    pub fn text_area(&self) -> Viewport {
+        Viewport {
+            x: self.voffset_x - X_PADDING,
+            y: self.voffset_y,
+            width: self.vwidth + X_PADDING,
+            height: self.vheight,
+        }
+    }
+
+    pub fn set_layout(&mut self, layout: &Layout) {
+        self.viewports = layout.viewports(self.text_area()).into_iter().collect();
+    }
 }
 
 
@@ -55,9 +77,10 @@ pub const Y_PADDING: f32 = 0.0;
 pub const X_PADDING: f32 = 20.0;
 pub const CURSOR_WIDTH: f64 = 4.3;
 pub const CURSOR_HEIGHT: f64 = 42.;
+pub const DIVIDER_WIDTH: f64 = 1.;
 
 impl FontRender {
-    fn render(&self, scene: &mut Scene, y_scroll: f32, buffer: &TextBuffer) -> (GlyphPosCache, LineCache) {
+    fn render(&self, scene: &mut Scene, viewport: &Viewport, y_scroll: f32, buffer: &TextBuffer) -> (GlyphPosCache, LineCache) {
         log::info!("begin render");
         // main font
         let file_ref = skrifa::raw::FileRef::new(self.font.data.as_ref()).unwrap();
@@ -102,8 +125,9 @@ impl FontRender {
         let mut line_nr = start_line as usize;
 
 
-        let off_x = self.style.voffset_x;
-        let off_y = start_line*line_height - y_scroll + self.style.voffset_y;
+        let off_x = viewport.x + X_PADDING;
+        let off_y = start_line*line_height - y_scroll + viewport.y;
+        let vwidth = viewport.width - X_PADDING;
         line_cache.push(pen_y + off_y);
         scene
             .draw_glyphs(&self.font)
@@ -118,8 +142,8 @@ impl FontRender {
                     let c = c.to_string().chars().nth(0).unwrap();
                     let prev_index = index;
                     index += probably_one;
-                    if c != '\n' && pen_x > self.style.vwidth { // if we're off screen just skip
-                        return if self.style.vheight + y_scroll <= (line_nr as f32) * line_height {
+                    if c != '\n' && pen_x > vwidth { // if we're off screen just skip
+                        return if viewport.height + y_scroll <= (line_nr as f32) * line_height {
                             // if we're off screen and last line we're done
                             FMTOption::Terminate
                         } else {
@@ -174,7 +198,7 @@ impl FontRender {
             .draw_glyphs(&self.fallback_font)
             .font_size(self.style.font_size)
             .brush(&peniko::Brush::Solid(self.style.fg_color))
-            .transform(Affine::translate((self.style.voffset_x as f64, (start_line*line_height - y_scroll + self.style.voffset_y) as f64)))
+            .transform(Affine::translate((off_x as f64, off_y as f64)))
             .glyph_transform(None)
             .draw(
                 NonZero,
@@ -196,6 +220,80 @@ impl FontRender {
     }
 }
 
+impl FontRender {
+    // one line of plain text (no fallback font), `y` is the top of the line
+    fn render_line(&self, scene: &mut Scene, text: &str, x: f32, y: f32, color: peniko::Color) {
+        let file_ref = skrifa::raw::FileRef::new(self.font.data.as_ref()).unwrap();
+        let font_ref = match file_ref {
+            skrifa::raw::FileRef::Font(f) => Some(f),
+            skrifa::raw::FileRef::Collection(c) => c.get(self.font.index).ok(),
+        }
+        .unwrap();
+        let charmap = font_ref.charmap();
+        let settings: Vec<(&str, f32)> = Vec::new();
+        let var_loc = font_ref.axes().location(settings.iter().copied());
+        let glyph_metrics = font_ref.glyph_metrics(skrifa::instance::Size::new(self.style.font_size), &var_loc);
+
+        let mut pen_x = 0f32;
+        scene
+            .draw_glyphs(&self.font)
+            .font_size(self.style.font_size)
+            .brush(&peniko::Brush::Solid(color))
+            .transform(Affine::translate((x as f64, (y + self.style.ascent) as f64)))
+            .glyph_transform(None)
+            .draw(
+                NonZero,
+                text.chars().map(|c| {
+                    let gid = charmap.map(c).unwrap_or_default();
+                    let x = pen_x;
+                    pen_x += glyph_metrics.advance_width(gid).unwrap_or_default();
+                    vello::Glyph {
+                        id: gid.to_u32(),
+                        x,
+                        y: 0.,
+                    }
+                }),
+            );
+    }
+}
+
+// the bottom line of the window: a question or the command line while typing one,
+// otherwise the last message or what's in the focused pane
+fn status_text(prompt: &Option<Prompt>, message: &Option<String>, buffers: &OrdMap<BufferId, TextBuffer>, pane: &Pane) -> String {
+    if let Some(prompt) = prompt {
+        return prompt.question.clone();
+    }
+    if pane.mode == Mode::Command {
+        return format!(":{}", pane.cmdline);
+    }
+    if let Some(msg) = message {
+        return msg.clone();
+    }
+    let buf = &buffers[&pane.buffer_id];
+    let modified = if buf.is_modified() { " [+]" } else { "" };
+    let new = if buf.file.as_ref().is_some_and(|fi| fi.is_new) { " [New]" } else { "" };
+    // only worth mentioning if it isn't plain UTF-8 (a hex dump has no encoding)
+    let encoding = match &buf.file {
+        Some(fi) if fi.hex => " [hex]".to_string(),
+        Some(fi) if !fi.encoding.is_utf8() => format!(" [{}]", fi.encoding.name()),
+        _ => String::new(),
+    };
+    let line_ending = match &buf.file {
+        Some(fi) if fi.line_ending != LineEnding::Lf => format!(" [{}]", fi.line_ending.name()),
+        _ => String::new(),
+    };
+    let compression = match buf.file.as_ref().and_then(|fi| fi.compression) {
+        Some(compression) => format!(" [{}]", compression.name()),
+        None => String::new(),
+    };
+    let read_only = if buf.is_read_only() { " [read only]" } else { "" };
+    let loading = match &buf.loading {
+        Some(progress) => format!(" loading {}% (esc to stop)", progress.percent()),
+        None => String::new(),
+    };
+    format!("{} {}{}{}{}{}{}{}{}", pane.buffer_id, buf.name(), new, compression, encoding, line_ending, read_only, modified, loading)
+}
+
 pub fn get_font_metrics(font: &peniko::Font, font_size: f32) -> (f32, f32) {
     let file_ref = skrifa::raw::FileRef::new(font.data.as_ref()).unwrap();
     let font_ref = match file_ref {
@@ -210,7 +308,7 @@ pub fn get_font_metrics(font: &peniko::Font, font_size: f32) -> (f32, f32) {
     (line_height * 2., metrics.ascent)
 }
 
-pub fn redraw_requested_handler(state: &mut WindowState, buf: &TextBuffer, pane: &Pane) -> (GlyphPosCache, LineCache) {
+pub fn redraw_requested_handler(state: &mut WindowState, buffers: &OrdMap<BufferId, TextBuffer>, panes: &OrdMap<PaneId, Pane>) {
     let renderer = &mut state.renderer;
     let scene = &mut state.scene;
     let font_render = &state.font_render;
@@ -219,36 +317,76 @@ pub fn redraw_requested_handler(state: &mut WindowState, buf: &TextBuffer, pane:
     let frame = state.surface.surface.get_current_texture().unwrap();
 
     scene.reset();
-    let dirty = if let Some(fi) = &buf.file {
-        fi.is_modified
-    } else {
-        false
+    let focused = state.layout.focused;
+    let dirty = match &buffers[&panes[&focused].buffer_id].file {
+        Some(fi) => fi.is_modified,
+        None => false,
     };
-    let (glyph_pos_cache, line_cache) = font_render.render(scene, pane.y_offset, &buf);
-    for c in pane.cursors_iter() {
-        if let Some(pos) = glyph_pos_cache.get(&c.start) {
-            let ((_, _), (x, y)) = *pos;
-            // draw cursor
-            let pos = (x as f64 - CURSOR_WIDTH/2., (y - font_render.style.ascent/2.) as f64 - CURSOR_HEIGHT/2.);
-            if state.should_draw_cursor {
-                let color = if pane.mode == Mode::Normal {
-                    font_render.style.color_scheme.get("blue").unwrap()
-                } else {
-                    font_render.style.color_scheme.get("red-1").unwrap()
-                };
-                scene.fill(NonZero, Affine::translate(pos), color, None, &font_render.style.cursor_shape);
-            }
-            if !c.is_empty() {
-                if let Some(pos) = glyph_pos_cache.get(&c.end()) {
-                    let ((end_x, end_y), (_, _)) = *pos;
-                    let selection = Rect::new(x.min(end_x) as f64, 0., (x - end_x).abs() as f64, font_render.style.line_height as f64);
-                    assert!(end_y == y);
-                    let inframe = Affine::translate((font_render.style.voffset_x as f64, font_render.style.voffset_y as f64));
-                    scene.fill(NonZero, inframe, font_render.style.selection_color, None, &selection);
+    for pane_id in state.layout.panes() {
+        let pane = &panes[&pane_id];
+        let buf = &buffers[&pane.buffer_id];
+        let viewport = font_render.style.viewports[&pane_id];
+        let clip = Rect::new(viewport.x as f64, viewport.y as f64, (viewport.x + viewport.width) as f64, (viewport.y + viewport.height) as f64);
+        scene.push_layer(peniko::Mix::Clip, 1.0, Affine::IDENTITY, &clip);
+
+        let y_scroll = pane.y_offset * font_render.style.line_height;
+        let (glyph_pos_cache, line_cache) = font_render.render(scene, &viewport, y_scroll, buf);
+        for c in pane.cursors_iter() {
+            if let Some(pos) = glyph_pos_cache.get(&c.start) {
+                let ((_, _), (x, y)) = *pos;
+                // draw cursor, only the focused pane blinks
+                let pos = (x as f64 - CURSOR_WIDTH/2., (y - font_render.style.ascent/2.) as f64 - CURSOR_HEIGHT/2.);
+                if pane_id != focused {
+                    let color = font_render.style.color_scheme.get("mono-3").unwrap();
+                    scene.fill(NonZero, Affine::translate(pos), color, None, &font_render.style.cursor_shape);
+                } else if state.should_draw_cursor {
+                    let color = if pane.mode == Mode::Insert {
+                        font_render.style.color_scheme.get("red-1").unwrap()
+                    } else {
+                        font_render.style.color_scheme.get("blue").unwrap()
+                    };
+                    scene.fill(NonZero, Affine::translate(pos), color, None, &font_render.style.cursor_shape);
+                }
+                if !c.is_empty() {
+                    if let Some(&(_, end)) = glyph_pos_cache.get(&c.end()) {
+                        // a line at a time, from the top of the start's line to
+                        // the bottom of the end's, the middle ones all the way across
+                        let ((x0, y0), (x1, y1)) = if end.1 < y || (end.1 == y && end.0 < x) { (end, (x, y)) } else { ((x, y), end) };
+                        let line_height = font_render.style.line_height;
+                        let lines = ((y1 - y0) / line_height).round() as usize;
+                        for i in 0..=lines {
+                            let top = y0 - font_render.style.ascent + i as f32 * line_height;
+                            let from = if i == 0 { x0 } else { viewport.x + X_PADDING };
+                            let to = if i == lines { x1 } else { viewport.x + viewport.width };
+                            let selection = Rect::new(from as f64, top as f64, to as f64, (top + line_height) as f64);
+                            scene.fill(NonZero, Affine::IDENTITY, font_render.style.selection_color, None, &selection);
+                        }
+                    }
                 }
             }
         }
+        scene.pop_layer();
+        state.glyph_pos_caches.insert(pane_id, glyph_pos_cache);
+        state.line_caches.insert(pane_id, line_cache);
+    }
+    // draw the lines between panes
+    let divider_color = font_render.style.color_scheme.get("bg-hl").unwrap();
+    for (dir, v) in state.layout.dividers(font_render.style.text_area()) {
+        let (x, y) = (v.x as f64, v.y as f64);
+        let line = match dir {
+            SplitDir::Horizontal => Rect::new(x, y, x + v.width as f64, y + DIVIDER_WIDTH),
+            SplitDir::Vertical => Rect::new(x, y, x + DIVIDER_WIDTH, y + v.height as f64),
+        };
+        scene.fill(NonZero, Affine::IDENTITY, divider_color, None, &line);
     }
+    // draw status line
+    let status = status_text(&state.prompt, &state.message, buffers, &panes[&focused]);
+    let status_y = font_render.style.voffset_y + font_render.style.vheight;
+    let status_color = *font_render.style.color_scheme.get("mono-2").unwrap();
+    let bar = Rect::new(0., status_y as f64, width as f64, (status_y + font_render.style.line_height) as f64);
+    scene.fill(NonZero, Affine::IDENTITY, font_render.style.color_scheme.get("bg-1").unwrap(), None, &bar);
+    font_render.render_line(scene, &status, X_PADDING, status_y, status_color);
+
     // draw titlebar
     scene.fill(NonZero, Affine::IDENTITY, font_render.style.bg_color, None, &state.font_render.style.titlebar);
     renderer
@@ -272,7 +410,6 @@ pub fn redraw_requested_handler(state: &mut WindowState, buf: &TextBuffer, pane:
     } else {
         state.window.set_document_edited(false);
     }
-    (glyph_pos_cache, line_cache)
 }
 
 pub fn blink_cursor(renderer_tx: mpsc::Sender<CustomEvent>, event_loop_proxy: winit::event_loop::EventLoopProxy, last_key: mpsc::Receiver<()>) {
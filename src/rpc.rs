@@ -0,0 +1,392 @@
+// Remote control: JSON-RPC 2.0 on a Unix socket, for scripts and other tools to
+// drive chop with. Only the chop that's listening for files (see `server`)
+// listens here too. Each request and response is a line of JSON, and offsets
+// are in bytes:
+//
+//   buffers                                  -> [{id, name, path, modified, read_only, loading, version}]
+//   panes                                    -> [{id, buffer, cursors: [{cursor, anchor}]}], main cursor first
+//   contents {buffer, start?, end?}          -> {text, version}
+//   edit {pane, edits: [{start, end, text}]} -> {version}
+//   set_cursors {pane, cursors: [{cursor, anchor?}]}, the first is the main one
+//   command {pane, command}                  like typing `:command` in the pane
+//
+// Edits are made one after the other, each to the text as the ones before it
+// left it, and they stop at the first one that can't be made. They're made in
+// the pane's buffer, but the pane's cursors only move along with the text.
+//
+// It's all done with `Op`s, same as keys: edits and cursors go straight to
+// the buffer thread, commands through the window (some of them are handled
+// there). The response is sent once they've been done.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc};
+use std::thread;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use crate::buffer::{BufferId, BufferMsg, BufferOp, Op, TextBuffer};
+use crate::command;
+use crate::pane::{Pane, PaneId, Selection};
+use crate::registry::Registry;
+
+const PARSE_ERROR: i64 = -32700;
+const INVALID_REQUEST: i64 = -32600;
+const METHOD_NOT_FOUND: i64 = -32601;
+const INVALID_PARAMS: i64 = -32602;
+// it was understood, chop just can't do it (read only, no such buffer, ...)
+const FAILED: i64 = -32000;
+
+#[derive(Debug, Clone, PartialEq)]
+struct Error {
+    code: i64,
+    message: String,
+}
+
+impl Error {
+    fn new(code: i64, message: impl Into<String>) -> Self {
+        Error {code, message: message.into()}
+    }
+}
+
+// sent after the ops for a request (or with them), the buffer thread says when
+// it gets to it, and whether they could be done
+#[derive(Debug, Clone)]
+pub struct Ack(Arc<mpsc::Sender<Result<(), String>>>);
+
+impl PartialEq for Ack {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Eq for Ack {}
+
+impl Ack {
+    fn new() -> (Self, mpsc::Receiver<Result<(), String>>) {
+        let (tx, rx) = mpsc::channel();
+        (Ack(Arc::new(tx)), rx)
+    }
+
+    pub fn done(&self) {
+        let _ = self.0.send(Ok(()));
+    }
+
+    pub fn failed(&self, msg: String) {
+        let _ = self.0.send(Err(msg));
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Cursor {
+    cursor: usize,
+    // the other end of the selection, if there is one
+    anchor: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct ContentsParams {
+    buffer: BufferId,
+    start: Option<usize>,
+    end: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct TextEdit {
+    start: usize,
+    end: usize,
+    text: String,
+}
+
+#[derive(Deserialize)]
+struct EditParams {
+    pane: PaneId,
+    edits: Vec<TextEdit>,
+}
+
+#[derive(Deserialize)]
+struct CursorsParams {
+    pane: PaneId,
+    cursors: Vec<Cursor>,
+}
+
+#[derive(Deserialize)]
+struct CommandParams {
+    pane: PaneId,
+    command: String,
+}
+
+pub struct Rpc {
+    buffers: Arc<Registry<TextBuffer>>,
+    panes: Arc<Registry<Pane>>,
+    buffer_tx: mpsc::Sender<BufferMsg>,
+    // runs ops in the window with the pane, as if they were typed in it, then
+    // acks once the buffer thread's done them
+    run_ops: Box<dyn Fn(PaneId, Vec<Op>, Ack) + Send + Sync>,
+}
+
+// next to `server`'s
+pub fn socket_path() -> PathBuf {
+    std::env::temp_dir().join(format!("chop-{}.rpc.sock", unsafe { libc::getuid() }))
+}
+
+impl Rpc {
+    pub fn new(buffers: Arc<Registry<TextBuffer>>, panes: Arc<Registry<Pane>>, buffer_tx: mpsc::Sender<BufferMsg>, run_ops: Box<dyn Fn(PaneId, Vec<Op>, Ack) + Send + Sync>) -> Self {
+        Rpc {buffers, panes, buffer_tx, run_ops}
+    }
+
+    // a thread for the socket, and one for each client
+    pub fn listen(self, path: &Path) -> io::Result<()> {
+        // left by one that crashed, `server` found nothing listening
+        let _ = fs::remove_file(path);
+        let listener = UnixListener::bind(path)?;
+        let rpc = Arc::new(self);
+        thread::spawn(move || {
+            for stream in listener.incoming() {
+                match stream {
+                    Ok(stream) => {
+                        let rpc = rpc.clone();
+                        thread::spawn(move || rpc.serve(stream));
+                    },
+                    Err(e) => log::warn!("couldn't accept an rpc client: {e}"),
+                }
+            }
+        });
+        Ok(())
+    }
+
+    fn serve(&self, stream: UnixStream) {
+        let mut out = &stream;
+        for line in BufReader::new(&stream).lines() {
+            let Ok(line) = line else {
+                break;
+            };
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Some(response) = self.call(&line) {
+                if writeln!(out, "{response}").is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    // the response to a line of JSON, None if it was a notification (no id)
+    pub fn call(&self, line: &str) -> Option<String> {
+        let (id, result) = match serde_json::from_str::<Value>(line) {
+            Ok(request) => {
+                let result = match request.get("method").and_then(Value::as_str) {
+                    Some(method) => self.dispatch(method, request.get("params").cloned().unwrap_or(Value::Null)),
+                    None => Err(Error::new(INVALID_REQUEST, "no method")),
+                };
+                (request.get("id").cloned()?, result)
+            },
+            Err(e) => (Value::Null, Err(Error::new(PARSE_ERROR, e.to_string()))),
+        };
+        let response = match result {
+            Ok(result) => json!({"jsonrpc": "2.0", "id": id, "result": result}),
+            Err(e) => json!({"jsonrpc": "2.0", "id": id, "error": {"code": e.code, "message": e.message}}),
+        };
+        Some(response.to_string())
+    }
+
+    fn dispatch(&self, method: &str, params: Value) -> Result<Value, Error> {
+        match method {
+            "buffers" => Ok(self.buffers.get().iter().map(|(id, b)| json!({
+                "id": id,
+                "name": b.name(),
+                "path": b.file.as_ref().map(|fi| fi.filename.display().to_string()),
+                "modified": b.is_modified(),
+                "read_only": b.is_read_only(),
+                "loading": b.loading.is_some(),
+                "version": b.version,
+            })).collect()),
+            "panes" => Ok(self.panes.get().values().map(|pane| {
+                let mut cursors: Vec<Cursor> = pane.cursors_iter()
+                    .map(|s| Cursor {cursor: s.start, anchor: Some(s.end())})
+                    .collect();
+                cursors.sort_by_key(|c| c.cursor != pane.main_cursor_start);
+                json!({"id": pane.id, "buffer": pane.buffer_id, "cursors": cursors})
+            }).collect()),
+            "contents" => {
+                let p: ContentsParams = parse(params)?;
+                let buffer = self.buffers.get().get(&p.buffer).cloned().ok_or_else(|| Error::new(FAILED, format!("buffer {} does not exist", p.buffer)))?;
+                let range = p.start.unwrap_or(0)..p.end.unwrap_or(buffer.contents.byte_len());
+                buffer.check_range(&range).map_err(|msg| Error::new(INVALID_PARAMS, msg))?;
+                Ok(json!({"text": buffer.contents.byte_slice(range).to_string(), "version": buffer.version}))
+            },
+            "edit" => {
+                let p: EditParams = parse(params)?;
+                for e in p.edits {
+                    let buffer = self.editable(p.pane)?;
+                    buffer.check_range(&(e.start..e.end)).map_err(|msg| Error::new(INVALID_PARAMS, msg))?;
+                    // it can still change before the buffer thread gets to it
+                    let (ack, done) = Ack::new();
+                    let _ = self.buffer_tx.send(BufferMsg::Op(BufferOp::Replace {start: e.start, end: e.end, text: e.text, ack}, vec![p.pane]));
+                    done.recv().unwrap_or_else(|_| Err(format!("pane {} was closed", p.pane))).map_err(|msg| Error::new(FAILED, msg))?;
+                }
+                Ok(json!({"version": self.buffer_in(p.pane)?.version}))
+            },
+            "set_cursors" => {
+                let p: CursorsParams = parse(params)?;
+                let buffer = self.buffer_in(p.pane)?;
+                let mut cursors = vec![];
+                for c in p.cursors {
+                    let anchor = c.anchor.unwrap_or(c.cursor);
+                    buffer.check_range(&(c.cursor.min(anchor)..c.cursor.max(anchor))).map_err(|msg| Error::new(INVALID_PARAMS, msg))?;
+                    cursors.push(Selection {start: c.cursor, offset: anchor as i64 - c.cursor as i64});
+                }
+                self.run(p.pane, vec![BufferOp::SetCursors(cursors)]);
+                Ok(Value::Null)
+            },
+            "command" => {
+                let p: CommandParams = parse(params)?;
+                self.buffer_in(p.pane)?;
+                let ops = command::parse(&p.command).map_err(|msg| Error::new(FAILED, msg))?;
+                let (ack, done) = Ack::new();
+                (self.run_ops)(p.pane, ops, ack);
+                // (or it was dropped, if the pane closed first)
+                let _ = done.recv();
+                Ok(Value::Null)
+            },
+            _ => Err(Error::new(METHOD_NOT_FOUND, format!("no method {method}"))),
+        }
+    }
+
+    // to the buffer thread, back once it's done them
+    fn run(&self, pane: PaneId, ops: Vec<BufferOp>) {
+        let (ack, done) = Ack::new();
+        for op in ops {
+            let _ = self.buffer_tx.send(BufferMsg::Op(op, vec![pane]));
+        }
+        let _ = self.buffer_tx.send(BufferMsg::Ack(ack));
+        let _ = done.recv();
+    }
+
+    fn buffer_in(&self, pane: PaneId) -> Result<TextBuffer, Error> {
+        let buffer_id = self.panes.get().get(&pane).map(|p| p.buffer_id).ok_or_else(|| Error::new(FAILED, format!("pane {pane} does not exist")))?;
+        self.buffers.get().get(&buffer_id).cloned().ok_or_else(|| Error::new(FAILED, format!("buffer {buffer_id} does not exist")))
+    }
+
+    fn editable(&self, pane: PaneId) -> Result<TextBuffer, Error> {
+        let buffer = self.buffer_in(pane)?;
+        if buffer.loading.is_some() {
+            return Err(Error::new(FAILED, format!("{} is still loading", buffer.name())));
+        }
+        if buffer.is_read_only() || buffer.is_hex() {
+            return Err(Error::new(FAILED, format!("{} is read only", buffer.name())));
+        }
+        Ok(buffer)
+    }
+}
+
+fn parse<T: DeserializeOwned>(params: Value) -> Result<T, Error> {
+    serde_json::from_value(params).map_err(|e| Error::new(INVALID_PARAMS, e.to_string()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::TestDir;
+    use crop::Rope;
+    use crate::buffer::{buffer_op_handler, Context, Ui};
+    use crate::observer::Observers;
+
+    // the real buffer thread, without a window to draw in
+    fn buffer_thread(buffers: &Arc<Registry<TextBuffer>>, panes: &Arc<Registry<Pane>>) -> mpsc::Sender<BufferMsg> {
+        let (buffer_tx, buffer_rx) = mpsc::channel();
+        let (render_tx, _render_rx) = mpsc::channel();
+        let cx = Context {buffers: buffers.clone(), panes: panes.clone(), observers: Arc::new(Observers::new()), buffer_tx: buffer_tx.clone(), ui: Ui::headless(render_tx)};
+        thread::spawn(buffer_op_handler(cx, buffer_rx));
+        buffer_tx
+    }
+
+    #[test]
+    fn test_rpc() {
+        let dir = TestDir::new("rpc");
+        let buffers = Arc::new(Registry::new());
+        let panes = Arc::new(Registry::new());
+        let buf_id = buffers.insert_with(|_| TextBuffer::new(None, Rope::from("hello\nworld\n")));
+        let pane_id = panes.insert_with(|id| Pane::new(buf_id, id));
+        let buffer_tx = buffer_thread(&buffers, &panes);
+        // the window passes on what it doesn't handle itself
+        let to_buffer_thread = buffer_tx.clone();
+        let run_ops = Box::new(move |pane, ops: Vec<Op>, ack| {
+            for op in ops {
+                if let Op::Buffer(op) = op {
+                    to_buffer_thread.send(BufferMsg::Op(op, vec![pane])).unwrap();
+                }
+            }
+            to_buffer_thread.send(BufferMsg::Ack(ack)).unwrap();
+        });
+        let path = dir.join("rpc.sock");
+        Rpc::new(buffers.clone(), panes.clone(), buffer_tx.clone(), run_ops).listen(&path).unwrap();
+
+        let stream = UnixStream::connect(&path).unwrap();
+        let mut lines = BufReader::new(stream.try_clone().unwrap()).lines();
+        let mut call = |request: Value| -> Value {
+            writeln!(&stream, "{request}").unwrap();
+            serde_json::from_str(&lines.next().unwrap().unwrap()).unwrap()
+        };
+
+        let response = call(json!({"jsonrpc": "2.0", "id": 1, "method": "buffers"}));
+        assert_eq!(response["result"][0]["id"], buf_id);
+        assert_eq!(response["result"][0]["name"], "[No Name]");
+        let response = call(json!({"jsonrpc": "2.0", "id": 2, "method": "panes"}));
+        assert_eq!(response["result"][0]["cursors"], json!([{"cursor": 0, "anchor": 0}]));
+
+        let edits = json!([{"start": 0, "end": 1, "text": "J"}, {"start": 5, "end": 5, "text": "!"}]);
+        let response = call(json!({"jsonrpc": "2.0", "id": 3, "method": "edit", "params": {"pane": pane_id, "edits": edits}}));
+        assert_eq!(response["result"]["version"], buffers.get()[&buf_id].version);
+        let response = call(json!({"jsonrpc": "2.0", "id": 4, "method": "contents", "params": {"buffer": buf_id, "end": 7}}));
+        assert_eq!(response["result"]["text"], "Jello!\n");
+
+        call(json!({"jsonrpc": "2.0", "id": 5, "method": "set_cursors", "params": {"pane": pane_id, "cursors": [{"cursor": 3, "anchor": 1}]}}));
+        let pane = &panes.get()[&pane_id];
+        assert_eq!(pane.cursors_iter().collect::<Vec<_>>(), vec![&Selection {start: 3, offset: -2}]);
+        let saved = dir.join("x.txt");
+        call(json!({"jsonrpc": "2.0", "id": 6, "method": "command", "params": {"pane": pane_id, "command": format!("w {}", saved.display())}}));
+        assert_eq!(fs::read_to_string(&saved).unwrap(), "Jello!\nworld\n");
+
+        // what can't be done
+        let response = call(json!({"jsonrpc": "2.0", "id": 7, "method": "edit", "params": {"pane": pane_id, "edits": [{"start": 0, "end": 99, "text": ""}]}}));
+        assert_eq!(response["error"]["code"], INVALID_PARAMS);
+        assert_eq!(call(json!({"jsonrpc": "2.0", "id": 8, "method": "contents", "params": {"buffer": 99}}))["error"]["code"], FAILED);
+        assert_eq!(call(json!({"jsonrpc": "2.0", "id": 9, "method": "bogus"}))["error"]["code"], METHOD_NOT_FOUND);
+        writeln!(&stream, "{{not json").unwrap();
+        assert_eq!(serde_json::from_str::<Value>(&lines.next().unwrap().unwrap()).unwrap()["error"]["code"], PARSE_ERROR);
+        // the buffer thread says so too, when it's changed since it was checked
+        let (ack, done) = Ack::new();
+        buffer_tx.send(BufferMsg::Op(BufferOp::Replace {start: 0, end: 99, text: String::new(), ack}, vec![pane_id])).unwrap();
+        assert!(done.recv().unwrap().is_err());
+    }
+
+    #[test]
+    fn test_closed_pane() {
+        let buffers = Arc::new(Registry::new());
+        let panes = Arc::new(Registry::new());
+        let buf_id = buffers.insert_with(|_| TextBuffer::new(None, Rope::from("hello")));
+        let open = panes.insert_with(|id| Pane::new(buf_id, id));
+        let closed = panes.insert_with(|id| Pane::new(buf_id, id));
+        let buffer_tx = buffer_thread(&buffers, &panes);
+
+        // what was still on its way to it when it closed
+        buffer_tx.send(BufferMsg::Op(BufferOp::ClosePane { discard: false }, vec![closed])).unwrap();
+        buffer_tx.send(BufferMsg::Op(BufferOp::Insert("lost".to_string()), vec![closed])).unwrap();
+        buffer_tx.send(BufferMsg::Op(BufferOp::SetCursors(vec![Selection {start: 1, offset: 0}]), vec![closed])).unwrap();
+        let (ack, done) = Ack::new();
+        buffer_tx.send(BufferMsg::Op(BufferOp::Replace {start: 0, end: 0, text: "lost".to_string(), ack}, vec![closed])).unwrap();
+        assert!(done.recv().is_err());
+
+        // the buffer thread's still going
+        let (ack, done) = Ack::new();
+        buffer_tx.send(BufferMsg::Op(BufferOp::Replace {start: 5, end: 5, text: "!".to_string(), ack}, vec![open])).unwrap();
+        assert_eq!(done.recv().unwrap(), Ok(()));
+        assert_eq!(buffers.get()[&buf_id].contents.to_string(), "hello!");
+        assert!(!panes.get().contains_key(&closed));
+    }
+}
@@ -0,0 +1,239 @@
+// Only one chop runs at a time (per user). The first one listens on a Unix
+// socket, and `chop file` with one already running hands it the files instead
+// of starting another: it connects, sends a `Request` (one line of JSON), and
+// gets back one `Reply` once the files are open, or once they're closed again
+// with `--wait`. They're opened in a new window (a tab, on macOS). Files opened
+// from Finder (or with `open`) are opened the same way, see `AppDelegate`.
+//
+// `-n` starts one that's on its own, it doesn't listen either.
+
+use std::fs;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use serde::{Deserialize, Serialize};
+
+use crate::buffer::{CustomEvent, Ui};
+use crate::cli::{Input, Options};
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Request {
+    pub files: Vec<Input>,
+    pub read_only: bool,
+    pub wait: bool,
+}
+
+impl Request {
+    // paths are made absolute, since the other one's somewhere else, and stdin's
+    // read now, since it can't read ours
+    pub fn of(options: &Options) -> io::Result<Self> {
+        let files = options.files.iter().map(|input| Ok(match input {
+            Input::File { path, line, col } => {
+                let path = std::path::absolute(path)?.to_string_lossy().into_owned();
+                Input::File { path, line: *line, col: *col }
+            },
+            Input::Stdin => {
+                let mut bytes = vec![];
+                io::stdin().read_to_end(&mut bytes)?;
+                Input::Text(String::from_utf8_lossy(&bytes).into_owned())
+            },
+            Input::Text(text) => Input::Text(text.clone()),
+        })).collect::<io::Result<_>>()?;
+        Ok(Request {files, read_only: options.read_only, wait: options.wait})
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Reply {
+    // what to exit with
+    Done(i32),
+    // none of them were opened
+    Error(String),
+}
+
+// who sent a request, to reply to once
+#[derive(Debug, Clone)]
+pub struct Client(Arc<Mutex<Option<UnixStream>>>);
+
+impl PartialEq for Client {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+impl Client {
+    // for files no-one's waiting on (from Finder), replies go nowhere
+    pub fn nobody() -> Self {
+        Client(Arc::new(Mutex::new(None)))
+    }
+
+    pub fn replied(&self) -> bool {
+        self.0.lock().unwrap().is_none()
+    }
+
+    // only the first reply's sent, then it's hung up on
+    pub fn reply(&self, reply: &Reply) {
+        if let Some(mut stream) = self.0.lock().unwrap().take() {
+            let json = serde_json::to_string(reply).unwrap();
+            if let Err(e) = writeln!(stream, "{json}") {
+                log::warn!("couldn't reply to a client: {e}");
+            }
+        }
+    }
+}
+
+// one per user. $TMPDIR is per user on macOS already, but not everywhere
+pub fn socket_path() -> PathBuf {
+    std::env::temp_dir().join(format!("chop-{}.sock", unsafe { libc::getuid() }))
+}
+
+// None if there's no chop running
+pub fn connect(path: &Path) -> Option<UnixStream> {
+    UnixStream::connect(path).ok()
+}
+
+// what to exit with, once the chop on the other end is done with the files
+pub fn send(mut stream: UnixStream, request: &Request) -> io::Result<i32> {
+    writeln!(stream, "{}", serde_json::to_string(request)?)?;
+    let mut line = String::new();
+    BufReader::new(stream).read_line(&mut line)?;
+    match serde_json::from_str(&line) {
+        Ok(Reply::Done(code)) => Ok(code),
+        Ok(Reply::Error(msg)) => {
+            eprintln!("chop: {msg}");
+            Ok(1)
+        },
+        // it went away before it was done with them
+        Err(_) => Ok(1),
+    }
+}
+
+// be the one that's running. Requests come in as `CustomEvent::Open`s
+pub fn listen(path: &Path, ui: Ui) -> io::Result<()> {
+    // it's only ours to take over if nothing answers on it, then it was left
+    // by one that crashed. One that started just before us does answer
+    match UnixStream::connect(path) {
+        Ok(_) => return Err(io::Error::new(io::ErrorKind::AddrInUse, "another chop is listening on it")),
+        Err(e) if e.kind() == io::ErrorKind::ConnectionRefused => {
+            let _ = fs::remove_file(path);
+        },
+        Err(_) => {},
+    }
+    let listener = UnixListener::bind(path)?;
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    log::warn!("couldn't accept a client: {e}");
+                    continue;
+                },
+            };
+            let ui = ui.clone();
+            // a client that doesn't say anything shouldn't hold up the rest
+            thread::spawn(move || {
+                if let Some((request, client)) = accept(stream) {
+                    ui.send(CustomEvent::Open(request, client));
+                }
+            });
+        }
+    });
+    Ok(())
+}
+
+fn accept(stream: UnixStream) -> Option<(Request, Client)> {
+    let mut line = String::new();
+    if let Err(e) = BufReader::new(&stream).read_line(&mut line) {
+        log::warn!("couldn't read from a client: {e}");
+        return None;
+    }
+    let client = Client(Arc::new(Mutex::new(Some(stream))));
+    match serde_json::from_str(&line) {
+        Ok(request) => Some((request, client)),
+        Err(e) => {
+            client.reply(&Reply::Error(format!("bad request: {e}")));
+            None
+        },
+    }
+}
+
+// the path in a `file://` URL, which is percent-encoded
+pub fn path_of_url(url: &str) -> Option<String> {
+    let path = url.strip_prefix("file://")?;
+    let path = path.strip_prefix("localhost").unwrap_or(path);
+    let bytes = path.as_bytes();
+    let mut decoded = vec![];
+    let mut i = 0;
+    while i < bytes.len() {
+        let escaped = bytes.get(i + 1..i + 3)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match (bytes[i], escaped) {
+            (b'%', Some(byte)) => {
+                decoded.push(byte);
+                i += 3;
+            },
+            (byte, _) => {
+                decoded.push(byte);
+                i += 1;
+            },
+        }
+    }
+    String::from_utf8(decoded).ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::TestDir;
+
+    #[test]
+    fn test_request_and_reply() {
+        let dir = TestDir::new("server");
+        let path = dir.join("chop.sock");
+        let listener = UnixListener::bind(&path).unwrap();
+        let request = Request {
+            files: vec![Input::File { path: "/tmp/a.rs".into(), line: Some(3), col: None }, Input::Text("piped\n".into())],
+            read_only: false,
+            wait: true,
+        };
+        let sent = request.clone();
+        let stream = connect(&path).unwrap();
+        let sender = thread::spawn(move || send(stream, &sent).unwrap());
+
+        let (received, client) = accept(listener.accept().unwrap().0).unwrap();
+        assert_eq!(received, request);
+        client.reply(&Reply::Done(1));
+        // only the first counts
+        client.reply(&Reply::Done(0));
+        assert_eq!(sender.join().unwrap(), 1);
+
+        // it hung up without replying
+        let stream = connect(&path).unwrap();
+        let sender = thread::spawn(move || send(stream, &request).unwrap());
+        drop(accept(listener.accept().unwrap().0));
+        assert_eq!(sender.join().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_listen() {
+        let dir = TestDir::new("listen");
+        let path = dir.join("chop.sock");
+        // one that crashed left it behind
+        drop(UnixListener::bind(&path).unwrap());
+        let (render_tx, _render_rx) = std::sync::mpsc::channel();
+        listen(&path, Ui::headless(render_tx.clone())).unwrap();
+        // but this one's still there
+        assert_eq!(listen(&path, Ui::headless(render_tx)).unwrap_err().kind(), io::ErrorKind::AddrInUse);
+        assert!(connect(&path).is_some());
+    }
+
+    #[test]
+    fn test_path_of_url() {
+        assert_eq!(path_of_url("file:///Users/me/My%20Notes/caf%C3%A9.txt").as_deref(), Some("/Users/me/My Notes/café.txt"));
+        assert_eq!(path_of_url("file://localhost/tmp/100%"), Some("/tmp/100%".to_string()));
+        assert_eq!(path_of_url("https://example.com/"), None);
+    }
+}
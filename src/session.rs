@@ -0,0 +1,230 @@
+// What was open when chop exited, so starting it again (without any files to
+// open) picks up where it left off: the files, each window's size and splits,
+// and where each pane was in its file. There's a session per directory chop is
+// started in, so each project gets its own. Starting with files to open (or
+// stdin) neither restores nor saves it.
+//
+// Buffers without a file aren't kept, a pane that showed one comes back empty.
+// The files are read again on startup like any other, and each pane is put
+// back where it was once its file is in (see `BufferOp::RestoreView`).
+
+use std::io;
+use std::path::{Path, PathBuf};
+use im::OrdMap;
+use serde::{Deserialize, Serialize};
+use winit::dpi::PhysicalSize;
+
+use crate::buffer::{BufferId, BufferOp, TextBuffer};
+use crate::file::{self, atomic_write};
+use crate::layout::{Layout, Node, SplitDir};
+use crate::pane::{Mode, Pane, PaneId, Selection};
+use crate::registry::Registry;
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Session {
+    // every buffer with a file, shown or not
+    pub files: Vec<PathBuf>,
+    pub windows: Vec<WindowSession>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct WindowSession {
+    // in physical pixels
+    pub width: u32,
+    pub height: u32,
+    pub layout: Split,
+    // these are indexes into `panes`
+    pub focused: usize,
+    pub panes: Vec<PaneSession>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub enum Split {
+    Pane(usize),
+    Split {
+        vertical: bool,
+        ratio: f32,
+        first: Box<Split>,
+        second: Box<Split>,
+    },
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct PaneSession {
+    pub file: Option<PathBuf>,
+    // (start, offset) of each `Selection`
+    pub cursors: Vec<(usize, i64)>,
+    pub main_cursor_start: usize,
+    pub y_offset: f32,
+    pub insert: bool,
+}
+
+// a window to open on startup, None is the default size
+pub type StartWindow = (Layout, Option<PhysicalSize<u32>>);
+
+// where the session for the current directory goes
+pub fn file() -> Option<PathBuf> {
+    let cwd = std::env::current_dir().ok()?;
+    Some(file::state_dir("sessions")?.join(format!("{}.json", file::path_key(&cwd))))
+}
+
+impl Split {
+    // `panes` gets the pane of each `Pane(i)` at `i`
+    fn from_node(node: &Node, panes: &mut Vec<PaneId>) -> Self {
+        match node {
+            Node::Leaf(id) => {
+                panes.push(*id);
+                Split::Pane(panes.len() - 1)
+            },
+            Node::Split {dir, ratio, first, second} => Split::Split {
+                vertical: *dir == SplitDir::Vertical,
+                ratio: *ratio,
+                first: Box::new(Split::from_node(first, panes)),
+                second: Box::new(Split::from_node(second, panes)),
+            },
+        }
+    }
+
+    // None if it has a pane that isn't in `ids`
+    fn to_node(&self, ids: &[PaneId]) -> Option<Node> {
+        Some(match self {
+            Split::Pane(i) => Node::Leaf(*ids.get(*i)?),
+            Split::Split {vertical, ratio, first, second} => Node::Split {
+                dir: if *vertical { SplitDir::Vertical } else { SplitDir::Horizontal },
+                ratio: *ratio,
+                first: Box::new(first.to_node(ids)?),
+                second: Box::new(second.to_node(ids)?),
+            },
+        })
+    }
+}
+
+impl Session {
+    pub fn capture<'a>(windows: impl Iterator<Item = (PhysicalSize<u32>, &'a Layout)>, buffers: &OrdMap<BufferId, TextBuffer>, panes: &OrdMap<PaneId, Pane>) -> Self {
+        let files = buffers.values().filter_map(|b| b.file.as_ref()).map(|fi| fi.filename.to_path_buf()).collect();
+        let windows = windows.map(|(size, layout)| {
+            let mut ids = vec![];
+            let split = Split::from_node(&layout.root, &mut ids);
+            let panes = ids.iter().map(|id| {
+                let pane = &panes[id];
+                PaneSession {
+                    file: buffers.get(&pane.buffer_id).and_then(|b| b.file.as_ref()).map(|fi| fi.filename.to_path_buf()),
+                    cursors: pane.cursors_iter().map(|s| (s.start, s.offset)).collect(),
+                    main_cursor_start: pane.main_cursor_start,
+                    y_offset: pane.y_offset,
+                    insert: pane.mode == Mode::Insert,
+                }
+            }).collect();
+            let focused = ids.iter().position(|id| *id == layout.focused).unwrap_or(0);
+            WindowSession {width: size.width, height: size.height, layout: split, focused, panes}
+        }).collect();
+        Session {files, windows}
+    }
+
+    // None if there isn't one (or it can't be read, which is logged)
+    pub fn load(path: &Path) -> Option<Self> {
+        let json = match std::fs::read(path) {
+            Ok(json) => json,
+            Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+            Err(e) => {
+                log::error!("couldn't read the session {}: {e}", path.display());
+                return None;
+            },
+        };
+        serde_json::from_slice(&json).inspect_err(|e| log::error!("couldn't parse the session {}: {e}", path.display())).ok()
+    }
+
+    pub fn save(&self, path: &Path) -> io::Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        atomic_write(path, std::iter::once(serde_json::to_vec_pretty(self)?))
+    }
+
+    // open the files and make the panes. Gives back the windows to open, and
+    // the `RestoreView` for each pane to send the buffer thread
+    pub fn restore(&self, buffers: &Registry<TextBuffer>, panes: &Registry<Pane>) -> (Vec<StartWindow>, Vec<(BufferOp, PaneId)>) {
+        let mut open: OrdMap<PathBuf, BufferId> = OrdMap::new();
+        let mut buffer_for = |path: &Path| -> Option<BufferId> {
+            if let Some(id) = open.get(path) {
+                return Some(*id);
+            }
+            match TextBuffer::pending(&path.to_string_lossy()) {
+                Ok(buffer) => {
+                    let id = buffers.insert_with(|_| buffer);
+                    open.insert(path.to_path_buf(), id);
+                    Some(id)
+                },
+                Err(e) => {
+                    log::error!("couldn't reopen {}: {e}", path.display());
+                    None
+                },
+            }
+        };
+        for path in &self.files {
+            buffer_for(path);
+        }
+
+        let mut windows = vec![];
+        let mut views = vec![];
+        for window in &self.windows {
+            let ids: Vec<PaneId> = window.panes.iter().map(|saved| {
+                let buf_id = saved.file.as_deref().and_then(&mut buffer_for)
+                    .unwrap_or_else(|| buffers.insert_with(|_| TextBuffer::from_blank()));
+                let pane_id = panes.insert_with(|pane_id| {
+                    let mode = if saved.insert { Mode::Insert } else { Mode::Normal };
+                    Pane {mode, ..Pane::new(buf_id, pane_id)}
+                });
+                let cursors = saved.cursors.iter().map(|(start, offset)| Selection {start: *start, offset: *offset}).collect();
+                let restore = BufferOp::RestoreView { cursors, main_cursor_start: saved.main_cursor_start, top_line: saved.y_offset.max(0.) as usize };
+                views.push((restore, pane_id));
+                pane_id
+            }).collect();
+            let Some(root) = window.layout.to_node(&ids) else {
+                log::error!("a window in the session has a pane that isn't there, skipping it");
+                continue;
+            };
+            let focused = ids.get(window.focused).copied().unwrap_or(ids[0]);
+            let size = (window.width > 0 && window.height > 0).then(|| PhysicalSize::new(window.width, window.height));
+            windows.push((Layout {root, focused}, size));
+        }
+        (windows, views)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::TestDir;
+
+    #[test]
+    fn test_capture_and_restore() {
+        let dir = TestDir::new("session");
+        let (buffers, panes) = (Registry::new(), Registry::new());
+        let file = buffers.insert_with(|_| TextBuffer::new_file(dir.join("a.txt").to_str().unwrap()));
+        buffers.insert_with(|_| TextBuffer::new_file(dir.join("hidden.txt").to_str().unwrap()));
+        let blank = buffers.insert_with(|_| TextBuffer::from_blank());
+        let first = panes.insert_with(|id| Pane {main_cursor_start: 3, y_offset: 2.5, ..Pane::new(file, id)});
+        let second = panes.insert_with(|id| Pane::new(blank, id));
+        let mut layout = Layout::new(first);
+        layout.split(SplitDir::Vertical, second);
+
+        let session = Session::capture(std::iter::once((PhysicalSize::new(800, 600), &layout)), &buffers.get(), &panes.get());
+        assert_eq!(session.files.len(), 2);
+        let path = dir.join("session.json");
+        session.save(&path).unwrap();
+        let loaded = Session::load(&path).unwrap();
+        assert_eq!(loaded, session);
+
+        let (buffers, panes) = (Registry::new(), Registry::new());
+        let (windows, views) = loaded.restore(&buffers, &panes);
+        // the two files and a blank for the pane that didn't have one
+        assert_eq!(buffers.get().len(), 3);
+        let (layout, size) = &windows[0];
+        assert_eq!(*size, Some(PhysicalSize::new(800, 600)));
+        let restored = layout.panes();
+        assert_eq!(restored.len(), 2);
+        assert_eq!(layout.focused, restored[1]);
+        assert!(matches!(&views[0], (BufferOp::RestoreView { main_cursor_start: 3, top_line: 2, .. }, id) if *id == restored[0]));
+    }
+}
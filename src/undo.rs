@@ -0,0 +1,299 @@
+// Undo and redo, as groups of edits that one undo takes back. The history is
+// kept on disk across reopening the file, as long as the file hasn't changed.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use crop::Rope;
+use im::Vector;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+use crate::file::{self, atomic_write};
+use crate::observer::Edit;
+use crate::pane::{Pane, Selection};
+
+// older groups than this are forgotten
+pub const MAX_GROUPS: usize = 1000;
+
+// `old` at `start` was replaced with `new`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Step {
+    start: usize,
+    old: String,
+    new: String,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Cursors {
+    // (start, offset) of each `Selection`
+    pub selections: Vec<(usize, i64)>,
+    pub main: usize,
+}
+
+impl Cursors {
+    pub fn of(pane: &Pane) -> Self {
+        Cursors {
+            selections: pane.cursors_iter().map(|s| (s.start, s.offset)).collect(),
+            main: pane.main_cursor_start,
+        }
+    }
+
+    pub fn selections(&self) -> Vec<Selection> {
+        self.selections.iter().map(|(start, offset)| Selection {start: *start, offset: *offset}).collect()
+    }
+}
+
+// a run of typing (or backspacing) in a pane without moving the cursors in
+// between, or anything else that changes the buffer in one go
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+struct Group {
+    steps: Vec<Step>,
+    // before and after, in the pane it was done in (if it was)
+    cursors: Option<(Cursors, Cursors)>,
+    // more typing can be added to it (not after it's been read back from disk)
+    #[serde(skip)]
+    typing: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct History {
+    done: Vector<Group>,
+    undone: Vector<Group>,
+    // how many groups were done when the buffer matched its file, None if
+    // there's no getting back there (it was undone past, then edited)
+    saved: Option<usize>,
+}
+
+// a buffer starts out the same as its file
+impl Default for History {
+    fn default() -> Self {
+        History {done: Vector::new(), undone: Vector::new(), saved: Some(0)}
+    }
+}
+
+impl History {
+    // `edits` were just made to `contents`. `typing` ones follow on from the last
+    // group if it was typed and the cursors are where it left them
+    pub fn record(&self, contents: &Rope, edits: &[Edit], cursors: Option<(Cursors, Cursors)>, typing: bool) -> Self {
+        if edits.is_empty() {
+            return self.clone();
+        }
+        let mut contents = contents.clone();
+        let steps: Vec<Step> = edits.iter().map(|e| {
+            let old = contents.byte_slice(e.start..e.old_end).to_string();
+            e.apply(&mut contents);
+            Step {start: e.start, old, new: e.text.clone()}
+        }).collect();
+
+        let mut done = self.done.clone();
+        // whatever was undone is gone now, and the saved state with it if it was in there
+        let saved = self.saved.filter(|saved| *saved <= done.len());
+        // (adding to the last group would hide the saved state inside it)
+        let follows_on = typing && saved != Some(done.len()) && done.last().is_some_and(|last| {
+            last.typing && last.cursors.as_ref().map(|(_, after)| after) == cursors.as_ref().map(|(before, _)| before)
+        });
+        if follows_on {
+            let mut last = done.pop_back().unwrap();
+            last.steps.extend(steps);
+            last.cursors = last.cursors.zip(cursors).map(|((before, _), (_, after))| (before, after));
+            done.push_back(last);
+        } else {
+            done.push_back(Group {steps, cursors, typing});
+        }
+        let mut saved = saved;
+        if done.len() > MAX_GROUPS {
+            done.pop_front();
+            // undoing can't get back to it now if it was before the one that's gone
+            saved = saved.and_then(|saved| saved.checked_sub(1));
+        }
+        History {done, undone: Vector::new(), saved}
+    }
+
+    // the edits that take back the last group, and where the cursors were before it
+    pub fn undo(&self, contents: &Rope) -> Option<(Self, Vec<Edit>, Option<Cursors>)> {
+        let mut done = self.done.clone();
+        let group = done.pop_back()?;
+        let mut contents = contents.clone();
+        let edits = group.steps.iter().rev().map(|s| {
+            let edit = Edit::replace(&contents, s.start..s.start + s.new.len(), &s.old);
+            edit.apply(&mut contents);
+            edit
+        }).collect();
+        let cursors = group.cursors.clone().map(|(before, _)| before);
+        let mut undone = self.undone.clone();
+        undone.push_back(Group {typing: false, ..group});
+        Some((History {done, undone, ..self.clone()}, edits, cursors))
+    }
+
+    // the edits that make the last undone group again, and where the cursors were after it
+    pub fn redo(&self, contents: &Rope) -> Option<(Self, Vec<Edit>, Option<Cursors>)> {
+        let mut undone = self.undone.clone();
+        let group = undone.pop_back()?;
+        let mut contents = contents.clone();
+        let edits = group.steps.iter().map(|s| {
+            let edit = Edit::replace(&contents, s.start..s.start + s.old.len(), &s.new);
+            edit.apply(&mut contents);
+            edit
+        }).collect();
+        let cursors = group.cursors.clone().map(|(_, after)| after);
+        let mut done = self.done.clone();
+        done.push_back(group);
+        Some((History {done, undone, ..self.clone()}, edits, cursors))
+    }
+
+    // whether undoing (or redoing) got the buffer back to what's in its file
+    pub fn is_saved(&self) -> bool {
+        self.saved == Some(self.done.len())
+    }
+
+    pub fn mark_saved(&self) -> Self {
+        History {saved: Some(self.done.len()), ..self.clone()}
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.done.is_empty() && self.undone.is_empty()
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+struct Saved {
+    // of the text it was saved with, which is where the history is up to
+    hash: String,
+    done: Vec<Group>,
+    undone: Vec<Group>,
+    saved: Option<usize>,
+}
+
+// where the histories go, None if there's no home to put them in
+pub fn dir() -> Option<PathBuf> {
+    file::state_dir("undo")
+}
+
+fn file(dir: &Path, path: &Path) -> PathBuf {
+    dir.join(format!("{}.json", file::path_key(path)))
+}
+
+fn hash(contents: &Rope) -> String {
+    let mut hasher = Sha256::new();
+    for chunk in contents.chunks() {
+        hasher.update(chunk.as_bytes());
+    }
+    format!("{:x}", hasher.finalize())
+}
+
+// `contents` is what was just saved to `path`
+pub fn write(dir: &Path, path: &Path, history: &History, contents: &Rope) -> io::Result<()> {
+    let saved = Saved {
+        hash: hash(contents),
+        done: history.done.iter().cloned().collect(),
+        undone: history.undone.iter().cloned().collect(),
+        saved: history.saved,
+    };
+    fs::create_dir_all(dir)?;
+    atomic_write(&file(dir, path), std::iter::once(serde_json::to_vec(&saved)?))
+}
+
+// the history of `path`, if it was saved with what's in `contents` (which was just read from it)
+pub fn read(dir: &Path, path: &Path, contents: &Rope) -> Option<History> {
+    let file = file(dir, path);
+    let json = match fs::read(&file) {
+        Ok(json) => json,
+        Err(e) if e.kind() == io::ErrorKind::NotFound => return None,
+        Err(e) => {
+            log::error!("couldn't read the undo history {}: {e}", file.display());
+            return None;
+        },
+    };
+    let saved: Saved = serde_json::from_slice(&json).inspect_err(|e| log::error!("couldn't parse the undo history {}: {e}", file.display())).ok()?;
+    if saved.hash != hash(contents) {
+        // changed by something else, it's no use now
+        let _ = fs::remove_file(&file);
+        return None;
+    }
+    Some(History {done: saved.done.into(), undone: saved.undone.into(), saved: saved.saved})
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::TestDir;
+
+    fn apply(contents: &Rope, edits: &[Edit]) -> Rope {
+        let mut contents = contents.clone();
+        for e in edits {
+            e.apply(&mut contents);
+        }
+        contents
+    }
+
+    #[test]
+    fn test_undo_and_redo() {
+        let at = |offset| Cursors {selections: vec![(offset, 0)], main: offset};
+        let start = Rope::from("hello");
+        let mut history = History::default();
+        let mut contents = start.clone();
+        // typed one char at a time, that's one group
+        for (i, c) in [" ", "y", "o"].into_iter().enumerate() {
+            let edit = Edit::insert(&contents, 5 + i, c);
+            history = history.record(&contents, std::slice::from_ref(&edit), Some((at(5 + i), at(6 + i))), true);
+            contents = apply(&contents, &[edit]);
+        }
+        let edit = Edit::replace(&contents, 0..1, "J");
+        history = history.record(&contents, std::slice::from_ref(&edit), None, false);
+        contents = apply(&contents, &[edit]);
+        assert_eq!(contents.to_string(), "Jello yo");
+        assert!(!history.is_saved());
+
+        let (history, edits, cursors) = history.undo(&contents).unwrap();
+        let contents = apply(&contents, &edits);
+        assert_eq!((contents.to_string(), cursors), ("hello yo".to_string(), None));
+        let (history, edits, cursors) = history.undo(&contents).unwrap();
+        let contents = apply(&contents, &edits);
+        assert_eq!((contents.to_string(), cursors), ("hello".to_string(), Some(at(5))));
+        assert!(history.is_saved());
+        assert!(history.undo(&contents).is_none());
+
+        let (history, edits, cursors) = history.redo(&contents).unwrap();
+        let contents = apply(&contents, &edits);
+        assert_eq!((contents.to_string(), cursors), ("hello yo".to_string(), Some(at(8))));
+        // a new edit forgets what's left to redo
+        let edit = Edit::delete(&contents, 7..8);
+        let history = history.record(&contents, &[edit], None, false);
+        assert!(history.redo(&contents).is_none());
+    }
+
+    #[test]
+    fn test_max_groups() {
+        let mut history = History::default();
+        let mut contents = Rope::new();
+        for _ in 0..=MAX_GROUPS {
+            let edit = Edit::insert(&contents, 0, "x");
+            history = history.record(&contents, std::slice::from_ref(&edit), None, false);
+            contents = apply(&contents, &[edit]);
+        }
+        assert_eq!(history.done.len(), MAX_GROUPS);
+        // the first edit can't be undone anymore, so neither can the empty file it was saved as
+        assert_eq!(history.saved, None);
+    }
+
+    #[test]
+    fn test_write_and_read() {
+        let dir = TestDir::new("undo");
+        let path = Path::new("/tmp/some/file.txt");
+        let before = Rope::from("hello");
+        let edit = Edit::insert(&before, 5, "!");
+        let history = History::default().record(&before, std::slice::from_ref(&edit), None, false).mark_saved();
+        let saved = apply(&before, &[edit]);
+        write(&dir, path, &history, &saved).unwrap();
+
+        let read_back = read(&dir, path, &saved).unwrap();
+        assert_eq!(read_back, history);
+        let (_, edits, _) = read_back.undo(&saved).unwrap();
+        assert_eq!(apply(&saved, &edits).to_string(), "hello");
+        // the file was changed by something else since
+        assert_eq!(read(&dir, path, &Rope::from("hello?")), None);
+        assert_eq!(read(&dir, path, &saved), None);
+
+    }
+}
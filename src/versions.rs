@@ -0,0 +1,108 @@
+// Local history: a copy of each file every time it's saved (not autosaved), so
+// there's something to go back to for files that aren't in git.
+
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::file;
+
+// per file, older ones are deleted
+pub const KEEP: usize = 50;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Version {
+    pub path: PathBuf,
+    pub time: SystemTime,
+}
+
+// where the versions go, None if there's no home to put them in
+pub fn dir() -> Option<PathBuf> {
+    file::state_dir("versions")
+}
+
+// each version is `<dir>/<the file's path_key>/<unix time in ms>`
+fn of(dir: &Path, path: &Path) -> PathBuf {
+    dir.join(file::path_key(path))
+}
+
+// newest first
+pub fn list(dir: &Path, path: &Path) -> Vec<Version> {
+    let mut versions: Vec<Version> = fs::read_dir(of(dir, path)).into_iter().flatten().flatten()
+        .filter_map(|entry| {
+            let ms: u64 = entry.file_name().to_str()?.parse().ok()?;
+            Some(Version {path: entry.path(), time: UNIX_EPOCH + Duration::from_millis(ms)})
+        })
+        .collect();
+    versions.sort_by_key(|v| std::cmp::Reverse(v.time));
+    versions
+}
+
+// `bytes` were just saved to `path` (before any compression, so they can be
+// diffed). Nothing's kept if it's what was saved last time
+pub fn snapshot(dir: &Path, path: &Path, bytes: &[u8], keep: usize) -> io::Result<()> {
+    let versions = list(dir, path);
+    if let Some(newest) = versions.first() {
+        if fs::read(&newest.path).is_ok_and(|newest| newest == bytes) {
+            return Ok(());
+        }
+    }
+    let dir = of(dir, path);
+    fs::create_dir_all(&dir)?;
+    let mut ms = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().as_millis();
+    // two saves in the same ms
+    while versions.iter().any(|v| v.path.ends_with(ms.to_string())) {
+        ms += 1;
+    }
+    fs::write(dir.join(ms.to_string()), bytes)?;
+    // that's one more than there was
+    for old in versions.iter().skip(keep.saturating_sub(1)) {
+        fs::remove_file(&old.path)?;
+    }
+    Ok(())
+}
+
+// `n` counts from the newest, which is 1
+pub fn read(dir: &Path, path: &Path, n: usize) -> Result<(Version, Vec<u8>), String> {
+    let versions = list(dir, path);
+    let Some(version) = n.checked_sub(1).and_then(|i| versions.get(i)).cloned() else {
+        return Err(format!("there are {} saved versions of {}", versions.len(), path.display()));
+    };
+    let bytes = fs::read(&version.path).map_err(|e| format!("couldn't read {}: {e}", version.path.display()))?;
+    Ok((version, bytes))
+}
+
+// how a version's shown: just the time if it's from today
+pub fn describe(time: SystemTime) -> String {
+    let time = chrono::DateTime::<chrono::Local>::from(time);
+    if time.date_naive() == chrono::Local::now().date_naive() {
+        time.format("%H:%M:%S").to_string()
+    } else {
+        time.format("%Y-%m-%d %H:%M").to_string()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::file::TestDir;
+
+    #[test]
+    fn test_snapshot() {
+        let dir = TestDir::new("versions");
+        let path = Path::new("/tmp/some/file.txt");
+        for bytes in [&b"one"[..], b"two", b"two", b"thr\xe9e", b"four\r\n"] {
+            snapshot(&dir, path, bytes, 3).unwrap();
+        }
+        // the same twice is only kept once, and the oldest goes
+        let versions = list(&dir, path);
+        assert_eq!(versions.len(), 3);
+        // exactly what was written
+        assert_eq!(read(&dir, path, 1).unwrap().1, b"four\r\n");
+        assert_eq!(read(&dir, path, 2).unwrap().1, b"thr\xe9e");
+        assert_eq!(read(&dir, path, 3).unwrap().1, b"two");
+        assert!(read(&dir, path, 4).is_err());
+        assert!(read(&dir, path, 0).is_err());
+    }
+}
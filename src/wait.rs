@@ -0,0 +1,112 @@
+// `--wait`, for when chop is someone's $EDITOR (`git commit`, `git rebase -i`)
+// and they're waiting on it to be done with the files it was given. It is once
+// they've all been closed, and it exits then, unless that would lose changes
+// to something else that's open (or it's exited some other way, like closing
+// the window). It's an abort, and exits with 1, if one was thrown away with
+// changes that weren't saved, or it's left with `:cq`.
+//
+// The files can also be from another chop that handed them to this one (see
+// `server`), then it's that one that's waiting, and exits instead.
+
+use std::collections::HashSet;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{mpsc, Arc};
+use im::OrdMap;
+
+use crate::buffer::{BufferId, TextBuffer};
+use crate::observer::BufferEvent;
+use crate::registry::Registry;
+
+// what's being waited on. `aborted` can be set from anywhere
+#[derive(Debug, Clone, Default)]
+pub struct Wait {
+    pub buffers: Vec<BufferId>,
+    pub aborted: Arc<AtomicBool>,
+}
+
+impl Wait {
+    pub fn is_waiting(&self) -> bool {
+        !self.buffers.is_empty()
+    }
+
+    pub fn abort(&self) {
+        self.aborted.store(true, Ordering::Relaxed);
+    }
+
+    // chop's exiting with `buffers` as they are now
+    pub fn exit_code(&self, buffers: &Registry<TextBuffer>) -> i32 {
+        let all = buffers.get();
+        if self.buffers.iter().filter_map(|id| all.get(id)).any(|b| b.has_unsaved_changes()) {
+            self.abort();
+        }
+        self.code()
+    }
+
+    pub fn code(&self) -> i32 {
+        if self.aborted.load(Ordering::Relaxed) { 1 } else { 0 }
+    }
+
+    // `done` once the buffers are closed, with the buffers that are left
+    pub fn spawn(&self, events: mpsc::Receiver<Arc<BufferEvent>>, buffers: Arc<Registry<TextBuffer>>, done: impl FnOnce(&Wait, &OrdMap<BufferId, TextBuffer>) + Send + 'static) {
+        let mut waiter = Waiter::new(self.clone());
+        std::thread::spawn(move || {
+            for event in events {
+                if waiter.handle(&event) {
+                    done(&waiter.wait, &buffers.get());
+                    return;
+                }
+            }
+        });
+    }
+}
+
+struct Waiter {
+    wait: Wait,
+    // the ones that are still open
+    open: HashSet<BufferId>,
+}
+
+impl Waiter {
+    fn new(wait: Wait) -> Self {
+        let open = wait.buffers.iter().copied().collect();
+        Self {wait, open}
+    }
+
+    // whether they've all been closed
+    fn handle(&mut self, event: &BufferEvent) -> bool {
+        let BufferEvent::Closed {buffer_id, discarded} = event else {
+            return false;
+        };
+        if !self.open.remove(buffer_id) {
+            return false;
+        }
+        if *discarded {
+            self.wait.abort();
+        }
+        self.open.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::observer::BufferChange;
+
+    #[test]
+    fn test_wait() {
+        let buffers = Registry::new();
+        let waited = buffers.insert_with(|_| TextBuffer::new_file("/tmp/COMMIT_EDITMSG"));
+        let other = buffers.insert_with(|_| TextBuffer::new_file("/tmp/other"));
+        let wait = Wait {buffers: vec![waited], ..Wait::default()};
+        let mut waiter = Waiter::new(wait.clone());
+
+        // edited, then thrown away
+        assert!(!waiter.handle(&BufferEvent::Changed(BufferChange {buffer_id: waited, version: 1, edits: vec![], lines: 0..1})));
+        assert!(!waiter.handle(&BufferEvent::Closed {buffer_id: other, discarded: true}));
+        assert_eq!(wait.code(), 0);
+        buffers.remove(waited);
+        assert!(waiter.handle(&BufferEvent::Closed {buffer_id: waited, discarded: true}));
+        assert_eq!(wait.exit_code(&buffers), 1);
+        assert_eq!(Wait::default().exit_code(&buffers), 0);
+    }
+}
@@ -0,0 +1,87 @@
+// Watches the files of open buffers so we find out when something else
+// changes them. The directories are watched rather than the files, because
+// most programs save by writing a new file and renaming it over the old one,
+// which a watch on the old file never hears about.
+//
+// All the watcher does is tell the buffer thread which file changed, that's
+// where it's decided whether it really did (by the mtime) and what to do.
+
+use std::collections::{HashMap, HashSet};
+use std::path::{Path, PathBuf};
+use std::sync::{mpsc, Arc, Mutex};
+use im::OrdMap;
+use notify::{EventKind, RecommendedWatcher, RecursiveMode, Watcher};
+
+use crate::buffer::{BufferId, BufferMsg, TextBuffer};
+
+pub struct FileWatcher {
+    // None if the platform wouldn't give us one, then nothing is watched
+    watcher: Option<RecommendedWatcher>,
+    // absolute path -> the name the buffer has for it
+    files: Arc<Mutex<HashMap<PathBuf, Arc<Path>>>>,
+    dirs: HashSet<PathBuf>,
+    // the buffers' files as of the last sync
+    names: HashSet<Arc<Path>>,
+}
+
+impl FileWatcher {
+    pub fn new(buffer_tx: mpsc::Sender<BufferMsg>) -> Self {
+        let files: Arc<Mutex<HashMap<PathBuf, Arc<Path>>>> = Arc::default();
+        let watched = files.clone();
+        let watcher = notify::recommended_watcher(move |res: notify::Result<notify::Event>| {
+            let event = match res {
+                Ok(event) => event,
+                Err(e) => return log::warn!("file watcher: {}", e),
+            };
+            if matches!(event.kind, EventKind::Access(_)) {
+                return;
+            }
+            let files = watched.lock().unwrap();
+            for path in event.paths {
+                if let Some(filename) = files.get(&path) {
+                    let _ = buffer_tx.send(BufferMsg::FileChanged(filename.to_path_buf()));
+                }
+            }
+        });
+        let watcher = watcher.map_err(|e| log::error!("can't watch files for changes: {}", e)).ok();
+        Self {watcher, files, dirs: HashSet::new(), names: HashSet::new()}
+    }
+
+    // watch exactly the files the buffers have, called whenever they might have changed
+    pub fn sync(&mut self, buffers: &OrdMap<BufferId, TextBuffer>) {
+        let names: HashSet<Arc<Path>> = buffers.values().filter_map(|b| Some(b.file.as_ref()?.filename.clone())).collect();
+        // nothing to do most of the time, so don't touch the disk
+        if names == self.names {
+            return;
+        }
+        let Some(watcher) = &mut self.watcher else {
+            return;
+        };
+        let files: HashMap<PathBuf, Arc<Path>> = names.iter().filter_map(|name| Some((absolute(name)?, name.clone()))).collect();
+        let dirs: HashSet<PathBuf> = files.keys().filter_map(|p| p.parent().map(Path::to_path_buf)).collect();
+        let wanted = dirs.len();
+        for dir in self.dirs.difference(&dirs) {
+            let _ = watcher.unwatch(dir);
+        }
+        self.dirs = dirs.into_iter().filter(|dir| {
+            self.dirs.contains(dir) || watcher.watch(dir, RecursiveMode::NonRecursive)
+                .map_err(|e| log::debug!("can't watch {}: {}", dir.display(), e)).is_ok()
+        }).collect();
+        // a new file's directory might not exist yet, this makes the next sync try again
+        self.names = if files.len() < names.len() || self.dirs.len() < wanted {
+            HashSet::new()
+        } else {
+            names
+        };
+        *self.files.lock().unwrap() = files;
+    }
+}
+
+// the path the watcher will report for the file, which doesn't have to exist
+fn absolute(path: &Path) -> Option<PathBuf> {
+    let parent = match path.parent() {
+        Some(p) if p != Path::new("") => p,
+        _ => Path::new("."),
+    };
+    Some(parent.canonicalize().ok()?.join(path.file_name()?))
+}